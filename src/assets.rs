@@ -1,14 +1,19 @@
 use std::path::{PathBuf, Path};
-use std::fs;
+use std::{fs, io};
 use std::lazy::SyncLazy;
+#[cfg(feature = "client")]
 use rustc_hash::FxHashMap;
+#[cfg(feature = "client")]
 use parking_lot::RwLock;
+#[cfg(feature = "client")]
 use std::sync::Arc;
 
 use anyhow::Result;
 use image::DynamicImage;
 
+#[cfg(feature = "client")]
 use crate::render::RenderContext;
+#[cfg(feature = "client")]
 use crate::render::model::Model;
 
 static LOADER: SyncLazy<AssetLoader> = SyncLazy::new(|| AssetLoader::from_path(PathBuf::from("res/")));
@@ -20,6 +25,7 @@ pub fn loader() -> &'static AssetLoader {
 // this is realy basic for now, may be improved in future
 pub struct AssetLoader {
 	resource_folder: PathBuf,
+	#[cfg(feature = "client")]
 	cached_models: RwLock<FxHashMap<PathBuf, Arc<Model>>>,
 }
 
@@ -27,6 +33,7 @@ impl AssetLoader {
 	fn from_path(resource_folder: PathBuf) -> Self {
 		Self {
 			resource_folder,
+			#[cfg(feature = "client")]
 			cached_models: RwLock::new(FxHashMap::default()),
 		}
 	}
@@ -38,19 +45,46 @@ impl AssetLoader {
 	}
 
 	pub fn load_bytes<T: AsRef<Path>>(&self, file: T) -> Result<Vec<u8>> {
-		Ok(fs::read(&self.path_of(file))?)
+		Ok(fs::read(self.path_of(file))?)
+	}
+
+	pub fn load_string<T: AsRef<Path>>(&self, file: T) -> Result<String> {
+		Ok(fs::read_to_string(self.path_of(file))?)
+	}
+
+	// returns the paths of the files in the folder, relative to the resource folder and sorted by name
+	// returns an empty list if the folder doesn't exist
+	pub fn list_folder<T: AsRef<Path>>(&self, folder: T) -> Result<Vec<PathBuf>> {
+		let folder = folder.as_ref();
+		let entries = match fs::read_dir(self.path_of(folder)) {
+			Ok(entries) => entries,
+			Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(error) => return Err(error.into()),
+		};
+
+		let mut files = Vec::new();
+		for entry in entries {
+			let entry = entry?;
+			if entry.file_type()?.is_file() {
+				files.push(folder.join(entry.file_name()));
+			}
+		}
+		files.sort();
+
+		Ok(files)
 	}
 
 	pub fn load_image<T: AsRef<Path>>(&self, file: T) -> Result<DynamicImage> {
-		Ok(image::open(&self.path_of(file))?)
+		Ok(image::open(self.path_of(file))?)
 	}
 
 	pub fn load_obj<T: AsRef<Path>>(&self, file: T) -> Result<(Vec<tobj::Model>, Vec<tobj::Material>)> {
-		let (obj_meshes, obj_materials) = tobj::load_obj(&self.path_of(file), &tobj::GPU_LOAD_OPTIONS)?;
+		let (obj_meshes, obj_materials) = tobj::load_obj(self.path_of(file), &tobj::GPU_LOAD_OPTIONS)?;
 		let obj_materials = obj_materials?;
 		Ok((obj_meshes, obj_materials))
 	}
 
+	#[cfg(feature = "client")]
 	pub fn load_model_cached<T: AsRef<Path>>(&self, file: T, context: RenderContext) -> Result<Arc<Model>> {
 		let file = file.as_ref();
 
@@ -0,0 +1,117 @@
+// headless server which runs the world without opening a window or using the gpu
+// to build it without any of the rendering dependencies, use:
+// cargo build --no-default-features --bin mineclone-server
+//
+// usage: mineclone-server <world folder> [--seed <seed>] [--ticks <number of ticks to run for>] [--address <address to listen on>]
+
+#[macro_use]
+extern crate log;
+
+use std::env;
+use std::thread;
+use std::time::Instant;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Result, Context, bail};
+
+use mineclone::game::{parallel, world::World, network::{Server, DEFAULT_PORT}, clock::{TICKS_PER_SECOND, TickAccumulator}};
+
+// save the world every 5 minutes
+const AUTOSAVE_INTERVAL: u64 = 5 * 60 * TICKS_PER_SECOND;
+
+struct Args {
+	world_folder: String,
+	// only used when creating a new world
+	seed: u32,
+	// if this is None the server runs forever
+	ticks: Option<u64>,
+	// address clients connect to
+	address: String,
+}
+
+fn parse_args() -> Result<Args> {
+	let mut args = env::args().skip(1);
+
+	let mut world_folder = None;
+	let mut seed = 0;
+	let mut ticks = None;
+	let mut address = format!("0.0.0.0:{}", DEFAULT_PORT);
+
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--seed" => {
+				let value = args.next().context("--seed requires a value")?;
+				seed = value.parse().with_context(|| format!("invalid seed: {}", value))?;
+			},
+			"--ticks" => {
+				let value = args.next().context("--ticks requires a value")?;
+				ticks = Some(value.parse().with_context(|| format!("invalid tick count: {}", value))?);
+			},
+			"--address" => address = args.next().context("--address requires a value")?,
+			_ if world_folder.is_none() => world_folder = Some(arg),
+			_ => bail!("unexpected argument: {}", arg),
+		}
+	}
+
+	Ok(Args {
+		world_folder: world_folder.context("usage: mineclone-server <world folder> [--seed <seed>] [--ticks <ticks>] [--address <address>]")?,
+		seed,
+		ticks,
+		address,
+	})
+}
+
+fn main() -> Result<()> {
+	pretty_env_logger::init();
+
+	let args = parse_args()?;
+
+	let world = if World::exists(&args.world_folder) {
+		World::open(&args.world_folder)?
+	} else {
+		info!("creating new world in {} with seed {}", args.world_folder, args.seed);
+		World::create(&args.world_folder, args.seed)?
+	};
+
+	// leave 1 for the tick thread
+	parallel::init(world.clone(), num_cpus::get().saturating_sub(1).max(1));
+
+	// keep the area around spawn loaded even when nobody is connected
+	world.keep_spawn_loaded();
+
+	let mut server = Server::bind(world.clone(), &args.address)?;
+
+	// stopping the server with ctrl-c or SIGTERM finishes the current tick and saves the world before exiting
+	let running = Arc::new(AtomicBool::new(true));
+	let handler_running = running.clone();
+	ctrlc::set_handler(move || handler_running.store(false, Ordering::Release))
+		.context("could not set the handler for stopping the server")?;
+
+	let mut accumulator = TickAccumulator::new();
+	let mut last_time = Instant::now();
+	let mut tick = 0;
+
+	while running.load(Ordering::Acquire) && args.ticks.map_or(true, |ticks| tick < ticks) {
+		// after a stall the missed ticks are run without sleeping, up to the catch up limit of the accumulator
+		while !accumulator.take_tick() {
+			thread::sleep(accumulator.time_until_tick());
+			let current_time = Instant::now();
+			accumulator.add_time(current_time - last_time);
+			last_time = current_time;
+		}
+
+		world.tick();
+		server.tick();
+		tick += 1;
+
+		if tick % AUTOSAVE_INTERVAL == 0 {
+			if let Err(error) = world.save() {
+				error!("could not save the world: {}", error);
+			}
+		}
+	}
+
+	info!("stopping the server and saving the world");
+	world.save()
+}
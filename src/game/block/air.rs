@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Air {}
 
 impl Air {
@@ -17,4 +17,8 @@ impl BlockTrait for Air {
 	fn is_translucent(&self) -> bool {
 		true
 	}
+
+	fn drops(&self) -> Vec<&str> {
+		Vec::new()
+	}
 }
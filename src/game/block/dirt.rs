@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Dirt {}
 
 impl Dirt {
@@ -8,8 +8,8 @@ impl Dirt {
 		Dirt {}
 	}
 
-	pub fn get_texture() -> Result<DynamicImage> {
-		Ok(texmanip::tile_from_side(&loader().load_image("textures/dirt.png")?))
+	pub fn get_texture() -> Result<texmanip::BlockTextures> {
+		Ok(texmanip::BlockTextures::from_side(&loader().load_image("textures/dirt.png")?))
 	}
 }
 
@@ -1,7 +1,7 @@
 use super::*;
 use texmanip::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Grass {}
 
 impl Grass {
@@ -9,23 +9,20 @@ impl Grass {
 		Grass {}
 	}
 
-	pub fn get_texture() -> Result<DynamicImage> {
+	pub fn get_texture() -> Result<BlockTextures> {
 		let dirt = loader().load_image("textures/dirt.png")?;
 		let grass_top = loader().load_image("textures/grass-top.png")?;
 		let grass_side = loader().load_image("textures/grass-side.png")?;
 
-		let mut tiled_dirt = tile_from_side(&dirt);
-		let tiled_grass = stitch_texture(TextureStitchFaces {
-			front: TextureFace::BlockAligned(&grass_side),
-			back: TextureFace::BlockAligned(&grass_side),
-			left: TextureFace::BlockAligned(&grass_side),
-			right: TextureFace::BlockAligned(&grass_side),
-			top: TextureFace::BlockAligned(&grass_top),
-			bottom: TextureFace::Empty,
-		})?;
-		
-		overlay(&mut tiled_dirt, &tiled_grass, 0, 0);
-		Ok(tiled_dirt)
+		let side = overlayed(&dirt, &grass_side);
+		Ok(BlockTextures::from_faces(BlockTextureFaces {
+			front: &side,
+			back: &side,
+			left: &side,
+			right: &side,
+			top: &overlayed(&dirt, &grass_top),
+			bottom: &dirt,
+		}))
 	}
 }
 
@@ -37,4 +34,8 @@ impl BlockTrait for Grass {
 	fn is_translucent(&self) -> bool {
 		false
 	}
+
+	fn drops(&self) -> Vec<&str> {
+		vec!["dirt"]
+	}
 }
@@ -1,16 +1,29 @@
-use std::{iter::FusedIterator, mem};
+use std::iter::FusedIterator;
+#[cfg(feature = "client")]
+use std::mem;
 
+#[cfg(feature = "client")]
 use image::DynamicImage;
 use nalgebra::Vector3;
-use enum_dispatch::enum_dispatch;
 use anyhow::Result;
 
-pub use crate::render::model::{Vertex, Model};
+#[cfg(feature = "client")]
+pub use crate::render::model::Vertex;
 use crate::util::{vec3_getx, vec3_gety, vec3_getz};
 use crate::prelude::*;
 use crate::assets::loader;
+use super::CHUNK_SIZE;
+use super::light::LightLevel;
 
 mod texmanip;
+mod registry;
+mod state;
+pub use state::{BlockProperty, BlockState, Axis, Half};
+use state::{facing_from_value, facing_value};
+mod shape;
+pub use shape::{BlockShape, BlockGeometry, ShapeBox, SHAPE_UNITS};
+pub use registry::{registry, BlockDefinition, BlockId, BlockIdMap, MAX_LIGHT_EMISSION};
+use registry::TextureSource;
 mod air;
 pub use air::*;
 mod dirt;
@@ -55,6 +68,18 @@ impl BlockFace {
 		}
 	}
 
+	// the face on the other side of the block
+	pub fn opposite(&self) -> Self {
+		match self {
+			Self::XPos => Self::XNeg,
+			Self::XNeg => Self::XPos,
+			Self::YPos => Self::YNeg,
+			Self::YNeg => Self::YPos,
+			Self::ZPos => Self::ZNeg,
+			Self::ZNeg => Self::ZPos,
+		}
+	}
+
 	pub fn is_positive_face(&self) -> bool {
 		matches!(self, Self::XPos | Self::YPos | Self::ZPos)
 	}
@@ -107,9 +132,45 @@ impl Iterator for BlockFaceIter {
 
 impl FusedIterator for BlockFaceIter {}
 
+// the direction a quad of a block mesh faces, which the shader uses to pick its normal and texture coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuadFacing {
+	Face(BlockFace),
+	// the planes going through opposite vertical edges of a block, which cross shaped blocks are made of
+	// this one goes from the x negative z negative edge to the x positive z positive edge
+	DiagonalPos,
+	// this one goes from the x positive z negative edge to the x negative z positive edge
+	DiagonalNeg,
+}
+
+impl From<QuadFacing> for u32 {
+	// the diagonals come after the faces
+	fn from(facing: QuadFacing) -> u32 {
+		match facing {
+			QuadFacing::Face(face) => face as u32,
+			QuadFacing::DiagonalPos => 6,
+			QuadFacing::DiagonalNeg => 7,
+		}
+	}
+}
+
 
 pub type TextureIndex = i32;
 
+// each textured block has a layer in the block texture array for each of its faces
+pub fn texture_layer(texture_index: TextureIndex, face: BlockFace) -> u32 {
+	u32::try_from(texture_index).unwrap() * 6 + face as u32
+}
+
+// the texture drawn on a face of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaceTexture {
+	// the layer of the block texture array
+	pub layer: u32,
+	// number of quarter turns the texture is rotated by, from 0 to 3
+	pub rotation: u8,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct OcclusionCorners {
 	pub tl: u8,
@@ -136,38 +197,53 @@ impl OcclusionCorners {
 	}
 }
 
+// vertexes are packed into 8 bytes, and are unpacked in the shader
+// the position is relative to the chunk, which is given to the shader seperately for each chunk
 #[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct BlockVertex {
-	position: [f32; 3],
-	normal: [f32; 3],
-	// texture color will be mutiplied by this color
-	color: [f32; 3],
-	// the wgpu sample function takes in a signed integer so we use it here
-	texture_index: TextureIndex,
+	// bits 0-29 are the x, y, and z position with 10 bits each, in 16ths of a block so block shapes can be drawn
+	position: u32,
+	// bits 0-15 are the layer of the block texture array, see texture_layer
+	// bits 16-18 are the quad facing, bits 19-20 are the occlusion level, and bits 21-22 are the texture rotation
+	// bits 23-26 are the sky light and bits 27-30 are the block light
+	data: u32,
 }
 
+// the position has to fit in the bits for each coordinate
+const _: () = assert!(CHUNK_SIZE * (SHAPE_UNITS as usize) < 1 << 10);
+
 impl BlockVertex {
-	// panics on invalid occlusion level
-	pub fn new(position: Position, normal: Vector3<f32>, texture_index: TextureIndex, occlusion_level: u8) -> Self {
+	// panics on invalid occlusion level, texture rotation, or texture layer, or if the position is outside of the chunk
+	pub fn new(position: Position, facing: QuadFacing, texture: FaceTexture, occlusion_level: u8, light: LightLevel) -> Self {
+		assert!(occlusion_level < 4, "invalid occlusion level passed to BlockVertex::new()");
+		assert!(texture.rotation < 4, "invalid texture rotation passed to BlockVertex::new()");
+		assert!(texture.layer <= u16::MAX as u32, "invalid texture layer passed to BlockVertex::new()");
+
+		let coordinate = |n: f64| {
+			assert!(n >= 0.0 && n <= CHUNK_SIZE as f64, "vertex position {:?} is outside of the chunk", position);
+			(n * SHAPE_UNITS as f64).round() as u32
+		};
+
 		Self {
-			position: [position.x as f32, position.y as f32, position.z as f32],
-			normal: [normal.x, normal.y, normal.z],
-			color: match occlusion_level {
-				0 => [1.0, 1.0, 1.0],
-				1 => [0.8, 0.8, 0.8],
-				2 => [0.6, 0.6, 0.6],
-				3 => [0.4, 0.4, 0.4],
-				_ => panic!("invalid occlusion level passed to BlockVertex::new()"),
-			},
-			texture_index,
+			position: coordinate(position.x)
+				| coordinate(position.y) << 10
+				| coordinate(position.z) << 20,
+			data: texture.layer
+				| u32::from(facing) << 16
+				| (occlusion_level as u32) << 19
+				| (texture.rotation as u32) << 21
+				| (light.sky() as u32) << 23
+				| (light.block() as u32) << 27,
 		}
 	}
 
-	const ATTRIBS: [wgpu::VertexAttribute; 4] =
-		wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x3, 3 => Sint32];
+	#[cfg(feature = "client")]
+	const ATTRIBS: [wgpu::VertexAttribute; 1] =
+		wgpu::vertex_attr_array![0 => Uint32x2];
 }
 
+#[cfg(feature = "client")]
 impl Vertex for BlockVertex {
 	fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
 		wgpu::VertexBufferLayout {
@@ -180,13 +256,14 @@ impl Vertex for BlockVertex {
 
 // the front of the face is the side from which the vertexes are going in a clockwise direction
 // all the BlockVertexes must also be coplanar
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct BlockFaceMesh(pub [BlockVertex; 4]);
 
 impl BlockFaceMesh {
 	// TODO: add small overlap on edges to stop rendering artifacts
 	// occlusion levels in the array are: [tl, bl, br, tr]
-	pub fn from_corners(face: BlockFace, texture_index: TextureIndex, tl_corner_block: BlockPos, br_corner_block: BlockPos, occlusion_data: OcclusionCorners) -> Self {
+	// the corner blocks are relative to the chunk the face is in
+	pub fn from_corners(face: BlockFace, texture: FaceTexture, tl_corner_block: BlockPos, br_corner_block: BlockPos, occlusion_data: OcclusionCorners, light: LightLevel) -> Self {
 		let tl_corner_pos = tl_corner_block.as_position();
 		let br_corner_pos = br_corner_block.as_position();
 
@@ -217,6 +294,19 @@ impl BlockFaceMesh {
 			),
 		};
 
+		let [tl_corner, bl_corner, br_corner, tr_corner] = Self::face_corners(face, tl_corner, br_corner);
+		let facing = QuadFacing::Face(face);
+
+		Self([
+			 BlockVertex::new(tl_corner, facing, texture, occlusion_data.tl, light),
+			 BlockVertex::new(bl_corner, facing, texture, occlusion_data.bl, light),
+			 BlockVertex::new(br_corner, facing, texture, occlusion_data.br, light),
+			 BlockVertex::new(tr_corner, facing, texture, occlusion_data.tr, light),
+		])
+	}
+
+	// returns the corners of a face in the order [tl, bl, br, tr], given its tl and br corners
+	fn face_corners(face: BlockFace, tl_corner: Position, br_corner: Position) -> [Position; 4] {
 		let (bl_corner, tr_corner) = match face {
 			BlockFace::XPos | BlockFace::XNeg => (
 				Position::new(vec3_getx(tl_corner), vec3_gety(br_corner), vec3_getz(tl_corner)),
@@ -232,25 +322,48 @@ impl BlockFaceMesh {
 			),
 		};
 
-		let normal = match face {
-			BlockFace::XPos => Vector3::new(1.0, 0.0, 0.0),
-			BlockFace::XNeg => Vector3::new(-1.0, 0.0, 0.0),
-			BlockFace::YPos => Vector3::new(0.0, 1.0, 0.0),
-			BlockFace::YNeg => Vector3::new(0.0, -1.0, 0.0),
-			BlockFace::ZPos => Vector3::new(0.0, 0.0, 1.0),
-			BlockFace::ZNeg => Vector3::new(0.0, 0.0, -1.0),
+		[tl_corner, bl_corner, br_corner, tr_corner]
+	}
+
+	// the face of a box which goes from min to max, relative to the chunk
+	// the occlusion function gives the occlusion level at each corner
+	pub fn from_box<F: Fn(Position) -> u8>(face: BlockFace, texture: FaceTexture, min: Position, max: Position, occlusion: F, light: LightLevel) -> Self {
+		let (tl_corner, br_corner) = match face {
+			BlockFace::XPos => (Position::new(max.x, max.y, min.z), Position::new(max.x, min.y, max.z)),
+			BlockFace::XNeg => (Position::new(min.x, max.y, max.z), Position::new(min.x, min.y, min.z)),
+			BlockFace::YPos => (Position::new(min.x, max.y, max.z), Position::new(max.x, max.y, min.z)),
+			BlockFace::YNeg => (Position::new(min.x, min.y, min.z), Position::new(max.x, min.y, max.z)),
+			BlockFace::ZPos => (Position::new(max.x, max.y, max.z), Position::new(min.x, min.y, max.z)),
+			BlockFace::ZNeg => (Position::new(min.x, max.y, min.z), Position::new(max.x, min.y, min.z)),
 		};
 
-		Self([
-			 BlockVertex::new(tl_corner, normal, texture_index, occlusion_data.tl),
-			 BlockVertex::new(bl_corner, normal, texture_index, occlusion_data.bl),
-			 BlockVertex::new(br_corner, normal, texture_index, occlusion_data.br),
-			 BlockVertex::new(tr_corner, normal, texture_index, occlusion_data.tr),
-		])
+		let facing = QuadFacing::Face(face);
+		Self(Self::face_corners(face, tl_corner, br_corner)
+			.map(|corner| BlockVertex::new(corner, facing, texture, occlusion(corner), light)))
+	}
+
+	// the faces of a cross shaped block, which are drawn from both sides
+	pub fn cross(texture: FaceTexture, block: BlockPos, light: LightLevel) -> [Self; 4] {
+		let corner = |x, y, z| block.as_position() + Vector3::new(x, y, z);
+		let quad = |facing, corners: [Position; 4]| Self(corners.map(|corner| BlockVertex::new(corner, facing, texture, 0, light)));
+
+		let diagonal_pos = [corner(0.0, 1.0, 0.0), corner(0.0, 0.0, 0.0), corner(1.0, 0.0, 1.0), corner(1.0, 1.0, 1.0)];
+		let diagonal_neg = [corner(1.0, 1.0, 0.0), corner(1.0, 0.0, 0.0), corner(0.0, 0.0, 1.0), corner(0.0, 1.0, 1.0)];
+		let reversed = |mut corners: [Position; 4]| {
+			corners.reverse();
+			corners
+		};
+
+		[
+			quad(QuadFacing::DiagonalPos, diagonal_pos),
+			quad(QuadFacing::DiagonalPos, reversed(diagonal_pos)),
+			quad(QuadFacing::DiagonalNeg, diagonal_neg),
+			quad(QuadFacing::DiagonalNeg, reversed(diagonal_neg)),
+		]
 	}
 
 	// TODO: this is probably more complicated than it needs to be
-	pub fn from_cube_corners(face: BlockFace, texture_index: TextureIndex, neg_corner_block: BlockPos, pos_corner_block: BlockPos, occlusion_data: OcclusionCorners) -> Self {
+	pub fn from_cube_corners(face: BlockFace, texture: FaceTexture, neg_corner_block: BlockPos, pos_corner_block: BlockPos, occlusion_data: OcclusionCorners, light: LightLevel) -> Self {
 		let (tl_corner, br_corner, occlusion_data) = match face {
 			BlockFace::XPos => (
 				BlockPos::new(pos_corner_block.x, pos_corner_block.y, neg_corner_block.z),
@@ -314,7 +427,7 @@ impl BlockFaceMesh {
 			),
 		};
 
-		Self::from_corners(face, texture_index, tl_corner, br_corner, occlusion_data)
+		Self::from_corners(face, texture, tl_corner, br_corner, occlusion_data, light)
 	}
 
 	// returns the indicies of the block model to be used for the index buffer
@@ -323,16 +436,41 @@ impl BlockFaceMesh {
 	}
 }
 
-#[enum_dispatch]
+// properties of a type of block
+// hand written blocks implement this so they can be registered, and Block implements it by looking up its definition
 pub trait BlockTrait: Send + Sync {
 	fn name(&self) -> &str;
 	fn is_translucent(&self) -> bool;
+
+	// how long the block takes to break
+	fn hardness(&self) -> f32 {
+		1.0
+	}
+
+	// the light level the block gives off, up to MAX_LIGHT_EMISSION
+	fn light_emission(&self) -> u8 {
+		0
+	}
+
+	// names of the blocks dropped when the block is broken
+	fn drops(&self) -> Vec<&str> {
+		vec![self.name()]
+	}
+
+	// the properties stored in the state of each block of this type
+	fn properties(&self) -> Vec<BlockProperty> {
+		Vec::new()
+	}
+
+	fn shape(&self) -> BlockShape {
+		BlockShape::Cube
+	}
 }
 
+// hand written blocks are registered before the blocks loaded from the block folder,
+// in the order they are given here, so their ids are known at compile time
 macro_rules! blocks {
-	($block:ident,
-		$block_type:ident,
-		$max_texture_index:ident,
+	($builtin_block:ident,
 		untextured {
 			$( $ublocks:ident ),+,
 		},
@@ -340,30 +478,9 @@ macro_rules! blocks {
 			$( $blocks:ident ),+,
 		},
 	) => {
-		// this enum is used to calculate the maximum allowed texture index
-		#[repr(u8)]
+		#[repr(u16)]
 		#[derive(Debug, Clone, Copy)]
-		enum $max_texture_index {
-			$(
-				$blocks,
-			)*
-			Max,
-		}
-
-		#[repr(u8)]
-		#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-		pub enum $block_type {
-			$(
-				$blocks,
-			)*
-			$(
-				$ublocks,
-			)*
-		}
-
-		#[enum_dispatch(BlockTrait)]
-		#[derive(Debug, Clone)]
-		pub enum $block {
+		enum $builtin_block {
 			$(
 				$ublocks,
 			)*
@@ -372,33 +489,37 @@ macro_rules! blocks {
 			)*
 		}
 
-		impl $block {
-			pub fn block_type(&self) -> $block_type {
-				match self {
-					$(
-						Self::$ublocks(_) => $block_type::$ublocks,
-					)*
-					$(
-						Self::$blocks(_) => $block_type::$blocks,
-					)*
+		$(
+			impl From<$ublocks> for Block {
+				fn from(_: $ublocks) -> Self {
+					Self::new($builtin_block::$ublocks as BlockId)
 				}
 			}
-		}
+		)*
+
+		$(
+			impl From<$blocks> for Block {
+				fn from(_: $blocks) -> Self {
+					Self::new($builtin_block::$blocks as BlockId)
+				}
+			}
+		)*
 
-		pub fn generate_texture_array() -> Result<Vec<DynamicImage>> {
-			Ok(vec![
+		fn builtin_definitions() -> Vec<BlockDefinition> {
+			vec![
 				$(
-					$blocks::get_texture()?,
+					BlockDefinition::from_block(&$ublocks::new(), None),
 				)*
-			])
+				$(
+					BlockDefinition::from_block(&$blocks::new(), Some(TextureSource::Generated($blocks::get_texture))),
+				)*
+			]
 		}
 	};
 }
 
 blocks! {
-	Block,
-	BlockType,
-	MaxTextureIndex,
+	BuiltinBlock,
 
 	untextured {
 		Air,
@@ -413,21 +534,164 @@ blocks! {
 	},
 }
 
+// a block in the world, which is the id of its type in the registry and the values of its properties
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Block {
+	id: BlockId,
+	state: BlockState,
+}
+
 impl Block {
+	// the id must be in the registry
+	fn new(id: BlockId) -> Self {
+		Self {
+			id,
+			state: BlockState::default(),
+		}
+	}
+
+	// returns None if there is no block with the id
+	pub fn from_id(id: BlockId) -> Option<Self> {
+		registry().get(id)?;
+		Some(Self::new(id))
+	}
+
+	pub fn from_name(name: &str) -> Option<Self> {
+		Some(Self::new(registry().id_of(name)?))
+	}
+
+	// id of the type of block in the registry, this can be different each time the game is run,
+	// so BlockIdMap is used to get the ids that are saved or sent over the network
+	pub fn id(&self) -> BlockId {
+		self.id
+	}
+
+	pub fn definition(&self) -> &'static BlockDefinition {
+		registry().get(self.id).unwrap()
+	}
+
 	pub fn is_air(&self) -> bool {
-		matches!(self, Self::Air(_))
+		self.id == BuiltinBlock::Air as BlockId
+	}
+
+	// the shape of the block in its current state
+	pub fn geometry(&self) -> &'static BlockGeometry {
+		self.definition().geometry(self.state)
+	}
+
+	// opaque cubes hide all the faces next to them, and are the only blocks that cause ambient occlusion
+	pub fn is_opaque_cube(&self) -> bool {
+		!self.is_translucent() && self.geometry().is_cube()
 	}
 
 	pub fn texture_index(&self) -> Option<TextureIndex> {
-		let block_type = self.block_type() as u8;
-		if block_type >= MaxTextureIndex::Max as u8 {
-			None
+		self.definition().texture_index()
+	}
+
+	// the texture drawn on a face of the block, after the block is turned by its state
+	pub fn face_texture(&self, face: BlockFace) -> Option<FaceTexture> {
+		let definition = self.definition();
+		let texture_index = definition.texture_index()?;
+		let (texture_face, rotation) = definition.state_layout().orient_face(self.state, face);
+
+		Some(FaceTexture {
+			layer: texture_layer(texture_index, texture_face),
+			rotation,
+		})
+	}
+
+	pub fn state(&self) -> BlockState {
+		self.state
+	}
+
+	// returns None if the state is not valid for this type of block
+	pub fn with_state(&self, state: BlockState) -> Option<Self> {
+		if self.definition().state_layout().is_valid(state) {
+			Some(Self {
+				id: self.id,
+				state,
+			})
 		} else {
-			Some(block_type.into())
+			None
 		}
 	}
+
+	// returns None if this type of block doesn't have the property
+	pub fn property(&self, property: BlockProperty) -> Option<u8> {
+		self.definition().state_layout().get(self.state, property)
+	}
+
+	// returns None if this type of block doesn't have the property, or the value is out of range
+	pub fn with_property(&self, property: BlockProperty, value: u8) -> Option<Self> {
+		Some(Self {
+			id: self.id,
+			state: self.definition().state_layout().set(self.state, property, value)?,
+		})
+	}
+
+	pub fn axis(&self) -> Option<Axis> {
+		Axis::from_value(self.property(BlockProperty::Axis)?)
+	}
+
+	pub fn with_axis(&self, axis: Axis) -> Option<Self> {
+		self.with_property(BlockProperty::Axis, axis as u8)
+	}
+
+	// the horizontal direction the front of the block points in
+	pub fn facing(&self) -> Option<BlockFace> {
+		facing_from_value(self.property(BlockProperty::Facing)?)
+	}
+
+	// returns None if the face is not horizontal
+	pub fn with_facing(&self, face: BlockFace) -> Option<Self> {
+		self.with_property(BlockProperty::Facing, facing_value(face)?)
+	}
+
+	pub fn half(&self) -> Option<Half> {
+		Half::from_value(self.property(BlockProperty::Half)?)
+	}
+
+	pub fn with_half(&self, half: Half) -> Option<Self> {
+		self.with_property(BlockProperty::Half, half as u8)
+	}
+}
+
+impl BlockTrait for Block {
+	fn name(&self) -> &str {
+		self.definition().name()
+	}
+
+	fn is_translucent(&self) -> bool {
+		self.definition().is_translucent()
+	}
+
+	fn hardness(&self) -> f32 {
+		self.definition().hardness()
+	}
+
+	fn light_emission(&self) -> u8 {
+		self.definition().light_emission()
+	}
+
+	fn drops(&self) -> Vec<&str> {
+		self.definition().drops().iter().map(String::as_str).collect()
+	}
+
+	fn properties(&self) -> Vec<BlockProperty> {
+		self.definition().state_layout().properties()
+	}
+
+	fn shape(&self) -> BlockShape {
+		self.definition().shape().clone()
+	}
+}
+
+// returns the layers of the block texture array, in the order given by texture_layer
+#[cfg(feature = "client")]
+pub fn generate_texture_array() -> Result<Vec<DynamicImage>> {
+	registry().generate_texture_array()
 }
 
-pub fn num_textures() -> u32 {
-	MaxTextureIndex::Max as u32
+pub fn num_texture_layers() -> u32 {
+	registry().num_textures() * 6
 }
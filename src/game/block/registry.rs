@@ -0,0 +1,503 @@
+use std::lazy::SyncLazy;
+use std::path::Path;
+
+use anyhow::{Result, Context, bail, ensure};
+#[cfg(feature = "client")]
+use image::DynamicImage;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use crate::assets::loader;
+use super::{Block, BlockTrait, BlockFace, TextureIndex, Air, texmanip::BlockTextures, builtin_definitions};
+use super::state::{BlockProperty, BlockState, StateLayout};
+use super::shape::{BlockShape, BlockGeometry};
+
+// numeric id of a type of block, which is its index in the registry
+pub type BlockId = u16;
+
+// folder in the resource folder with a toml file for each data driven block
+const BLOCK_FOLDER: &str = "blocks";
+
+// the brightest light a block can emit
+pub const MAX_LIGHT_EMISSION: u8 = 15;
+
+static REGISTRY: SyncLazy<BlockRegistry> = SyncLazy::new(|| BlockRegistry::load().expect("could not load block definitions"));
+
+pub fn registry() -> &'static BlockRegistry {
+	&REGISTRY
+}
+
+// where the textures of a block come from
+pub enum TextureSource {
+	// hand written blocks generate their textures in code
+	Generated(fn() -> Result<BlockTextures>),
+	// data driven blocks have an image file for each face, indexed by BlockFace
+	Files([String; 6]),
+}
+
+impl TextureSource {
+	#[cfg(feature = "client")]
+	fn load(&self) -> Result<BlockTextures> {
+		match self {
+			Self::Generated(generate) => generate(),
+			Self::Files(files) => {
+				let mut images = Vec::with_capacity(6);
+				for file in files.iter() {
+					images.push(loader().load_image(file)?);
+				}
+				Ok(BlockTextures(images.try_into().unwrap()))
+			},
+		}
+	}
+}
+
+// everything about a type of block that doesn't change between blocks of that type
+pub struct BlockDefinition {
+	name: String,
+	translucent: bool,
+	// how long the block takes to break
+	hardness: f32,
+	light_emission: u8,
+	// names of the blocks dropped when the block is broken
+	drops: Vec<String>,
+	state_layout: StateLayout,
+	shape: BlockShape,
+	// the geometry of the shape in every state, indexed by the bits of the state
+	geometry: Vec<BlockGeometry>,
+	textures: Option<TextureSource>,
+	// assigned when the block is registered, None for blocks without textures
+	texture_index: Option<TextureIndex>,
+}
+
+impl BlockDefinition {
+	pub fn from_block<T: BlockTrait>(block: &T, textures: Option<TextureSource>) -> Self {
+		let state_layout = StateLayout::new(&block.properties()).expect("invalid block properties");
+		let shape = block.shape();
+		shape.check().expect("invalid block shape");
+
+		Self {
+			name: block.name().to_owned(),
+			translucent: block.is_translucent(),
+			hardness: block.hardness(),
+			light_emission: block.light_emission(),
+			drops: block.drops().into_iter().map(str::to_owned).collect(),
+			geometry: shape.geometry(&state_layout),
+			state_layout,
+			shape,
+			textures,
+			texture_index: None,
+		}
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn is_translucent(&self) -> bool {
+		self.translucent
+	}
+
+	pub fn hardness(&self) -> f32 {
+		self.hardness
+	}
+
+	pub fn light_emission(&self) -> u8 {
+		self.light_emission
+	}
+
+	pub fn drops(&self) -> &[String] {
+		&self.drops
+	}
+
+	pub fn state_layout(&self) -> &StateLayout {
+		&self.state_layout
+	}
+
+	pub fn shape(&self) -> &BlockShape {
+		&self.shape
+	}
+
+	// the state must be valid for this block
+	pub fn geometry(&self, state: BlockState) -> &BlockGeometry {
+		&self.geometry[state.bits() as usize]
+	}
+
+	pub fn texture_index(&self) -> Option<TextureIndex> {
+		self.texture_index
+	}
+}
+
+fn default_hardness() -> f32 {
+	1.0
+}
+
+// the format of the block definition files
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockFile {
+	name: String,
+	#[serde(default)]
+	translucent: bool,
+	#[serde(default = "default_hardness")]
+	hardness: f32,
+	#[serde(default)]
+	light_emission: u8,
+	// defaults to the block itself
+	drops: Option<Vec<String>>,
+	#[serde(default)]
+	properties: Vec<BlockProperty>,
+	#[serde(default)]
+	shape: BlockShape,
+	textures: Option<TextureFile>,
+}
+
+// image paths for the faces of a block, a face uses the most specific path that is given
+// front, back, left, and right fall back to side, and every face falls back to all
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TextureFile {
+	all: Option<String>,
+	side: Option<String>,
+	top: Option<String>,
+	bottom: Option<String>,
+	front: Option<String>,
+	back: Option<String>,
+	left: Option<String>,
+	right: Option<String>,
+}
+
+impl TextureFile {
+	fn face_file(&self, face: BlockFace) -> Option<&String> {
+		let (specific, side) = match face {
+			BlockFace::XPos => (&self.right, &self.side),
+			BlockFace::XNeg => (&self.left, &self.side),
+			BlockFace::YPos => (&self.top, &None),
+			BlockFace::YNeg => (&self.bottom, &None),
+			BlockFace::ZPos => (&self.front, &self.side),
+			BlockFace::ZNeg => (&self.back, &self.side),
+		};
+
+		specific.as_ref().or(side.as_ref()).or(self.all.as_ref())
+	}
+}
+
+impl BlockFile {
+	fn into_definition(self) -> Result<BlockDefinition> {
+		ensure!(
+			self.light_emission <= MAX_LIGHT_EMISSION,
+			"light emission is {}, but the maximum is {}",
+			self.light_emission,
+			MAX_LIGHT_EMISSION,
+		);
+
+		let textures = match self.textures {
+			Some(textures) => {
+				let mut files = Vec::with_capacity(6);
+				for face in BlockFace::iter() {
+					match textures.face_file(face) {
+						Some(file) => files.push(file.clone()),
+						None => bail!("no texture is given for the {:?} face", face),
+					}
+				}
+				Some(TextureSource::Files(files.try_into().unwrap()))
+			},
+			None => None,
+		};
+
+		self.shape.check()?;
+		let state_layout = StateLayout::new(&self.properties)?;
+
+		Ok(BlockDefinition {
+			drops: self.drops.unwrap_or_else(|| vec![self.name.clone()]),
+			name: self.name,
+			translucent: self.translucent,
+			hardness: self.hardness,
+			light_emission: self.light_emission,
+			geometry: self.shape.geometry(&state_layout),
+			state_layout,
+			shape: self.shape,
+			textures,
+			texture_index: None,
+		})
+	}
+}
+
+// every type of block, the hand written blocks are registered first, followed by the blocks in the block folder
+pub struct BlockRegistry {
+	definitions: Vec<BlockDefinition>,
+	ids: FxHashMap<String, BlockId>,
+	num_textures: u32,
+}
+
+impl BlockRegistry {
+	fn load() -> Result<Self> {
+		let mut registry = Self::with_builtin_blocks();
+
+		for file in loader().list_folder(BLOCK_FOLDER)? {
+			if file.extension() == Some("toml".as_ref()) {
+				registry.register_file(&file)
+					.with_context(|| format!("invalid block definition {}", file.display()))?;
+			}
+		}
+
+		registry.check_drops()?;
+		Ok(registry)
+	}
+
+	fn with_builtin_blocks() -> Self {
+		let mut registry = Self {
+			definitions: Vec::new(),
+			ids: FxHashMap::default(),
+			num_textures: 0,
+		};
+
+		for definition in builtin_definitions() {
+			registry.register(definition).expect("invalid hand written block");
+		}
+
+		registry
+	}
+
+	fn register_file(&mut self, file: &Path) -> Result<BlockId> {
+		let block_file: BlockFile = toml::from_str(&loader().load_string(file)?)?;
+		self.register(block_file.into_definition()?)
+	}
+
+	fn register(&mut self, mut definition: BlockDefinition) -> Result<BlockId> {
+		ensure!(!self.ids.contains_key(&definition.name), "there is already a block named {}", definition.name);
+		let id = BlockId::try_from(self.definitions.len())
+			.map_err(|_| anyhow::anyhow!("too many blocks, the maximum is {}", BlockId::MAX as usize + 1))?;
+
+		if definition.textures.is_some() {
+			definition.texture_index = Some(self.num_textures.try_into().unwrap());
+			self.num_textures += 1;
+		}
+
+		self.ids.insert(definition.name.clone(), id);
+		self.definitions.push(definition);
+		Ok(id)
+	}
+
+	// makes sure every block only drops blocks that exist
+	fn check_drops(&self) -> Result<()> {
+		for definition in self.definitions.iter() {
+			for drop in definition.drops.iter() {
+				ensure!(self.ids.contains_key(drop), "block {} drops {}, which is not a block", definition.name, drop);
+			}
+		}
+		Ok(())
+	}
+
+	pub fn get(&self, id: BlockId) -> Option<&BlockDefinition> {
+		self.definitions.get(id as usize)
+	}
+
+	pub fn id_of(&self, name: &str) -> Option<BlockId> {
+		self.ids.get(name).copied()
+	}
+
+	// the number of blocks, every id below this is valid
+	pub fn len(&self) -> usize {
+		self.definitions.len()
+	}
+
+	pub fn num_textures(&self) -> u32 {
+		self.num_textures
+	}
+
+	// returns the layers of the block texture array, in the order given by texture_layer
+	#[cfg(feature = "client")]
+	pub fn generate_texture_array(&self) -> Result<Vec<DynamicImage>> {
+		let mut out = Vec::new();
+		for definition in self.definitions.iter() {
+			if let Some(textures) = &definition.textures {
+				let textures = textures.load()
+					.with_context(|| format!("could not load textures of block {}", definition.name))?;
+				out.extend(textures.0);
+			}
+		}
+		Ok(out)
+	}
+}
+
+// the ids blocks get depend on which blocks are registered, so worlds store the name of each id they use
+// this maps between the ids blocks have in the registry and the ids a world saves them with
+#[derive(Debug, Clone)]
+pub struct BlockIdMap {
+	// name of the block with each saved id
+	names: Vec<String>,
+	// saved id of each block in the registry
+	saved_ids: Vec<BlockId>,
+	// block with each saved id, None if there is no block with that name anymore
+	blocks: Vec<Option<Block>>,
+}
+
+impl BlockIdMap {
+	// keeps the saved ids of all the names, and gives the next ids to registered blocks that aren't in names
+	pub fn new(mut names: Vec<String>) -> Result<Self> {
+		let registry = registry();
+		let mut saved_ids: Vec<Option<BlockId>> = vec![None; registry.len()];
+		let mut blocks = Vec::with_capacity(names.len());
+
+		for (saved_id, name) in names.iter().enumerate() {
+			let block = registry.id_of(name);
+			if let Some(id) = block {
+				ensure!(saved_ids[id as usize].is_none(), "block {} has more than 1 saved id", name);
+				saved_ids[id as usize] = Some(saved_id.try_into()?);
+			}
+			blocks.push(block.map(Block::new));
+		}
+
+		for (id, definition) in registry.definitions.iter().enumerate() {
+			if saved_ids[id].is_none() {
+				saved_ids[id] = Some(names.len().try_into().context("too many saved block ids")?);
+				names.push(definition.name.clone());
+				blocks.push(Some(Block::new(id as BlockId)));
+			}
+		}
+
+		Ok(Self {
+			names,
+			saved_ids: saved_ids.into_iter().map(Option::unwrap).collect(),
+			blocks,
+		})
+	}
+
+	// saved ids are the same as the ids in the registry
+	pub fn from_registry() -> Self {
+		Self::new(Vec::new()).unwrap()
+	}
+
+	// names of the blocks, indexed by saved id
+	pub fn names(&self) -> &[String] {
+		&self.names
+	}
+
+	// names that were saved but are not in the registry
+	pub fn missing_names(&self) -> impl Iterator<Item = &str> {
+		self.names.iter()
+			.zip(self.blocks.iter())
+			.filter(|(_, block)| block.is_none())
+			.map(|(name, _)| name.as_str())
+	}
+
+	pub fn saved_id(&self, block: &Block) -> BlockId {
+		self.saved_ids[block.id as usize]
+	}
+
+	// returns None if there is no block with the saved id, or the state isn't valid for the block
+	// blocks which don't exist anymore are replaced with air, so chunks that have them can still be loaded
+	pub fn block(&self, saved_id: BlockId, state: BlockState) -> Option<Block> {
+		match self.blocks.get(saved_id as usize)? {
+			Some(block) => block.with_state(state),
+			None => Some(Air::new().into()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::game::block::{Air, Stone};
+
+	fn register_str(registry: &mut BlockRegistry, text: &str) -> Result<BlockId> {
+		let block_file: BlockFile = toml::from_str(text)?;
+		registry.register(block_file.into_definition()?)
+	}
+
+	#[test]
+	fn data_driven_blocks() {
+		let mut registry = BlockRegistry::with_builtin_blocks();
+		let builtin_count = registry.len();
+		let builtin_textures = registry.num_textures();
+
+		let lamp = register_str(&mut registry, r#"
+			name = "lamp"
+			light_emission = 15
+			hardness = 0.5
+			textures = { all = "textures/stone.png", top = "textures/dirt.png" }
+		"#).unwrap();
+		let glass = register_str(&mut registry, r#"
+			name = "glass"
+			translucent = true
+			drops = []
+			properties = ["facing", "half"]
+		"#).unwrap();
+
+		assert_eq!(lamp as usize, builtin_count);
+		assert_eq!(glass as usize, builtin_count + 1);
+		assert_eq!(registry.id_of("lamp"), Some(lamp));
+
+		let lamp = registry.get(lamp).unwrap();
+		assert_eq!(lamp.light_emission(), 15);
+		assert_eq!(lamp.hardness(), 0.5);
+		assert!(!lamp.is_translucent());
+		assert_eq!(lamp.drops(), ["lamp".to_owned()]);
+		assert_eq!(lamp.texture_index(), Some(builtin_textures as TextureIndex));
+		match &lamp.textures {
+			Some(TextureSource::Files(files)) => {
+				assert_eq!(files[usize::from(BlockFace::YPos)], "textures/dirt.png");
+				assert_eq!(files[usize::from(BlockFace::XNeg)], "textures/stone.png");
+			},
+			_ => panic!("lamp should have texture files"),
+		}
+
+		let glass = registry.get(glass).unwrap();
+		assert!(glass.is_translucent());
+		assert!(glass.drops().is_empty());
+		assert_eq!(glass.texture_index(), None);
+		assert_eq!(glass.state_layout().properties(), [BlockProperty::Facing, BlockProperty::Half]);
+		assert_eq!(registry.num_textures(), builtin_textures + 1);
+
+		registry.check_drops().unwrap();
+
+		// names have to be unique, and light emission has to be in range
+		assert!(register_str(&mut registry, "name = \"glass\"").is_err());
+		assert!(register_str(&mut registry, "name = \"sun\"\nlight_emission = 16").is_err());
+		// every face needs a texture
+		assert!(register_str(&mut registry, "name = \"half\"\ntextures = { side = \"a.png\" }").is_err());
+		// shapes can be given by name or as a list of boxes
+		let slab = register_str(&mut registry, "name = \"slab\"\nshape = \"slab\"").unwrap();
+		assert_eq!(registry.get(slab).unwrap().shape(), &BlockShape::Slab);
+		let carpet = register_str(&mut registry, r#"
+			name = "carpet"
+			shape = { boxes = [{ min = [0, 0, 0], max = [16, 1, 16] }] }
+		"#).unwrap();
+		assert!(!registry.get(carpet).unwrap().geometry(BlockState::default()).is_cube());
+		assert!(register_str(&mut registry, "name = \"big\"\nshape = { boxes = [{ min = [0, 0, 0], max = [17, 1, 16] }] }").is_err());
+		// properties can't be repeated
+		assert!(register_str(&mut registry, "name = \"log\"\nproperties = [\"axis\", \"axis\"]").is_err());
+
+		register_str(&mut registry, "name = \"ore\"\ndrops = [\"gem\"]").unwrap();
+		assert!(registry.check_drops().is_err());
+	}
+
+	#[test]
+	fn saved_ids_are_kept() {
+		let stone = Block::from(Stone::new());
+		let air = Block::from(Air::new());
+
+		// stone was saved first, and there is a block that doesn't exist anymore
+		let map = BlockIdMap::new(vec!["stone".to_owned(), "removed block".to_owned()]).unwrap();
+
+		assert_eq!(map.saved_id(&stone), 0);
+		assert_eq!(map.block(0, BlockState::default()), Some(stone.clone()));
+		// blocks that don't exist anymore load as air, whatever state they were saved with
+		assert_eq!(map.block(1, BlockState::from_bits(5)), Some(air.clone()));
+		assert_eq!(map.missing_names().collect::<Vec<_>>(), ["removed block"]);
+		assert_eq!(map.names().len(), registry().len() + 1);
+
+		// new blocks get the ids after the saved ones
+		let air_id = map.saved_id(&air);
+		assert!(air_id >= 2);
+		assert_eq!(map.block(air_id, BlockState::default()), Some(air));
+		assert_eq!(map.block(map.names().len() as BlockId, BlockState::default()), None);
+
+		// loading the names again gives the same ids
+		let reloaded = BlockIdMap::new(map.names().to_vec()).unwrap();
+		assert_eq!(reloaded.names(), map.names());
+		assert_eq!(reloaded.saved_id(&stone), 0);
+
+		assert!(BlockIdMap::new(vec!["stone".to_owned(), "stone".to_owned()]).is_err());
+	}
+}
@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RockyDirt {}
 
 impl RockyDirt {
@@ -8,8 +8,8 @@ impl RockyDirt {
 		RockyDirt {}
 	}
 
-	pub fn get_texture() -> Result<DynamicImage> {
-		Ok(texmanip::tile_from_side(&loader().load_image("textures/rocky-dirt.png")?))
+	pub fn get_texture() -> Result<texmanip::BlockTextures> {
+		Ok(texmanip::BlockTextures::from_side(&loader().load_image("textures/rocky-dirt.png")?))
 	}
 }
 
@@ -0,0 +1,279 @@
+use anyhow::{Result, ensure};
+use array_init::array_init;
+use nalgebra::{Matrix3, Vector3};
+use serde::Deserialize;
+
+use crate::prelude::*;
+use super::BlockFace;
+use super::state::{BlockProperty, BlockState, StateLayout, Half};
+
+// number of steps each side of a block is split into for block shapes
+pub const SHAPE_UNITS: u8 = 16;
+
+// a box inside of a block, in 16ths of a block, not including max
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShapeBox {
+	pub min: [u8; 3],
+	pub max: [u8; 3],
+}
+
+impl ShapeBox {
+	// the entire block
+	pub const FULL: Self = Self::new([0, 0, 0], [SHAPE_UNITS; 3]);
+
+	pub const fn new(min: [u8; 3], max: [u8; 3]) -> Self {
+		Self {
+			min,
+			max,
+		}
+	}
+
+	fn check(&self) -> Result<()> {
+		for axis in 0..3 {
+			ensure!(
+				self.min[axis] < self.max[axis] && self.max[axis] <= SHAPE_UNITS,
+				"box from {:?} to {:?} is empty or goes outside of the block",
+				self.min,
+				self.max,
+			);
+		}
+		Ok(())
+	}
+
+	// the corners relative to the block, in blocks
+	pub fn min_offset(&self) -> Position {
+		Position::from(self.min.map(|n| n as f64 / SHAPE_UNITS as f64))
+	}
+
+	pub fn max_offset(&self) -> Position {
+		Position::from(self.max.map(|n| n as f64 / SHAPE_UNITS as f64))
+	}
+
+	// if the face of the box is on the side of the block
+	pub fn touches_face(&self, face: BlockFace) -> bool {
+		let axis = face_axis(face);
+		if face.is_positive_face() {
+			self.max[axis] == SHAPE_UNITS
+		} else {
+			self.min[axis] == 0
+		}
+	}
+
+	// flips the box upside down when half is top, then turns it with the rotation from the block's state
+	fn oriented(&self, rotation: &Matrix3<i64>, half: Half) -> Self {
+		let units = SHAPE_UNITS as i64;
+		let mut min = Vector3::from(self.min.map(i64::from));
+		let mut max = Vector3::from(self.max.map(i64::from));
+
+		if half == Half::Top {
+			(min.y, max.y) = (units - max.y, units - min.y);
+		}
+
+		// turn around the center of the block
+		let center = Vector3::repeat(units / 2);
+		let a = rotation * (min - center) + center;
+		let b = rotation * (max - center) + center;
+
+		Self {
+			min: array_init(|axis| a[axis].min(b[axis]) as u8),
+			max: array_init(|axis| a[axis].max(b[axis]) as u8),
+		}
+	}
+}
+
+// the shape of a type of block, as the block is when its textures are defined
+//
+// shapes made of boxes are turned by the axis and facing properties of the block,
+// and are flipped upside down by the half property
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockShape {
+	#[default]
+	Cube,
+	// the bottom half of the block
+	Slab,
+	// a slab with a step on the back half, so the front faces the way a player walks up it
+	Stair,
+	// a thin post in the middle of the block
+	FencePost,
+	// 2 planes crossing along the diagonals of the block, like a plant
+	Cross,
+	Boxes(Vec<ShapeBox>),
+}
+
+impl BlockShape {
+	pub fn check(&self) -> Result<()> {
+		if let Self::Boxes(boxes) = self {
+			ensure!(!boxes.is_empty(), "a block shape needs at least 1 box");
+			for shape_box in boxes.iter() {
+				shape_box.check()?;
+			}
+		}
+		Ok(())
+	}
+
+	// returns None for shapes that aren't made of boxes
+	fn boxes(&self) -> Option<Vec<ShapeBox>> {
+		let half = SHAPE_UNITS / 2;
+		match self {
+			Self::Cube | Self::Cross => None,
+			Self::Slab => Some(vec![ShapeBox::new([0, 0, 0], [SHAPE_UNITS, half, SHAPE_UNITS])]),
+			Self::Stair => Some(vec![
+				ShapeBox::new([0, 0, 0], [SHAPE_UNITS, half, SHAPE_UNITS]),
+				ShapeBox::new([0, half, 0], [SHAPE_UNITS, SHAPE_UNITS, half]),
+			]),
+			Self::FencePost => Some(vec![ShapeBox::new([6, 0, 6], [10, SHAPE_UNITS, 10])]),
+			Self::Boxes(boxes) => Some(boxes.clone()),
+		}
+	}
+
+	// returns the geometry of the shape for every state in the layout, indexed by the state's bits
+	pub fn geometry(&self, layout: &StateLayout) -> Vec<BlockGeometry> {
+		(0..layout.num_states()).map(|bits| {
+			let state = BlockState::from_bits(bits as u8);
+
+			match self.boxes() {
+				Some(boxes) => {
+					let rotation = layout.rotation(state);
+					let half = layout.get(state, BlockProperty::Half)
+						.and_then(Half::from_value)
+						.unwrap_or(Half::Bottom);
+
+					BlockGeometry::from_boxes(boxes.iter().map(|shape_box| shape_box.oriented(&rotation, half)).collect())
+				},
+				None if *self == Self::Cross => BlockGeometry::Cross,
+				None => BlockGeometry::Cube,
+			}
+		}).collect()
+	}
+}
+
+// the parts of a side of the block space which are covered by a shape
+// there is a bit for every 16th of a block on the 2 axes in the plane of the face, see plane_axes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaceCoverage([u16; SHAPE_UNITS as usize]);
+
+impl FaceCoverage {
+	// covers the area that the face of the box would take up on the side of the block
+	fn insert(&mut self, face: BlockFace, shape_box: &ShapeBox) {
+		let (a, b) = plane_axes(face);
+		for row in shape_box.min[a]..shape_box.max[a] {
+			for column in shape_box.min[b]..shape_box.max[b] {
+				self.0[row as usize] |= 1 << column;
+			}
+		}
+	}
+
+	fn contains(&self, face: BlockFace, shape_box: &ShapeBox) -> bool {
+		let (a, b) = plane_axes(face);
+		(shape_box.min[a]..shape_box.max[a]).all(|row| {
+			(shape_box.min[b]..shape_box.max[b]).all(|column| self.0[row as usize] & (1 << column) != 0)
+		})
+	}
+}
+
+// the geometry of a block in a specific state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockGeometry {
+	// full cubes are the only blocks which are greedy meshed
+	Cube,
+	Boxes {
+		boxes: Vec<ShapeBox>,
+		// indexed by BlockFace, boxed so the other variants stay small
+		coverage: Box<[FaceCoverage; 6]>,
+	},
+	Cross,
+}
+
+impl BlockGeometry {
+	fn from_boxes(boxes: Vec<ShapeBox>) -> Self {
+		let mut coverage = [FaceCoverage::default(); 6];
+		for shape_box in boxes.iter() {
+			for face in BlockFace::iter() {
+				if shape_box.touches_face(face) {
+					coverage[usize::from(face)].insert(face, shape_box);
+				}
+			}
+		}
+
+		Self::Boxes {
+			boxes,
+			coverage: Box::new(coverage),
+		}
+	}
+
+	pub fn is_cube(&self) -> bool {
+		matches!(self, Self::Cube)
+	}
+
+	// if the part of the side of the block space that the box's face would be on is completely covered,
+	// which means the face of the block next to this one which touches that area is hidden if this block is opaque
+	pub fn covers(&self, face: BlockFace, shape_box: &ShapeBox) -> bool {
+		match self {
+			Self::Cube => true,
+			Self::Boxes { coverage, .. } => coverage[usize::from(face)].contains(face, shape_box),
+			Self::Cross => false,
+		}
+	}
+}
+
+fn face_axis(face: BlockFace) -> usize {
+	match face {
+		BlockFace::XPos | BlockFace::XNeg => 0,
+		BlockFace::YPos | BlockFace::YNeg => 1,
+		BlockFace::ZPos | BlockFace::ZNeg => 2,
+	}
+}
+
+// the axes in the plane of a face, which are the same for both faces on an axis so opposite faces line up
+fn plane_axes(face: BlockFace) -> (usize, usize) {
+	match face {
+		BlockFace::XPos | BlockFace::XNeg => (1, 2),
+		BlockFace::YPos | BlockFace::YNeg => (0, 2),
+		BlockFace::ZPos | BlockFace::ZNeg => (0, 1),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::game::block::state::facing_from_value;
+
+	#[test]
+	fn slab_coverage() {
+		let layout = StateLayout::new(&[BlockProperty::Half]).unwrap();
+		let geometry = BlockShape::Slab.geometry(&layout);
+		let bottom = &geometry[Half::Bottom as usize];
+		let top = &geometry[Half::Top as usize];
+
+		assert!(bottom.covers(BlockFace::YNeg, &ShapeBox::FULL));
+		assert!(!bottom.covers(BlockFace::YPos, &ShapeBox::FULL));
+		assert!(top.covers(BlockFace::YPos, &ShapeBox::FULL));
+		assert!(!top.covers(BlockFace::YNeg, &ShapeBox::FULL));
+
+		// the side of a bottom slab only hides the bottom half of the face next to it
+		let bottom_half = ShapeBox::new([0, 0, 0], [16, 8, 16]);
+		let top_half = ShapeBox::new([0, 8, 0], [16, 16, 16]);
+		assert!(bottom.covers(BlockFace::XPos, &bottom_half));
+		assert!(!bottom.covers(BlockFace::XPos, &top_half));
+		assert!(!bottom.covers(BlockFace::XPos, &ShapeBox::FULL));
+	}
+
+	#[test]
+	fn stairs_turn_with_facing() {
+		let layout = StateLayout::new(&[BlockProperty::Facing]).unwrap();
+		let geometry = BlockShape::Stair.geometry(&layout);
+
+		// the back of the stair is a full face, and the front isn't
+		for (bits, geometry) in geometry.iter().enumerate() {
+			let front = facing_from_value(bits as u8).unwrap();
+			assert!(geometry.covers(front.opposite(), &ShapeBox::FULL));
+			assert!(!geometry.covers(front, &ShapeBox::FULL));
+			assert!(geometry.covers(BlockFace::YNeg, &ShapeBox::FULL));
+		}
+
+		let fence = BlockShape::FencePost.geometry(&layout);
+		assert!(BlockFace::iter().all(|face| !fence[0].covers(face, &ShapeBox::FULL)));
+	}
+}
@@ -0,0 +1,297 @@
+use anyhow::{Result, ensure};
+use nalgebra::{Matrix3, Vector3};
+use serde::Deserialize;
+
+use super::BlockFace;
+
+// a property that blocks of a type can have, which is stored in the state of each block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockProperty {
+	// the axis the block is lined up with, like a log
+	// the top face of the block's textures points along the axis
+	Axis,
+	// the horizontal direction the front face of the block points in, like a stair
+	Facing,
+	// which half of the block space the block is in, like a slab
+	Half,
+}
+
+impl BlockProperty {
+	// number of bits the property uses in the state
+	fn bits(&self) -> u32 {
+		match self {
+			Self::Axis => 2,
+			Self::Facing => 2,
+			Self::Half => 1,
+		}
+	}
+
+	fn num_values(&self) -> u8 {
+		match self {
+			Self::Axis => 3,
+			Self::Facing => 4,
+			Self::Half => 2,
+		}
+	}
+}
+
+// value 0 of each property is the block how its textures are defined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+	Y = 0,
+	X = 1,
+	Z = 2,
+}
+
+impl Axis {
+	pub fn from_value(value: u8) -> Option<Self> {
+		[Self::Y, Self::X, Self::Z].get(value as usize).copied()
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Half {
+	Bottom = 0,
+	Top = 1,
+}
+
+impl Half {
+	pub fn from_value(value: u8) -> Option<Self> {
+		[Self::Bottom, Self::Top].get(value as usize).copied()
+	}
+}
+
+// the faces a block can be facing, in the order of their values
+const FACING_VALUES: [BlockFace; 4] = [BlockFace::ZPos, BlockFace::XPos, BlockFace::ZNeg, BlockFace::XNeg];
+
+pub fn facing_from_value(value: u8) -> Option<BlockFace> {
+	FACING_VALUES.get(value as usize).copied()
+}
+
+// returns None for faces that aren't horizontal
+pub fn facing_value(face: BlockFace) -> Option<u8> {
+	FACING_VALUES.iter().position(|elem| *elem == face).map(|value| value as u8)
+}
+
+// the values of all the properties of a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct BlockState(u8);
+
+impl BlockState {
+	pub fn from_bits(bits: u8) -> Self {
+		Self(bits)
+	}
+
+	pub fn bits(&self) -> u8 {
+		self.0
+	}
+}
+
+// the properties of a type of block, each property is stored in its own bits of the state in the order they are declared
+#[derive(Debug, Clone, Default)]
+pub struct StateLayout {
+	// each property with the position of its lowest bit
+	properties: Vec<(BlockProperty, u32)>,
+	// number of bits used by all the properties
+	bits: u32,
+}
+
+impl StateLayout {
+	pub fn new(properties: &[BlockProperty]) -> Result<Self> {
+		let mut out = Self::default();
+		let mut shift = 0;
+
+		for property in properties.iter() {
+			ensure!(out.shift_of(*property).is_none(), "property {:?} is declared more than once", property);
+			out.properties.push((*property, shift));
+			shift += property.bits();
+		}
+		ensure!(shift <= u8::BITS, "the properties need {} bits, but states only have {} bits", shift, u8::BITS);
+		out.bits = shift;
+
+		Ok(out)
+	}
+
+	pub fn properties(&self) -> Vec<BlockProperty> {
+		self.properties.iter().map(|(property, _)| *property).collect()
+	}
+
+	// every valid state is less than this, but not every state less than this is valid
+	pub fn num_states(&self) -> usize {
+		1 << self.bits
+	}
+
+	fn shift_of(&self, property: BlockProperty) -> Option<u32> {
+		self.properties.iter()
+			.find(|(elem, _)| *elem == property)
+			.map(|(_, shift)| *shift)
+	}
+
+	// returns None if the block doesn't have the property
+	pub fn get(&self, state: BlockState, property: BlockProperty) -> Option<u8> {
+		let shift = self.shift_of(property)?;
+		Some((state.0 >> shift) & ((1 << property.bits()) - 1))
+	}
+
+	// returns None if the block doesn't have the property or the value is out of range
+	pub fn set(&self, state: BlockState, property: BlockProperty, value: u8) -> Option<BlockState> {
+		let shift = self.shift_of(property)?;
+		if value >= property.num_values() {
+			return None;
+		}
+
+		let mask = ((1 << property.bits()) - 1) << shift;
+		Some(BlockState((state.0 & !mask) | (value << shift)))
+	}
+
+	// a state is valid if every property has a valid value and no other bits are set
+	pub fn is_valid(&self, state: BlockState) -> bool {
+		let mut used_bits = 0u8;
+		for (property, shift) in self.properties.iter() {
+			if self.get(state, *property).unwrap() >= property.num_values() {
+				return false;
+			}
+			used_bits |= ((1 << property.bits()) - 1) << shift;
+		}
+
+		state.0 & !used_bits == 0
+	}
+
+	// the rotation from the block as its textures are defined to the block in the world
+	pub(super) fn rotation(&self, state: BlockState) -> Matrix3<i64> {
+		let axis_rotation = match self.get(state, BlockProperty::Axis).and_then(Axis::from_value) {
+			Some(Axis::Y) | None => Matrix3::identity(),
+			// the top of the block turns to face positive x
+			Some(Axis::X) => Matrix3::new(
+				0, 1, 0,
+				-1, 0, 0,
+				0, 0, 1,
+			),
+			// the top of the block turns to face positive z
+			Some(Axis::Z) => Matrix3::new(
+				1, 0, 0,
+				0, 0, -1,
+				0, 1, 0,
+			),
+		};
+
+		// turns around the y axis so the front of the block, which is z positive, points towards the facing
+		let facing_rotation = match self.get(state, BlockProperty::Facing).and_then(facing_from_value) {
+			Some(BlockFace::XPos) => Matrix3::new(
+				0, 0, 1,
+				0, 1, 0,
+				-1, 0, 0,
+			),
+			Some(BlockFace::ZNeg) => Matrix3::new(
+				-1, 0, 0,
+				0, 1, 0,
+				0, 0, -1,
+			),
+			Some(BlockFace::XNeg) => Matrix3::new(
+				0, 0, -1,
+				0, 1, 0,
+				1, 0, 0,
+			),
+			_ => Matrix3::identity(),
+		};
+
+		facing_rotation * axis_rotation
+	}
+
+	// returns which face of the block's textures is drawn on the face of the block in the world,
+	// and how many quarter turns the texture is rotated by, in the direction used by the shader
+	pub fn orient_face(&self, state: BlockState, face: BlockFace) -> (BlockFace, u8) {
+		let rotation = self.rotation(state);
+		let texture_face = face_from_normal(rotation.transpose() * face.block_pos_offset()).unwrap();
+
+		// where the texture's u axis ends up compared to the u and v axes the shader uses for the face
+		let (u_axis, _) = texture_axes(texture_face);
+		let rotated_u_axis = rotation * u_axis;
+		let (face_u_axis, face_v_axis) = texture_axes(face);
+
+		let quarter_turns = match (rotated_u_axis.dot(&face_u_axis), rotated_u_axis.dot(&face_v_axis)) {
+			(1, 0) => 0,
+			(0, -1) => 1,
+			(-1, 0) => 2,
+			(0, 1) => 3,
+			_ => unreachable!("rotated texture is not in the plane of the face"),
+		};
+
+		(texture_face, quarter_turns)
+	}
+}
+
+fn face_from_normal(normal: Vector3<i64>) -> Option<BlockFace> {
+	BlockFace::iter().find(|face| face.block_pos_offset() == normal)
+}
+
+// the directions the u and v texture coordinates increase in on each face, this must match fs_main in shader.wgsl
+fn texture_axes(face: BlockFace) -> (Vector3<i64>, Vector3<i64>) {
+	match face {
+		BlockFace::XPos => (Vector3::new(0, 0, -1), Vector3::new(0, -1, 0)),
+		BlockFace::XNeg => (Vector3::new(0, 0, 1), Vector3::new(0, -1, 0)),
+		BlockFace::YPos => (Vector3::new(1, 0, 0), Vector3::new(0, 0, 1)),
+		BlockFace::YNeg => (Vector3::new(-1, 0, 0), Vector3::new(0, 0, 1)),
+		BlockFace::ZPos => (Vector3::new(1, 0, 0), Vector3::new(0, -1, 0)),
+		BlockFace::ZNeg => (Vector3::new(-1, 0, 0), Vector3::new(0, -1, 0)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn properties_are_packed() {
+		let layout = StateLayout::new(&[BlockProperty::Half, BlockProperty::Facing]).unwrap();
+		let default = BlockState::default();
+
+		let state = layout.set(default, BlockProperty::Facing, 3).unwrap();
+		let state = layout.set(state, BlockProperty::Half, Half::Top as u8).unwrap();
+		assert_eq!(layout.get(state, BlockProperty::Facing), Some(3));
+		assert_eq!(layout.get(state, BlockProperty::Half), Some(1));
+		assert_eq!(layout.get(state, BlockProperty::Axis), None);
+		assert!(layout.is_valid(state));
+
+		assert_eq!(layout.set(state, BlockProperty::Facing, 4), None);
+		assert_eq!(layout.set(state, BlockProperty::Axis, 0), None);
+		// only 3 bits are used
+		assert!(!layout.is_valid(BlockState::from_bits(0b1000)));
+
+		assert!(StateLayout::new(&[BlockProperty::Axis, BlockProperty::Axis]).is_err());
+	}
+
+	#[test]
+	fn unrotated_faces() {
+		let layout = StateLayout::new(&[BlockProperty::Axis, BlockProperty::Facing]).unwrap();
+		for face in BlockFace::iter() {
+			assert_eq!(layout.orient_face(BlockState::default(), face), (face, 0));
+		}
+	}
+
+	#[test]
+	fn rotated_faces() {
+		let layout = StateLayout::new(&[BlockProperty::Axis, BlockProperty::Facing]).unwrap();
+
+		// a log lying along the x axis shows its top and bottom on the x faces, and its sides turned sideways
+		let x_axis = layout.set(BlockState::default(), BlockProperty::Axis, Axis::X as u8).unwrap();
+		assert_eq!(layout.orient_face(x_axis, BlockFace::XPos).0, BlockFace::YPos);
+		assert_eq!(layout.orient_face(x_axis, BlockFace::XNeg).0, BlockFace::YNeg);
+		let (side, turns) = layout.orient_face(x_axis, BlockFace::ZPos);
+		assert_eq!(side, BlockFace::ZPos);
+		assert!(turns % 2 == 1);
+
+		let z_axis = layout.set(BlockState::default(), BlockProperty::Axis, Axis::Z as u8).unwrap();
+		assert_eq!(layout.orient_face(z_axis, BlockFace::ZPos).0, BlockFace::YPos);
+		assert_eq!(layout.orient_face(z_axis, BlockFace::YPos).0, BlockFace::ZNeg);
+
+		// facing only turns the block around the y axis, so the sides stay upright
+		for face in [BlockFace::XPos, BlockFace::XNeg, BlockFace::ZNeg] {
+			let state = layout.set(BlockState::default(), BlockProperty::Facing, facing_value(face).unwrap()).unwrap();
+			assert_eq!(layout.orient_face(state, face), (BlockFace::ZPos, 0));
+			assert_eq!(layout.orient_face(state, BlockFace::YNeg).0, BlockFace::YNeg);
+			assert_eq!(layout.orient_face(state, face.opposite()), (BlockFace::ZNeg, 0));
+		}
+	}
+}
@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Stone {}
 
 impl Stone {
@@ -8,8 +8,8 @@ impl Stone {
 		Stone {}
 	}
 
-	pub fn get_texture() -> Result<DynamicImage> {
-		Ok(texmanip::tile_from_side(&loader().load_image("textures/stone.png")?))
+	pub fn get_texture() -> Result<texmanip::BlockTextures> {
+		Ok(texmanip::BlockTextures::from_side(&loader().load_image("textures/stone.png")?))
 	}
 }
 
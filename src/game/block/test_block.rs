@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TestBlock {}
 
 impl TestBlock {
@@ -8,8 +8,8 @@ impl TestBlock {
 		TestBlock {}
 	}
 
-	pub fn get_texture() -> Result<DynamicImage> {
-		Ok(texmanip::tile_from_side(&loader().load_image("textures/test-block.png")?))
+	pub fn get_texture() -> Result<texmanip::BlockTextures> {
+		Ok(texmanip::BlockTextures::from_side(&loader().load_image("textures/test-block.png")?))
 	}
 }
 
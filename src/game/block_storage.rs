@@ -0,0 +1,302 @@
+use std::mem;
+
+use super::block::Block;
+use super::chunk::CHUNK_SIZE;
+
+// total number of blocks stored in a chunk
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+// gets the index of a block in the storage from its chunk local coordinates
+pub fn block_index(x: usize, y: usize, z: usize) -> usize {
+	(x * CHUNK_SIZE + y) * CHUNK_SIZE + z
+}
+
+// stores all the blocks of a chunk
+// each distinct block is only stored once in a palette, and each position stores a bit packed index into the palette
+pub enum BlockStorage {
+	// the entire chunk is made of this block, which is very common for chunks of air or stone
+	Uniform(Block),
+	Paletted(PalettedStorage),
+}
+
+impl BlockStorage {
+	// creates the storage by calling block_fn with the index of every block
+	pub fn from_fn<F: FnMut(usize) -> Block>(mut block_fn: F) -> Self {
+		let mut palette: Vec<Block> = Vec::new();
+		let mut indicies = Vec::with_capacity(CHUNK_VOLUME);
+
+		for i in 0..CHUNK_VOLUME {
+			let block = block_fn(i);
+
+			let palette_index = match palette.iter().position(|elem| *elem == block) {
+				Some(index) => index,
+				None => {
+					palette.push(block);
+					palette.len() - 1
+				},
+			};
+			indicies.push(palette_index);
+		}
+
+		if palette.len() == 1 {
+			return Self::Uniform(palette.pop().unwrap());
+		}
+
+		let bits = PalettedStorage::bits_needed(palette.len());
+		let mut storage = PalettedStorage::new(palette, bits);
+		for (i, palette_index) in indicies.into_iter().enumerate() {
+			storage.set_index(i, palette_index);
+		}
+		Self::Paletted(storage)
+	}
+
+	pub fn get(&self, index: usize) -> &Block {
+		match self {
+			Self::Uniform(block) => block,
+			Self::Paletted(storage) => storage.get(index),
+		}
+	}
+
+	pub fn set(&mut self, index: usize, block: Block) {
+		match self {
+			Self::Uniform(uniform_block) => {
+				if *uniform_block == block {
+					return;
+				}
+
+				let uniform_block = mem::replace(uniform_block, block.clone());
+				let mut storage = PalettedStorage::new(vec![uniform_block], 1);
+				storage.set(index, block);
+				*self = Self::Paletted(storage);
+			},
+			Self::Paletted(storage) => storage.set(index, block),
+		}
+	}
+}
+
+pub struct PalettedStorage {
+	palette: Vec<Block>,
+	// number of bits used by each index, always a power of 2 so an index never spans 2 words
+	bits: usize,
+	data: Vec<u64>,
+}
+
+impl PalettedStorage {
+	const MAX_BITS: usize = 16;
+
+	fn new(palette: Vec<Block>, bits: usize) -> Self {
+		let indicies_per_word = 64 / bits;
+		Self {
+			palette,
+			bits,
+			data: vec![0; (CHUNK_VOLUME + indicies_per_word - 1) / indicies_per_word],
+		}
+	}
+
+	fn bits_needed(palette_len: usize) -> usize {
+		let mut bits = 1;
+		while (1 << bits) < palette_len {
+			bits *= 2;
+		}
+		assert!(bits <= Self::MAX_BITS, "too many different blocks in chunk");
+		bits
+	}
+
+	fn get_index(&self, index: usize) -> usize {
+		let indicies_per_word = 64 / self.bits;
+		let word = self.data[index / indicies_per_word];
+		let shift = (index % indicies_per_word) * self.bits;
+		((word >> shift) & ((1 << self.bits) - 1)) as usize
+	}
+
+	fn set_index(&mut self, index: usize, palette_index: usize) {
+		let indicies_per_word = 64 / self.bits;
+		let word = &mut self.data[index / indicies_per_word];
+		let shift = (index % indicies_per_word) * self.bits;
+		let mask = ((1u64 << self.bits) - 1) << shift;
+		*word = (*word & !mask) | ((palette_index as u64) << shift);
+	}
+
+	fn get(&self, index: usize) -> &Block {
+		&self.palette[self.get_index(index)]
+	}
+
+	fn set(&mut self, index: usize, block: Block) {
+		let palette_index = match self.palette.iter().position(|elem| *elem == block) {
+			Some(palette_index) => palette_index,
+			None => {
+				if self.palette.len() == 1 << self.bits {
+					// try to make room by removing unused blocks before making the indicies bigger
+					self.compact();
+				}
+
+				self.palette.push(block);
+				if self.palette.len() > 1 << self.bits {
+					self.resize(Self::bits_needed(self.palette.len()));
+				}
+				self.palette.len() - 1
+			},
+		};
+
+		self.set_index(index, palette_index);
+	}
+
+	// changes the number of bits used for each index
+	fn resize(&mut self, bits: usize) {
+		let mut new_storage = Self::new(Vec::new(), bits);
+		for i in 0..CHUNK_VOLUME {
+			new_storage.set_index(i, self.get_index(i));
+		}
+
+		self.bits = new_storage.bits;
+		self.data = new_storage.data;
+	}
+
+	// removes all blocks from the palette which are not used anymore
+	fn compact(&mut self) {
+		let mut used = vec![false; self.palette.len()];
+		for i in 0..CHUNK_VOLUME {
+			used[self.get_index(i)] = true;
+		}
+
+		if used.iter().all(|used| *used) {
+			return;
+		}
+
+		// maps old palette indicies to new ones
+		let mut remap = vec![0; self.palette.len()];
+		let mut new_palette = Vec::new();
+		for (i, block) in mem::take(&mut self.palette).into_iter().enumerate() {
+			if used[i] {
+				remap[i] = new_palette.len();
+				new_palette.push(block);
+			}
+		}
+
+		for i in 0..CHUNK_VOLUME {
+			let palette_index = self.get_index(i);
+			self.set_index(i, remap[palette_index]);
+		}
+		self.palette = new_palette;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::game::block::{Air, BlockIdMap, BlockState};
+
+	// every block in the registry in every valid state, so there are enough different blocks to grow the palette
+	fn distinct_blocks() -> Vec<Block> {
+		let block_ids = BlockIdMap::from_registry();
+		let mut blocks = Vec::new();
+		for id in 0..block_ids.names().len() {
+			for state in 0..=u8::MAX {
+				if let Some(block) = block_ids.block(id as u16, BlockState::from_bits(state)) {
+					if !blocks.contains(&block) {
+						blocks.push(block);
+					}
+				}
+			}
+		}
+		blocks
+	}
+
+	fn check(storage: &BlockStorage, expected: &[Block]) {
+		for (i, block) in expected.iter().enumerate() {
+			assert!(storage.get(i) == block, "wrong block at index {}", i);
+		}
+	}
+
+	fn as_paletted(storage: &BlockStorage) -> &PalettedStorage {
+		match storage {
+			BlockStorage::Paletted(storage) => storage,
+			BlockStorage::Uniform(_) => panic!("storage is uniform"),
+		}
+	}
+
+	#[test]
+	fn bits_needed() {
+		let cases = [(1, 1), (2, 1), (3, 2), (4, 2), (5, 4), (16, 4), (17, 8), (256, 8), (257, 16), (1 << 16, 16)];
+		for (palette_len, bits) in cases {
+			assert_eq!(PalettedStorage::bits_needed(palette_len), bits, "palette of {} blocks", palette_len);
+		}
+	}
+
+	#[test]
+	#[should_panic]
+	fn too_many_blocks() {
+		PalettedStorage::bits_needed((1 << 16) + 1);
+	}
+
+	#[test]
+	fn from_fn() {
+		let air = Block::from(Air::new());
+		assert!(matches!(BlockStorage::from_fn(|_| air.clone()), BlockStorage::Uniform(_)));
+
+		let blocks = distinct_blocks();
+		let expected = (0..CHUNK_VOLUME).map(|i| blocks[(i * 7) % 5].clone()).collect::<Vec<_>>();
+		let storage = BlockStorage::from_fn(|i| expected[i].clone());
+		check(&storage, &expected);
+		assert_eq!(as_paletted(&storage).bits, 4);
+	}
+
+	// sets blocks one at a time so the storage goes from uniform through every index size it can reach
+	#[test]
+	fn set_grows_palette() {
+		let blocks = distinct_blocks();
+		assert!(blocks.len() > 16, "not enough blocks to test 8 bit indicies");
+
+		let mut expected = vec![blocks[0].clone(); CHUNK_VOLUME];
+		let mut storage = BlockStorage::from_fn(|_| blocks[0].clone());
+
+		// setting the block that is already there keeps the storage uniform
+		storage.set(10, blocks[0].clone());
+		assert!(matches!(storage, BlockStorage::Uniform(_)));
+
+		for (n, block) in blocks.iter().enumerate().skip(1) {
+			// spread each block over positions in different words
+			for i in (n..CHUNK_VOLUME).step_by(331 + n) {
+				storage.set(i, block.clone());
+				expected[i] = block.clone();
+			}
+
+			let paletted = as_paletted(&storage);
+			assert_eq!(paletted.palette.len(), n + 1);
+			assert_eq!(paletted.bits, PalettedStorage::bits_needed(n + 1));
+			check(&storage, &expected);
+		}
+	}
+
+	// when the palette is full, blocks that aren't used anymore are removed before the indicies get bigger
+	#[test]
+	fn compact_before_resize() {
+		let blocks = distinct_blocks();
+		let mut expected = (0..CHUNK_VOLUME).map(|i| blocks[i % 4].clone()).collect::<Vec<_>>();
+		let mut storage = BlockStorage::from_fn(|i| expected[i].clone());
+		assert_eq!(as_paletted(&storage).bits, 2);
+
+		// nothing uses blocks 1 or 2 anymore
+		for (i, block) in expected.iter_mut().enumerate() {
+			if i % 4 == 1 || i % 4 == 2 {
+				storage.set(i, blocks[0].clone());
+				*block = blocks[0].clone();
+			}
+		}
+
+		storage.set(1, blocks[4].clone());
+		expected[1] = blocks[4].clone();
+		let paletted = as_paletted(&storage);
+		assert_eq!(paletted.bits, 2);
+		assert_eq!(paletted.palette.len(), 3);
+		check(&storage, &expected);
+
+		// there is no room left, so this one has to resize
+		storage.set(2, blocks[5].clone());
+		expected[2] = blocks[5].clone();
+		storage.set(5, blocks[6].clone());
+		expected[5] = blocks[6].clone();
+		assert_eq!(as_paletted(&storage).bits, 4);
+		check(&storage, &expected);
+	}
+}
@@ -1,21 +1,91 @@
+use std::mem;
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use array_init::array_init;
+use anyhow::{Result, bail};
 use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use super::block::{Block, BlockTrait, BlockFaceMesh, BlockFace, OcclusionCorners};
-use super::entity::Entity;
+use super::block::{Block, BlockId, BlockState, BlockTrait, BlockFaceMesh, BlockFace, BlockGeometry, ShapeBox, OcclusionCorners, Air};
+use super::block_storage::{BlockStorage, block_index, CHUNK_VOLUME};
+use super::connectivity::ChunkConnectivity;
+use super::light::{LightLevel, ChunkLightData, empty_chunk_light};
+use super::entity::{Entity, SavedEntity, load_entity};
 use super::world::World;
 use crate::prelude::*;
 
 pub const CHUNK_SIZE: usize = 32;
+// number of bytes used by the saved format of a chunk,
+// which is a little endian saved block id followed by the state for each block
+const CHUNK_DATA_SIZE: usize = CHUNK_VOLUME * 3;
+
+// a set of layers of a chunk mesh, with 1 bit for every layer of every face
+// layer n of a face is made of the faces of the blocks with a coordinate of n on the axis of the face
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MeshLayers([u32; 6]);
+
+// each face needs 1 bit for every layer
+const _: () = assert!(CHUNK_SIZE <= u32::BITS as usize);
+
+impl MeshLayers {
+	pub const ALL: Self = Self([u32::MAX; 6]);
+
+	pub fn insert(&mut self, face: BlockFace, layer: usize) {
+		self.0[Into::<usize>::into(face)] |= 1 << layer;
+	}
+
+	pub fn contains(&self, face: BlockFace, layer: usize) -> bool {
+		self.0[Into::<usize>::into(face)] & (1 << layer) != 0
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.0.iter().all(|layers| *layers == 0)
+	}
+
+	// adds all the layers in other
+	pub fn union(&mut self, other: Self) {
+		for (layers, other_layers) in self.0.iter_mut().zip(other.0) {
+			*layers |= other_layers;
+		}
+	}
+}
+
+// the faces of a chunk or a layer of a chunk, split by which render pass draws them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChunkFaces {
+	pub opaque: Vec<BlockFaceMesh>,
+	// faces of translucent blocks, which are drawn after the opaque faces and blended with what is behind them
+	pub translucent: Vec<BlockFaceMesh>,
+}
+
+impl ChunkFaces {
+	fn clear(&mut self) {
+		self.opaque.clear();
+		self.translucent.clear();
+	}
+
+	// the list the faces of the block go in
+	fn faces_for(&mut self, block: &Block) -> &mut Vec<BlockFaceMesh> {
+		if block.is_translucent() {
+			&mut self.translucent
+		} else {
+			&mut self.opaque
+		}
+	}
+
+	fn extend(&mut self, other: &Self) {
+		self.opaque.extend_from_slice(&other.opaque);
+		self.translucent.extend_from_slice(&other.translucent);
+	}
+}
 
 // says all blocks that have been visited for the greedy meshing algorithm in a given layer
 pub struct VisitedBlockMap {
 	visited_blocks: Box<[[bool; CHUNK_SIZE]; CHUNK_SIZE]>,
 	vertex_occlusion: Box<[[u8; CHUNK_SIZE + 1]; CHUNK_SIZE + 1]>,
+	// the light of the block in front of each face, faces are only merged if they have the same light
+	face_light: Box<[[LightLevel; CHUNK_SIZE]; CHUNK_SIZE]>,
 	face: BlockFace,
 	coord3: i64,
 }
@@ -27,6 +97,7 @@ impl VisitedBlockMap {
 		VisitedBlockMap {
 			visited_blocks: Box::new([[false; CHUNK_SIZE]; CHUNK_SIZE]),
 			vertex_occlusion: Box::new([[0; CHUNK_SIZE + 1]; CHUNK_SIZE + 1]),
+			face_light: Box::new([[LightLevel::default(); CHUNK_SIZE]; CHUNK_SIZE]),
 			face: BlockFace::XPos,
 			coord3: 0,
 		}
@@ -82,6 +153,16 @@ impl VisitedBlockMap {
 			&& self.vertex_occlusion[x1 + 1][y1 + 1] == self.vertex_occlusion[x2 + 1][y2 + 1]
 	}
 
+	fn set_face_light(&mut self, position: BlockPos, light: LightLevel) {
+		let (x, y) = self.get_index(position);
+		self.face_light[x][y] = light;
+	}
+
+	fn get_face_light(&self, position: BlockPos) -> LightLevel {
+		let (x, y) = self.get_index(position);
+		self.face_light[x][y]
+	}
+
 	fn get_occlusion_data(&self, block: BlockPos) -> OcclusionCorners {
 		let (x, y) = self.get_index(block);
 		OcclusionCorners {
@@ -92,16 +173,33 @@ impl VisitedBlockMap {
 		}
 	}
 
+	// occlusion level of a point on the layer, which is interpolated between the corners of the blocks
+	fn occlusion_at(&self, position: Position) -> u8 {
+		let (x, y) = match self.face {
+			BlockFace::XPos | BlockFace::XNeg => (position.y, position.z),
+			BlockFace::YPos | BlockFace::YNeg => (position.x, position.z),
+			BlockFace::ZPos | BlockFace::ZNeg => (position.x, position.y),
+		};
+
+		let cell_x = (x.floor() as usize).min(CHUNK_SIZE - 1);
+		let cell_y = (y.floor() as usize).min(CHUNK_SIZE - 1);
+		let x = x - cell_x as f64;
+		let y = y - cell_y as f64;
+		let level = |x_offset: usize, y_offset: usize| self.vertex_occlusion[cell_x + x_offset][cell_y + y_offset] as f64;
+
+		let bottom = level(0, 0) * (1.0 - x) + level(1, 0) * x;
+		let top = level(0, 1) * (1.0 - x) + level(1, 1) * x;
+		(bottom * (1.0 - y) + top * y).round() as u8
+	}
+
 	fn set_face_coord(&mut self, face: BlockFace, coord3: i64) {
 		self.face = face;
 		self.coord3 = coord3;
 	}
 }
 
-type BlockArray = Box<[[[Block; CHUNK_SIZE]; CHUNK_SIZE]; CHUNK_SIZE]>;
-
 pub struct ChunkBlockRef<'a> {
-	_block_lock: RwLockReadGuard<'a, BlockArray>,
+	_block_lock: RwLockReadGuard<'a, BlockStorage>,
 	block: *const Block,
 }
 
@@ -114,69 +212,134 @@ impl Deref for ChunkBlockRef<'_> {
 	}
 }
 
+// blocks in the palette are shared by many positions, so this holds a copy of the block
+// which is written back to the chunk when it is dropped
 pub struct ChunkBlockRefMut<'a> {
-	_block_lock: RwLockWriteGuard<'a, BlockArray>,
-	block: *mut Block,
+	block_lock: RwLockWriteGuard<'a, BlockStorage>,
+	index: usize,
+	block: Block,
 }
 
 impl Deref for ChunkBlockRefMut<'_> {
 	type Target = Block;
 
 	fn deref(&self) -> &Self::Target {
-		// safety: write lock will ensure this block is still alive
-		unsafe { self.block.as_ref().unwrap() }
+		&self.block
 	}
 }
 
 impl DerefMut for ChunkBlockRefMut<'_> {
 	fn deref_mut(&mut self) -> &mut Self::Target {
-		// safety: write lock will ensure this block is still alive
-		unsafe { self.block.as_mut().unwrap() }
+		&mut self.block
+	}
+}
+
+impl Drop for ChunkBlockRefMut<'_> {
+	fn drop(&mut self) {
+		let block = mem::replace(&mut self.block, Air::new().into());
+		self.block_lock.set(self.index, block);
 	}
 }
 
 pub struct Chunk {
 	world: Arc<World>,
-	// position of back bottom left corner of chunk in block coordinates
-	// increases in incraments of 32
-	position: Position,
 	// coordinates of chunk, increases in incraments of 1
 	chunk_position: ChunkPos,
-	// coordinates of bottom left back block in world space
-	block_position: BlockPos,
-	// store them on heap to avoid stack overflow
-	blocks: RwLock<BlockArray>,
+	blocks: RwLock<BlockStorage>,
+	// the light of every block, which is set by light updates in the world
+	light: RwLock<Box<[LightLevel]>>,
 	//chunk_mesh: HashMap<BlockPos, Vec<BlockFaceMesh>>,
-	chunk_mesh: RwLock<Box<[[Vec<BlockFaceMesh>; CHUNK_SIZE]; 6]>>,
+	chunk_mesh: RwLock<Box<[[ChunkFaces; CHUNK_SIZE]; 6]>>,
+	// set when part of the mesh is regenerated, so the client knows to upload it again
+	mesh_changed: AtomicBool,
+	// which faces of the chunk can see each other, this is updated when the chunk is meshed
+	connectivity: RwLock<ChunkConnectivity>,
+	// set when a block changes, so the connectivity is recalculated the next time the chunk is meshed
+	connectivity_outdated: AtomicBool,
+	// set when a block is modified, so we know the chunk has to be saved
+	dirty: AtomicBool,
 }
 
 impl Chunk {
 	pub fn new<F: FnMut(BlockPos) -> Block>(world: Arc<World>, position: ChunkPos, mut block_fn: F) -> Self {
 		let block_position = position * CHUNK_SIZE as i64;
 
-		let blocks = Box::new(array_init(|x| {
-			array_init(|y| {
-				array_init(|z| {
-					let block = BlockPos::new(x as i64, y as i64, z as i64) + block_position;
-					block_fn(block)
-				})
-			})
-		}));
+		let blocks = BlockStorage::from_fn(|index| {
+			let x = index / (CHUNK_SIZE * CHUNK_SIZE);
+			let y = (index / CHUNK_SIZE) % CHUNK_SIZE;
+			let z = index % CHUNK_SIZE;
+			block_fn(BlockPos::new(x as i64, y as i64, z as i64) + block_position)
+		});
 
-		let x = (position.x * CHUNK_SIZE as i64) as f64;
-		let y = (position.y * CHUNK_SIZE as i64) as f64;
-		let z = (position.z * CHUNK_SIZE as i64) as f64;
-		
 		Self {
 			world,
-			position: Position::new(x, y, z),
 			chunk_position: position,
-			block_position,
 			blocks: RwLock::new(blocks),
-			chunk_mesh: RwLock::new(Box::new(array_init(|_| array_init(|_| Vec::new())))),
+			light: RwLock::new(empty_chunk_light()),
+			chunk_mesh: RwLock::new(Box::new(array_init(|_| array_init(|_| ChunkFaces::default())))),
+			mesh_changed: AtomicBool::new(false),
+			connectivity: RwLock::new(ChunkConnectivity::ALL),
+			connectivity_outdated: AtomicBool::new(true),
+			dirty: AtomicBool::new(false),
 		}
 	}
 
+	// loads a chunk from data that was created by to_bytes
+	pub fn from_bytes(world: Arc<World>, position: ChunkPos, data: &[u8]) -> Result<Self> {
+		if data.len() != CHUNK_DATA_SIZE {
+			bail!("chunk data for chunk {:?} is {} bytes, expected {} bytes", position, data.len(), CHUNK_DATA_SIZE);
+		}
+
+		// check all the block ids before creating the chunk, since the block function can't fail
+		let block_ids = world.block_ids();
+		let mut blocks = Vec::with_capacity(CHUNK_VOLUME);
+		for bytes in data.chunks_exact(3) {
+			let id = BlockId::from_le_bytes([bytes[0], bytes[1]]);
+			match block_ids.block(id, BlockState::from_bits(bytes[2])) {
+				Some(block) => blocks.push(block),
+				None => bail!("chunk data for chunk {:?} contains invalid block id {} with state {}", position, id, bytes[2]),
+			}
+		}
+
+		let block_position = position * CHUNK_SIZE as i64;
+
+		Ok(Self::new(world, position, |block| {
+			let (x, y, z) = (block - block_position).as_indicies().unwrap();
+			blocks[block_index(x, y, z)].clone()
+		}))
+	}
+
+	// serializes all the blocks in the chunk, using the saved block ids of the world
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let block_ids = self.world.block_ids();
+		let blocks = self.blocks.read();
+
+		let mut data = Vec::with_capacity(CHUNK_DATA_SIZE);
+		for index in 0..CHUNK_VOLUME {
+			let block = blocks.get(index);
+			data.extend(block_ids.saved_id(block).to_le_bytes());
+			data.push(block.state().bits());
+		}
+		data
+	}
+
+	pub fn chunk_position(&self) -> ChunkPos {
+		self.chunk_position
+	}
+
+	pub fn is_dirty(&self) -> bool {
+		self.dirty.load(Ordering::Acquire)
+	}
+
+	pub fn set_dirty(&self) {
+		self.dirty.store(true, Ordering::Release);
+	}
+
+	// returns if the chunk was dirty
+	pub fn clear_dirty(&self) -> bool {
+		self.dirty.swap(false, Ordering::AcqRel)
+	}
+
 	// calls the function on the given block position
 	// the block may be from another chunk
 	#[inline]
@@ -211,12 +374,12 @@ impl Chunk {
 	}*/
 
 	#[inline]
-	pub fn get_block(&self, block: BlockPos) -> ChunkBlockRef {
+	pub fn get_block(&self, block: BlockPos) -> ChunkBlockRef<'_> {
 		assert!(block.is_chunk_local());
 		let (x, y, z) = block.as_indicies().unwrap();
 
 		let block_lock = self.blocks.read();
-		let block = &block_lock[x][y][z] as *const Block;
+		let block = block_lock.get(block_index(x, y, z)) as *const Block;
 		ChunkBlockRef {
 			_block_lock: block_lock,
 			block,
@@ -224,14 +387,18 @@ impl Chunk {
 	}
 
 	#[inline]
-	pub fn get_block_mut(&self, block: BlockPos) -> ChunkBlockRefMut {
+	pub fn get_block_mut(&self, block: BlockPos) -> ChunkBlockRefMut<'_> {
 		assert!(block.is_chunk_local());
 		let (x, y, z) = block.as_indicies().unwrap();
 
-		let mut block_lock = self.blocks.write();
-		let block = &mut block_lock[x][y][z] as *mut Block;
+		let block_lock = self.blocks.write();
+		self.set_dirty();
+		self.connectivity_outdated.store(true, Ordering::Release);
+		let index = block_index(x, y, z);
+		let block = block_lock.get(index).clone();
 		ChunkBlockRefMut {
-			_block_lock: block_lock,
+			block_lock,
+			index,
 			block,
 		}
 	}
@@ -241,7 +408,63 @@ impl Chunk {
 		assert!(block_pos.is_chunk_local());
 		let (x, y, z) = block_pos.as_indicies().unwrap();
 
-		self.blocks.write()[x][y][z] = block;
+		self.blocks.write().set(block_index(x, y, z), block);
+		self.set_dirty();
+		self.connectivity_outdated.store(true, Ordering::Release);
+	}
+
+	#[inline]
+	pub fn get_light(&self, block: BlockPos) -> LightLevel {
+		assert!(block.is_chunk_local());
+		let (x, y, z) = block.as_indicies().unwrap();
+		self.light.read()[block_index(x, y, z)]
+	}
+
+	// gets the light at the given block position, which may be in another chunk
+	#[inline]
+	fn light_at(&self, block: BlockPos) -> Option<LightLevel> {
+		if block.is_chunk_local() {
+			Some(self.get_light(block))
+		} else {
+			let chunk_position = block.as_chunk_pos() + self.chunk_position;
+			Some(self.world.chunks.get(&chunk_position)?.chunk.get_light(block.as_chunk_local()))
+		}
+	}
+
+	pub fn set_light(&self, light: Box<[LightLevel]>) {
+		assert_eq!(light.len(), CHUNK_VOLUME);
+		*self.light.write() = light;
+	}
+
+	// if light can go into a block, and the light the block emits
+	pub fn light_properties(&self, block: BlockPos) -> (bool, u8) {
+		let block = self.get_block(block);
+		(!block.is_opaque_cube(), block.light_emission())
+	}
+
+	// copies the light and how every block affects light, for a light update
+	pub fn light_data(&self) -> ChunkLightData {
+		let light = self.light.read().clone();
+
+		let blocks = self.blocks.read();
+		let (transparent, emission) = match &*blocks {
+			BlockStorage::Uniform(block) => (
+				vec![!block.is_opaque_cube(); CHUNK_VOLUME],
+				vec![block.light_emission(); CHUNK_VOLUME],
+			),
+			blocks => (0..CHUNK_VOLUME)
+				.map(|index| {
+					let block = blocks.get(index);
+					(!block.is_opaque_cube(), block.light_emission())
+				})
+				.unzip(),
+		};
+
+		ChunkLightData {
+			light,
+			transparent: transparent.into_boxed_slice(),
+			emission: emission.into_boxed_slice(),
+		}
 	}
 
 	// the visit map is passed in seperately to avoid having to reallocat the memory for the visit map every time	
@@ -252,15 +475,19 @@ impl Chunk {
 
 		let face_offset = face.block_pos_offset();
 
-		// discard all block faces that are not visible and all faces on an air block
+		// discard all block faces that are not visible and all faces on an air block or a block without textures
+		// blocks that aren't cubes are also skipped, since they are meshed seperately below
 		for x in 0..CHUNK_SIZE as i64 {
 			for y in 0..CHUNK_SIZE as i64 {
 				let block_pos = visit_map.get_block_pos(x, y);
+				let block = (*self.get_block(block_pos)).clone();
 
-				if self.get_block(block_pos).is_air() {
+				if block.is_air() || !block.geometry().is_cube() || block.face_texture(face).is_none() {
 					visit_map.set_visited(block_pos, true);
-				} else if let Some(is_translucent) = self.with_block(block_pos + face_offset, |block| block.is_translucent()) {
-					visit_map.set_visited(block_pos, !is_translucent);
+				} else if let Some(is_hidden) = self.with_block(block_pos + face_offset, |neighbor| hides_face(&block, neighbor, face, &ShapeBox::FULL)) {
+					visit_map.set_visited(block_pos, is_hidden);
+					// faces are lit by the light in front of them, since the inside of an opaque block is always dark
+					visit_map.set_face_light(block_pos, self.light_at(block_pos + face_offset).unwrap_or_default());
 				} else {
 					// there is no adjacent chunk, don't do this mesh
 					visit_map.set_visited(block_pos, true);
@@ -269,12 +496,8 @@ impl Chunk {
 		}
 
 		let is_occluded_by = |block_pos| {
-			if let Some(is_translucent) = self.with_block(block_pos + face_offset, |block| block.is_translucent()) {
-				if is_translucent {
-					0
-				} else {
-					1
-				}
+			if let Some(true) = self.with_block(block_pos + face_offset, |block| block.is_opaque_cube()) {
+				1
 			} else {
 				0
 			}
@@ -306,7 +529,6 @@ impl Chunk {
 				}
 
 				let block = self.get_block(block_pos);
-				let block_type = block.block_type();
 	
 				// width and height of the greedy mesh region
 				let mut width = 1;
@@ -320,8 +542,9 @@ impl Chunk {
 					}
 	
 					if !visit_map.is_visited(current_block_pos)
-						&& self.get_block(current_block_pos).block_type() == block_type
-						&& visit_map.occlusion_level_matches(block_pos, current_block_pos) {
+						&& *self.get_block(current_block_pos) == *block
+						&& visit_map.occlusion_level_matches(block_pos, current_block_pos)
+						&& visit_map.get_face_light(current_block_pos) == visit_map.get_face_light(block_pos) {
 						visit_map.set_visited(current_block_pos, true);
 						width += 1;
 					} else {
@@ -346,8 +569,9 @@ impl Chunk {
 						}
 
 						if visit_map.is_visited(current_block_pos)
-							|| self.get_block(current_block_pos).block_type() != block_type
-							|| !visit_map.occlusion_level_matches(block_pos, current_block_pos) {
+							|| *self.get_block(current_block_pos) != *block
+							|| !visit_map.occlusion_level_matches(block_pos, current_block_pos)
+							|| visit_map.get_face_light(current_block_pos) != visit_map.get_face_light(block_pos) {
 							expandable = false;
 							break;
 						}
@@ -366,42 +590,142 @@ impl Chunk {
 
 				let block_face_mesh = BlockFaceMesh::from_cube_corners(
 					face,
-					block.texture_index().unwrap(),
-					block_pos + self.block_position,
-					visit_map.get_block_pos_offset(block_pos, width - 1, height - 1) + self.block_position,
+					block.face_texture(face).expect("faces without a texture are skipped before meshing"),
+					block_pos,
+					visit_map.get_block_pos_offset(block_pos, width - 1, height - 1),
 					visit_map.get_occlusion_data(block_pos),
+					visit_map.get_face_light(block_pos),
 				);
 	
-				chunk_mesh[Into::<usize>::into(face)][index].push(block_face_mesh);
+				chunk_mesh[Into::<usize>::into(face)][index].faces_for(&block).push(block_face_mesh);
+			}
+		}
+
+		// blocks that aren't cubes have each of their faces added on their own
+		for x in 0..CHUNK_SIZE as i64 {
+			for y in 0..CHUNK_SIZE as i64 {
+				let block_pos = visit_map.get_block_pos(x, y);
+				let block = (*self.get_block(block_pos)).clone();
+				// light goes into blocks which aren't cubes, so faces inside of the block space use the block's own light
+				let light = self.get_light(block_pos);
+
+				match block.geometry() {
+					BlockGeometry::Cube => (),
+					BlockGeometry::Boxes { boxes, .. } => {
+						let texture = match block.face_texture(face) {
+							Some(texture) => texture,
+							None => continue,
+						};
+
+						for shape_box in boxes.iter() {
+							// faces on the side of the block can be hidden by the block next to them,
+							// and use the same ambient occlusion as cube faces in the layer
+							let on_side = shape_box.touches_face(face);
+							if on_side && self.with_block(block_pos + face_offset, |neighbor| hides_face(&block, neighbor, face, shape_box)) != Some(false) {
+								continue;
+							}
+
+							let face_light = if on_side {
+								self.light_at(block_pos + face_offset).unwrap_or(light)
+							} else {
+								light
+							};
+
+							let block_position = block_pos.as_position();
+							let block_face_mesh = BlockFaceMesh::from_box(
+								face,
+								texture,
+								block_position + shape_box.min_offset(),
+								block_position + shape_box.max_offset(),
+								|position| if on_side { visit_map.occlusion_at(position) } else { 0 },
+								face_light,
+							);
+
+							chunk_mesh[Into::<usize>::into(face)][index].faces_for(&block).push(block_face_mesh);
+						}
+					},
+					// the crossed planes are all put in the top face layer of the block
+					BlockGeometry::Cross => if face == BlockFace::YPos {
+						if let Some(texture) = block.face_texture(BlockFace::ZPos) {
+							chunk_mesh[Into::<usize>::into(face)][index].faces_for(&block).extend(BlockFaceMesh::cross(texture, block_pos, light));
+						}
+					},
+				}
 			}
 		}
+
+		// this is set while the mesh is still locked, so anyone who sees it will wait for the new mesh
+		self.mesh_changed.store(true, Ordering::Release);
 	}
 
-	// updates the mesh for the entire chunk
-	pub fn chunk_mesh_update(&self) {
+	// updates the mesh for the given layers of the chunk
+	pub fn mesh_update_layers(&self, layers: MeshLayers) {
+		// this is done first so it is up to date by the time the client sees the mesh has changed
+		if self.connectivity_outdated.swap(false, Ordering::AcqRel) {
+			let connectivity = match &*self.blocks.read() {
+				BlockStorage::Uniform(block) if !block.is_opaque_cube() => ChunkConnectivity::ALL,
+				BlockStorage::Uniform(_) => ChunkConnectivity::NONE,
+				blocks => ChunkConnectivity::from_fn(|index| !blocks.get(index).is_opaque_cube()),
+			};
+			*self.connectivity.write() = connectivity;
+		}
+
 		let mut visit_map = VisitedBlockMap::new();
 
 		for face in BlockFace::iter() {
 			for i in 0..CHUNK_SIZE {
-				self.mesh_update_inner(face, i, &mut visit_map);
+				if layers.contains(face, i) {
+					self.mesh_update_inner(face, i, &mut visit_map);
+				}
 			}
 		}
 	}
 
+	// updates the mesh for the entire chunk
+	pub fn chunk_mesh_update(&self) {
+		self.mesh_update_layers(MeshLayers::ALL);
+	}
+
 	// returns None if the mesh is currently locked, which means it is being generated,
 	// so we wouldn't have to display it anywats
-	pub fn get_chunk_mesh(&self) -> Option<Vec<BlockFaceMesh>> {
-		Some(self.chunk_mesh.try_read()?.iter()
-			.flatten()
-			.flatten()
-			.copied()
-			.collect::<Vec<_>>())
+	pub fn get_chunk_mesh(&self) -> Option<ChunkFaces> {
+		let chunk_mesh = self.chunk_mesh.try_read()?;
+
+		let mut out = ChunkFaces::default();
+		for layer in chunk_mesh.iter().flatten() {
+			out.extend(layer);
+		}
+		Some(out)
+	}
+
+	pub fn connectivity(&self) -> ChunkConnectivity {
+		*self.connectivity.read()
+	}
+
+	// returns the mesh if it has changed since the last time this was called
+	// if the mesh is locked it is being changed, and it will be returned the next time this is called after that
+	pub fn take_changed_mesh(&self) -> Option<ChunkFaces> {
+		if self.mesh_changed.swap(false, Ordering::AcqRel) {
+			self.get_chunk_mesh()
+		} else {
+			None
+		}
 	}
 }
 
+// if the face of a block is hidden by the neighbor in front of it
+// the box is the part of the block the face belongs to, and only the part of the face it covers has to be hidden
+//
+// translucent blocks only hide the faces of blocks of the same type, so the inside of a body of water isn't drawn,
+// but the faces between different translucent blocks are
+fn hides_face(block: &Block, neighbor: &Block, face: BlockFace, shape_box: &ShapeBox) -> bool {
+	let hides_block = !neighbor.is_translucent() || (block.is_translucent() && neighbor.id() == block.id());
+	hides_block && neighbor.geometry().covers(face.opposite(), shape_box)
+}
+
+// a chunk stays loaded as long as it is in the load area of at least 1 player
 pub struct LoadedChunk {
 	pub chunk: Chunk,
-	pub load_count: AtomicU64,
 }
 
 impl LoadedChunk {
@@ -409,26 +733,58 @@ impl LoadedChunk {
 		//let chunk_mesh = chunk.generate_block_faces();
 		LoadedChunk {
 			chunk,
-			load_count: AtomicU64::new(0),
 		}
 	}
-
-	pub fn inc_load_count(&self) {
-		self.load_count.fetch_add(1, Ordering::AcqRel);
-	}
-
-	pub fn dec_load_count(&self) -> u64 {
-		self.load_count.fetch_sub(1, Ordering::AcqRel) - 1
-	}
-
-	pub fn get_load_count(&self) -> u64 {
-		self.load_count.load(Ordering::Acquire)
-	}
 }
 
 // the entire saved state of the chunk, which is all blocks and entities
 // TODO: maybe save chunk mesh to load faster
 pub struct ChunkData {
-	chunk: Chunk,
-	entities: Vec<Box<dyn Entity>>,
+	pub chunk: Chunk,
+	pub entities: Vec<Box<dyn Entity>>,
+}
+
+impl ChunkData {
+	// loads a chunk saved by ChunkData::to_bytes
+	// chunks saved before entities were saved are just the blocks, so they load with no entities
+	pub fn from_bytes(world: Arc<World>, position: ChunkPos, data: &[u8]) -> Result<Self> {
+		if data.len() < CHUNK_DATA_SIZE {
+			bail!("chunk data for chunk {:?} is {} bytes, expected at least {} bytes", position, data.len(), CHUNK_DATA_SIZE);
+		}
+		let (blocks, entities) = data.split_at(CHUNK_DATA_SIZE);
+
+		// entities that can't be loaded are left out, since failing would regenerate the chunk and lose its blocks
+		let saved_entities = if entities.is_empty() {
+			Vec::new()
+		} else {
+			bincode::deserialize::<Vec<SavedEntity>>(entities).unwrap_or_else(|error| {
+				warn!("entities in chunk {:?} are invalid, the chunk is loaded without them: {}", position, error);
+				Vec::new()
+			})
+		};
+
+		let entities = saved_entities.iter()
+			.filter_map(|saved| match load_entity(&world, saved) {
+				Ok(entity) => Some(entity),
+				Err(error) => {
+					warn!("could not load entity in chunk {:?}, removing it: {}", position, error);
+					None
+				},
+			})
+			.collect();
+
+		Ok(Self {
+			chunk: Chunk::from_bytes(world, position, blocks)?,
+			entities,
+		})
+	}
+
+	// the saved format is the blocks of the chunk, followed by the bincode encoded entities if there are any
+	pub fn to_bytes(chunk: &Chunk, entities: &[SavedEntity]) -> Result<Vec<u8>> {
+		let mut data = chunk.to_bytes();
+		if !entities.is_empty() {
+			data.extend(bincode::serialize(entities)?);
+		}
+		Ok(data)
+	}
 }
@@ -1,7 +1,7 @@
 use std::time::Duration;
 
 use winit::event::*;
-use nalgebra::{Unit, Matrix, Vector4};
+use nalgebra::{Unit, Matrix, Vector3, Vector4};
 
 use crate::render::camera::Camera;
 
@@ -23,6 +23,9 @@ pub struct CameraController {
 	rotate_left_pressed: bool,
 	rotate_right_pressed: bool,
 	sprint_pressed: bool,
+	fly_toggle_pressed: bool,
+	// the camera passes through everything when flying, otherwise the player walks and collides with blocks
+	flying: bool,
 }
 
 impl CameraController {
@@ -42,6 +45,8 @@ impl CameraController {
 			rotate_left_pressed: false,
 			rotate_right_pressed: false,
 			sprint_pressed: false,
+			fly_toggle_pressed: false,
+			flying: true,
 		}
 	}
 
@@ -101,6 +106,14 @@ impl CameraController {
 						self.sprint_pressed = is_pressed;
 						true
 					},
+					VirtualKeyCode::F => {
+						// holding the key down repeats the event, which shouldn't toggle flying again
+						if is_pressed && !self.fly_toggle_pressed {
+							self.flying = !self.flying;
+						}
+						self.fly_toggle_pressed = is_pressed;
+						true
+					},
 					_ => false,
 				}
 			}
@@ -108,7 +121,57 @@ impl CameraController {
 		}
 	}
 
-	pub fn update_camera(&self, camera: &mut Camera, time_delta: Duration) {
+	pub fn is_flying(&self) -> bool {
+		self.flying
+	}
+
+	// true while the player is trying to jump, which only happens when walking
+	pub fn jump_pressed(&self) -> bool {
+		!self.flying && self.up_pressed
+	}
+
+	fn current_speed(&self) -> f32 {
+		if self.sprint_pressed {
+			self.fast_speed
+		} else {
+			self.speed
+		}
+	}
+
+	// the velocity the player is trying to walk at, in meters / second
+	// this is always horizontal, so looking up or down doesn't change how fast the player walks
+	pub fn walk_velocity(&self, camera: &Camera) -> Vector3<f64> {
+		let forward = camera.look_at - camera.position;
+		let forward = Vector3::new(forward.x as f64, 0.0, forward.z as f64);
+		if forward.norm_squared() == 0.0 {
+			return Vector3::zeros();
+		}
+		let forward = forward.normalize();
+		let right = forward.cross(&Vector3::y());
+
+		let mut direction = Vector3::zeros();
+		if self.forward_pressed {
+			direction += forward;
+		}
+		if self.backward_pressed {
+			direction -= forward;
+		}
+		if self.left_pressed {
+			direction -= right;
+		}
+		if self.right_pressed {
+			direction += right;
+		}
+
+		if direction.norm_squared() == 0.0 {
+			direction
+		} else {
+			direction.normalize() * self.current_speed() as f64
+		}
+	}
+
+	// the velocity the camera moves at while flying, in meters / second
+	pub fn fly_velocity(&self, camera: &Camera) -> Vector3<f64> {
 		let forward = camera.look_at - camera.position;
 		let up = camera.up;
 		// sideways is pointing right
@@ -120,33 +183,34 @@ impl CameraController {
 		let right_norm = right.normalize();
 		let camera_up_norm = camera_up.normalize();
 
-
-		let distance_moved = time_delta.as_millis() as f32 * 
-			if self.sprint_pressed {
-				self.fast_speed
-			} else {
-				self.speed
-			} / 1000.0;
-
+		let mut velocity = Vector3::zeros();
 		if self.forward_pressed {
-			camera.position += forward_norm * distance_moved;
+			velocity += forward_norm;
 		}
 		if self.backward_pressed {
-			camera.position -= forward_norm * distance_moved;
+			velocity -= forward_norm;
 		}
 		if self.left_pressed {
-			camera.position -= right_norm * distance_moved;
+			velocity -= right_norm;
 		}
 		if self.right_pressed {
-			camera.position += right_norm * distance_moved;
+			velocity += right_norm;
 		}
 		if self.up_pressed {
-			camera.position += camera_up_norm * distance_moved;
+			velocity += camera_up_norm;
 		}
 		if self.down_pressed {
-			camera.position -= camera_up_norm * distance_moved;
+			velocity -= camera_up_norm;
 		}
 
+		(velocity * self.current_speed()).cast::<f64>()
+	}
+
+	// turns the camera without moving it
+	pub fn rotate_camera(&self, camera: &mut Camera, time_delta: Duration) {
+		let forward = camera.look_at - camera.position;
+		let up = camera.up;
+		let right_norm = forward.cross(&up).normalize();
 
 		let angle_rotated = time_delta.as_millis() as f32 * self.rotation_speed / 1000.0;
 
@@ -0,0 +1,272 @@
+use rustc_hash::FxHashMap;
+
+use crate::prelude::*;
+use crate::render::RenderContext;
+use crate::render::model::{Mesh, ChunkOffset};
+use crate::game::block::{BlockFaceMesh, BlockVertex};
+use crate::game::chunk::ChunkFaces;
+use crate::game::connectivity::ChunkConnectivity;
+
+// creates and writes the gpu buffers of chunk meshes
+// the buffers are freed when the values returned are dropped
+pub trait MeshAllocator {
+	type Mesh;
+	type Offset;
+
+	// creates buffers which hold exactly the vertexes and indexes
+	fn create_mesh(&mut self, name: &str, vertexes: &[BlockVertex], indexes: &[u32]) -> Self::Mesh;
+	// writes to the start of the buffers of the mesh, which are big enough for the vertexes and indexes
+	fn write_mesh(&mut self, mesh: &mut Self::Mesh, vertexes: &[BlockVertex], indexes: &[u32]);
+	fn create_offset(&mut self, chunk: ChunkPos) -> Self::Offset;
+}
+
+impl MeshAllocator for RenderContext<'_> {
+	type Mesh = Mesh;
+	type Offset = ChunkOffset;
+
+	fn create_mesh(&mut self, name: &str, vertexes: &[BlockVertex], indexes: &[u32]) -> Mesh {
+		Mesh::new(name, vertexes, indexes, 0, *self)
+	}
+
+	fn write_mesh(&mut self, mesh: &mut Mesh, vertexes: &[BlockVertex], indexes: &[u32]) {
+		mesh.write(vertexes, indexes, *self);
+	}
+
+	fn create_offset(&mut self, chunk: ChunkPos) -> ChunkOffset {
+		ChunkOffset::new(chunk, *self)
+	}
+}
+
+// a mesh and how many vertexes and indexes fit in its buffers, which can be more than it has after it is updated
+struct MeshBuffers<M> {
+	mesh: M,
+	vertex_capacity: usize,
+	index_capacity: usize,
+}
+
+// what the client needs to draw a chunk
+struct ChunkMesh<M, O> {
+	// None if the chunk has no faces
+	mesh: Option<MeshBuffers<M>>,
+	// None if the chunk has no translucent faces
+	translucent_mesh: Option<MeshBuffers<M>>,
+	offset: O,
+	connectivity: ChunkConnectivity,
+}
+
+// converts the faces of a chunk to the vertexes and indexes of its mesh
+fn chunk_mesh_data(faces: &[BlockFaceMesh]) -> (Vec<BlockVertex>, Vec<u32>) {
+	let mut vertexes = Vec::with_capacity(faces.len() * 4);
+	let mut indexes = Vec::with_capacity(faces.len() * 6);
+
+	let mut current_index = 0;
+	for block_face in faces {
+		vertexes.extend(block_face.0);
+		indexes.extend(BlockFaceMesh::indicies().iter().map(|elem| elem + current_index));
+		current_index += 4;
+	}
+
+	(vertexes, indexes)
+}
+
+// uploads the faces to the mesh, or removes the mesh if there are no faces
+// the old buffers are written to if the new mesh fits in them
+fn update_mesh<A: MeshAllocator>(mesh: &mut Option<MeshBuffers<A::Mesh>>, name: &str, faces: &[BlockFaceMesh], allocator: &mut A) {
+	if faces.is_empty() {
+		*mesh = None;
+		return;
+	}
+
+	let (vertexes, indexes) = chunk_mesh_data(faces);
+	match mesh {
+		Some(mesh) if vertexes.len() <= mesh.vertex_capacity && indexes.len() <= mesh.index_capacity => {
+			allocator.write_mesh(&mut mesh.mesh, &vertexes, &indexes);
+		},
+		_ => *mesh = Some(MeshBuffers {
+			mesh: allocator.create_mesh(name, &vertexes, &indexes),
+			vertex_capacity: vertexes.len(),
+			index_capacity: indexes.len(),
+		}),
+	}
+}
+
+// each chunk has its own mesh, so only the chunks that changed have to be uploaded again
+// M and O are the mesh and offset types of the MeshAllocator which is used to update the meshes
+pub struct ChunkMeshes<M, O> {
+	chunks: FxHashMap<ChunkPos, ChunkMesh<M, O>>,
+}
+
+impl<M, O> ChunkMeshes<M, O> {
+	pub fn new() -> Self {
+		Self {
+			chunks: FxHashMap::default(),
+		}
+	}
+
+	// removes the meshes of the chunks which aren't loaded anymore, which frees their buffers
+	pub fn retain_loaded<F: Fn(ChunkPos) -> bool>(&mut self, is_loaded: F) {
+		self.chunks.retain(|position, _| is_loaded(*position));
+	}
+
+	// uploads the new faces of a chunk
+	// chunks with no faces are still kept for their connectivity
+	pub fn update<A: MeshAllocator<Mesh = M, Offset = O>>(&mut self, position: ChunkPos, faces: ChunkFaces, connectivity: ChunkConnectivity, allocator: &mut A) {
+		let chunk_mesh = self.chunks.entry(position).or_insert_with(|| ChunkMesh {
+			mesh: None,
+			translucent_mesh: None,
+			offset: allocator.create_offset(position),
+			connectivity,
+		});
+		chunk_mesh.connectivity = connectivity;
+
+		let ChunkFaces { opaque, translucent } = faces;
+		update_mesh(
+			&mut chunk_mesh.mesh,
+			&format!("chunk {} {} {} mesh", position.x, position.y, position.z),
+			&opaque,
+			allocator,
+		);
+		update_mesh(
+			&mut chunk_mesh.translucent_mesh,
+			&format!("chunk {} {} {} translucent mesh", position.x, position.y, position.z),
+			&translucent,
+			allocator,
+		);
+	}
+
+	pub fn connectivity(&self, chunk: ChunkPos) -> Option<ChunkConnectivity> {
+		Some(self.chunks.get(&chunk)?.connectivity)
+	}
+
+	pub fn mesh(&self, chunk: ChunkPos) -> Option<(&M, &O)> {
+		let chunk_mesh = self.chunks.get(&chunk)?;
+		Some((&chunk_mesh.mesh.as_ref()?.mesh, &chunk_mesh.offset))
+	}
+
+	pub fn translucent_mesh(&self, chunk: ChunkPos) -> Option<(&M, &O)> {
+		let chunk_mesh = self.chunks.get(&chunk)?;
+		Some((&chunk_mesh.translucent_mesh.as_ref()?.mesh, &chunk_mesh.offset))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::rc::Rc;
+	use std::cell::Cell;
+
+	use bytemuck::Zeroable;
+
+	use super::*;
+
+	// counts the buffers that haven't been dropped yet
+	struct TestBuffer {
+		live_buffers: Rc<Cell<usize>>,
+	}
+
+	impl TestBuffer {
+		fn new(live_buffers: &Rc<Cell<usize>>) -> Self {
+			live_buffers.set(live_buffers.get() + 1);
+			Self {
+				live_buffers: live_buffers.clone(),
+			}
+		}
+	}
+
+	impl Drop for TestBuffer {
+		fn drop(&mut self) {
+			self.live_buffers.set(self.live_buffers.get() - 1);
+		}
+	}
+
+	struct TestMesh {
+		_buffer: TestBuffer,
+		vertex_count: usize,
+		index_count: usize,
+	}
+
+	#[derive(Default)]
+	struct TestAllocator {
+		live_buffers: Rc<Cell<usize>>,
+		created_meshes: usize,
+		written_meshes: usize,
+	}
+
+	impl MeshAllocator for TestAllocator {
+		type Mesh = TestMesh;
+		type Offset = TestBuffer;
+
+		fn create_mesh(&mut self, _name: &str, vertexes: &[BlockVertex], indexes: &[u32]) -> TestMesh {
+			self.created_meshes += 1;
+			TestMesh {
+				_buffer: TestBuffer::new(&self.live_buffers),
+				vertex_count: vertexes.len(),
+				index_count: indexes.len(),
+			}
+		}
+
+		fn write_mesh(&mut self, mesh: &mut TestMesh, vertexes: &[BlockVertex], indexes: &[u32]) {
+			self.written_meshes += 1;
+			mesh.vertex_count = vertexes.len();
+			mesh.index_count = indexes.len();
+		}
+
+		fn create_offset(&mut self, _chunk: ChunkPos) -> TestBuffer {
+			TestBuffer::new(&self.live_buffers)
+		}
+	}
+
+	fn faces(opaque: usize, translucent: usize) -> ChunkFaces {
+		let face = BlockFaceMesh([BlockVertex::zeroed(); 4]);
+		ChunkFaces {
+			opaque: vec![face; opaque],
+			translucent: vec![face; translucent],
+		}
+	}
+
+	#[test]
+	fn buffers_reused_and_freed() {
+		let mut allocator = TestAllocator::default();
+		let mut meshes = ChunkMeshes::new();
+		let a = ChunkPos::new(0, 0, 0);
+		let b = ChunkPos::new(1, -2, 3);
+
+		// a mesh and an offset for a, a mesh for each pass and an offset for b
+		meshes.update(a, faces(10, 0), ChunkConnectivity::NONE, &mut allocator);
+		meshes.update(b, faces(5, 2), ChunkConnectivity::ALL, &mut allocator);
+		assert_eq!(allocator.live_buffers.get(), 5);
+		assert_eq!(allocator.created_meshes, 3);
+		assert_eq!(meshes.mesh(a).unwrap().0.index_count, 60);
+		assert!(meshes.translucent_mesh(a).is_none());
+		assert_eq!(meshes.translucent_mesh(b).unwrap().0.vertex_count, 8);
+		assert_eq!(meshes.connectivity(b), Some(ChunkConnectivity::ALL));
+
+		// a smaller mesh is written to the old buffers, even after it was made smaller before
+		meshes.update(a, faces(4, 0), ChunkConnectivity::ALL, &mut allocator);
+		meshes.update(a, faces(10, 0), ChunkConnectivity::ALL, &mut allocator);
+		assert_eq!(allocator.created_meshes, 3);
+		assert_eq!(allocator.written_meshes, 2);
+		assert_eq!(meshes.mesh(a).unwrap().0.vertex_count, 40);
+		assert_eq!(meshes.connectivity(a), Some(ChunkConnectivity::ALL));
+
+		// a bigger mesh gets new buffers, and the old ones are freed
+		meshes.update(a, faces(11, 0), ChunkConnectivity::ALL, &mut allocator);
+		assert_eq!(allocator.created_meshes, 4);
+		assert_eq!(allocator.live_buffers.get(), 5);
+		assert_eq!(meshes.mesh(a).unwrap().0.index_count, 66);
+
+		// a chunk with no faces frees its meshes but keeps its offset and connectivity
+		meshes.update(b, faces(0, 0), ChunkConnectivity::NONE, &mut allocator);
+		assert_eq!(allocator.live_buffers.get(), 3);
+		assert!(meshes.mesh(b).is_none());
+		assert_eq!(meshes.connectivity(b), Some(ChunkConnectivity::NONE));
+
+		// unloading a chunk frees all of its buffers
+		meshes.retain_loaded(|chunk| chunk == b);
+		assert_eq!(allocator.live_buffers.get(), 1);
+		assert!(meshes.mesh(a).is_none());
+		assert_eq!(meshes.connectivity(a), None);
+
+		meshes.retain_loaded(|_| false);
+		assert_eq!(allocator.live_buffers.get(), 0);
+	}
+}
@@ -6,93 +6,146 @@ use winit::{
 	event::*,
 	dpi::PhysicalSize
 };
+use nalgebra::{Point3, Vector3};
 
 use crate::prelude::*;
 use crate::render::Renderer;
-use crate::render::model::{Mesh, Material, ModelVertex};
+use crate::render::culling::{Frustum, visible_chunks, sort_back_to_front};
+use crate::render::model::{Mesh, Material, ChunkOffset};
+use crate::render::texture::TextureFiltering;
 use camera_controller::CameraController;
-use super::player::PlayerId;
+use chunk_meshes::ChunkMeshes;
+use super::player::{PlayerId, PLAYER_REACH};
 use super::world::World;
-use super::block::{generate_texture_array, BlockFaceMesh, Air};
+use super::network::{ServerConnection, protocol::ClientMessage};
+use super::block::{generate_texture_array, Block, Air};
+use super::clock::{Sky, TICK_TIME};
+use super::physics::PhysicsBody;
 
 mod camera_controller;
+mod chunk_meshes;
+
+// size of the player's collision box in meters, the camera is at eye height above the bottom of the box
+const PLAYER_WIDTH: f64 = 0.6;
+const PLAYER_HEIGHT: f64 = 1.8;
+const EYE_HEIGHT: f64 = 1.6;
+
+// how the client changes the world
+enum WorldConnection {
+	// the world is running in this process
+	Local(PlayerId),
+	// the world is a copy of a world running on a server
+	Remote(ServerConnection),
+}
 
 pub struct Client {
 	world: Arc<World>,
-	world_mesh: Mesh,
+	chunk_meshes: ChunkMeshes<Mesh, ChunkOffset>,
 	block_textures: Material,
-	player_id: PlayerId,
+	connection: WorldConnection,
 	camera_controller: CameraController,
+	// the camera is drawn at eye height above the body
+	player_body: PhysicsBody,
+	// where the body was a tick ago, the camera is drawn between this and where it is now
+	previous_player_position: Position,
 	renderer: Renderer,
 	// destroy block on the next physics frame
 	destroy_block: bool,
-	// the number of physics updates that will happen before we update again
-	// reset evrytime world tells us to update the mesh
-	// the reason we wait a bit is because when loading new chunks world will tell us to update many times
-	// in rapid succession, and waiting a bit will improve performance
-	mesh_update_countdown: Option<u64>,
 }
 
 impl Client {
-	const MESH_UPDATE_FRAME_DELAY: u64 = 5;
-
-	pub fn new(window: &Window, world: Arc<World>) -> Self {
-		let renderer = pollster::block_on(Renderer::new(window));
+	// if server_connection is Some, world must be a remote world that will be filled in by the server
+	// otherwise the player joins the local world with the given name
+	pub fn new(window: &Window, world: Arc<World>, server_connection: Option<ServerConnection>, player_name: &str) -> Self {
+		let mut renderer = pollster::block_on(Renderer::new(window)).expect("could not create the renderer");
 
 		let texture_array = generate_texture_array().expect("could not load texture map");
-		let block_textures = Material::array_from_images(&texture_array, String::from("texture map"), renderer.context());
+		let block_textures = Material::array_from_images(
+			&texture_array,
+			String::from("block textures"),
+			TextureFiltering::default(),
+			renderer.context(),
+		).expect("could not create block texture array");
 
-		let player_id = world.connect();
-
-		let mut vertexes = Vec::new();
-		let mut indexes = Vec::new();
+		let (connection, position) = match server_connection {
+			Some(server_connection) => {
+				let position = server_connection.spawn_position();
+				world.set_time(server_connection.time());
+				(WorldConnection::Remote(server_connection), Some(position))
+			},
+			None => {
+				let player_id = world.connect(player_name).expect("could not join the world");
+				(WorldConnection::Local(player_id), world.get_player_position(player_id))
+			},
+		};
 
-		let mut current_index = 0;
-		for block_face in world.world_mesh() {
-			vertexes.extend(block_face.0);
-			indexes.extend(BlockFaceMesh::indicies().iter().map(|elem| elem + current_index));
-			current_index += 4;
+		if let Some(position) = position {
+			let camera = renderer.get_camera_mut();
+			let position = Point3::new(position.x as f32, position.y as f32, position.z as f32);
+			camera.look_at = position + (camera.look_at - camera.position);
+			camera.position = position;
 		}
 
-		let mesh = Mesh::new(
-			"world mesh",
-			&vertexes,
-			&indexes,
-			0,
-			renderer.context()
-		);
+		let eye_offset = Vector3::new(0.0, EYE_HEIGHT, 0.0);
+		let player_body = PhysicsBody::new(renderer.get_camera_mut().get_position() - eye_offset, PLAYER_WIDTH, PLAYER_HEIGHT);
 
-		Self {
+		let mut client = Self {
 			world,
-			world_mesh: mesh,
+			chunk_meshes: ChunkMeshes::new(),
 			block_textures,
-			player_id,
+			connection,
 			camera_controller: CameraController::new(7.0, 20.0, 2.0),
+			previous_player_position: player_body.position,
+			player_body,
 			renderer,
 			destroy_block: false,
-			mesh_update_countdown: None,
+		};
+		client.update_chunk_meshes();
+		client
+	}
+
+	// tells the server the player has left, does nothing for a local world
+	pub fn disconnect(&mut self) {
+		if let WorldConnection::Remote(server_connection) = &mut self.connection {
+			if let Err(error) = server_connection.send(&ClientMessage::Disconnect) {
+				warn!("could not disconnect from the server: {}", error);
+			}
 		}
 	}
 
-	pub fn generate_mesh(&mut self) {
-		let mut vertexes = Vec::new();
-		let mut indexes = Vec::new();
+	// uploads the meshes of chunks which have changed, and removes the meshes of unloaded chunks
+	pub fn update_chunk_meshes(&mut self) {
+		let world = &self.world;
+		self.chunk_meshes.retain_loaded(|position| world.chunks.contains_key(&position));
 
-		let mut current_index = 0;
-		for block_face in self.world.world_mesh() {
-			vertexes.extend(block_face.0);
-			indexes.extend(BlockFaceMesh::indicies().iter().map(|elem| elem + current_index));
-			current_index += 4;
+		let mut context = self.renderer.context();
+		for (position, faces, connectivity) in self.world.changed_chunk_meshes() {
+			self.chunk_meshes.update(position, faces, connectivity, &mut context);
 		}
+	}
+
+	// draws the chunks that are in view of the camera and aren't hidden behind solid chunks
+	fn render(&mut self) {
+		let camera = self.renderer.camera();
+		let frustum = Frustum::from_matrix(&camera.get_camera_matrix());
+		let visible = visible_chunks(camera.get_position().into_chunk_pos(), &frustum, |chunk| {
+			self.chunk_meshes.connectivity(chunk)
+		});
 
-		// TODO: write to the underlying buffer
-		self.world_mesh = Mesh::new(
-			"world mesh",
-			&vertexes,
-			&indexes,
-			0,
-			self.renderer.context()
-		);
+		let chunks = visible.iter()
+			.filter_map(|chunk| self.chunk_meshes.mesh(*chunk))
+			.collect::<Vec<_>>();
+
+		let mut translucent_chunks = visible.iter()
+			.copied()
+			.filter(|chunk| self.chunk_meshes.translucent_mesh(*chunk).is_some())
+			.collect::<Vec<_>>();
+		sort_back_to_front(&mut translucent_chunks, camera.get_position());
+		let translucent_chunks = translucent_chunks.iter()
+			.filter_map(|chunk| self.chunk_meshes.translucent_mesh(*chunk))
+			.collect::<Vec<_>>();
+
+		self.renderer.render(&chunks, &translucent_chunks, &self.block_textures);
 	}
 
 	pub fn input(&mut self, event: &WindowEvent) {
@@ -109,50 +162,103 @@ impl Client {
 		}
 	}
 
-	pub fn frame_update(&mut self, new_window_size: Option<PhysicalSize<u32>>) {
-		if let Some(new_window_size) = new_window_size {
-			self.renderer.resize(new_window_size);
-		}
-		self.renderer.render(&[(&self.world_mesh, &self.block_textures)]);
+	pub fn resize(&mut self, new_window_size: PhysicalSize<u32>) {
+		self.renderer.resize(new_window_size);
 	}
 
-	pub fn physics_update(&mut self, delta: Duration) {
+	// draws a frame, tick_progress is how far it is from the last tick to the next one, from 0 to 1
+	// the player is drawn between where it was on the last 2 ticks, so it moves smoothly at any framerate
+	pub fn frame_update(&mut self, frame_delta: Duration, tick_progress: f64) {
 		let camera = self.renderer.get_camera_mut();
-		self.camera_controller.update_camera(camera, delta);
-		let camera_position = camera.get_position();
+		// turning is done every frame, since it doesn't change anything in the world
+		self.camera_controller.rotate_camera(camera, frame_delta);
+
+		let position = self.previous_player_position.lerp(&self.player_body.position, tick_progress)
+			+ Vector3::new(0.0, EYE_HEIGHT, 0.0);
+		let position = Point3::new(position.x as f32, position.y as f32, position.z as f32);
+		camera.look_at = position + (camera.look_at - camera.position);
+		camera.position = position;
+
+		self.renderer.set_sky(Sky::at(self.world.time()));
+		self.render();
+	}
+
+	// runs 1 tick of the world and moves the player, this is called TICKS_PER_SECOND times a second
+	pub fn tick(&mut self) {
+		// the clock of a remote world also runs here, so it keeps going between the times the server sends
+		self.world.tick();
+
+		let camera = self.renderer.camera();
+		let tick_time = TICK_TIME.as_secs_f64();
+		self.previous_player_position = self.player_body.position;
+		if self.camera_controller.is_flying() {
+			let position = self.player_body.position + self.camera_controller.fly_velocity(camera) * tick_time;
+			self.player_body.teleport(position);
+		} else {
+			self.player_body.walk(
+				&self.world,
+				self.camera_controller.walk_velocity(camera),
+				self.camera_controller.jump_pressed(),
+				tick_time,
+			);
+		}
+
+		let camera_position = self.player_body.position + Vector3::new(0.0, EYE_HEIGHT, 0.0);
+		let camera_direction = camera.forward();
 
 		let mut generate_mesh = false;
 
 		if self.destroy_block {
-			if let Some(block) = self.world.block_raycast(camera_position, camera.forward(), 15.0) {
-				self.world.set_block(block, Air::new().into());
-				self.world.mesh_update_adjacent(block);
+			if let Some(block) = self.world.block_raycast(camera_position, camera_direction, PLAYER_REACH) {
+				let new_block: Block = Air::new().into();
+
+				// the block is changed locally straight away, so the player doesn't have to wait for the server
+				if let WorldConnection::Remote(server_connection) = &mut self.connection {
+					if let Err(error) = server_connection.send(&ClientMessage::SetBlock {
+						position: block.into(),
+						block_id: self.world.block_ids().saved_id(&new_block),
+						block_state: new_block.state().bits(),
+					}) {
+						error!("could not send block change to the server: {}", error);
+					}
+				}
+
+				self.world.set_block(block, new_block);
 				generate_mesh = true;
 			}
 
 			self.destroy_block = false;
 		}
 
-		if let Some(result) = self.world.set_player_position(self.player_id, camera_position) {
-			if result {
-				generate_mesh = true;
-			}
+		match &mut self.connection {
+			WorldConnection::Local(player_id) => {
+				if let Some(true) = self.world.set_player_position(*player_id, camera_position) {
+					generate_mesh = true;
+				}
+				self.world.set_player_direction(*player_id, camera_direction);
+			},
+			WorldConnection::Remote(server_connection) => {
+				let result = server_connection.send(&ClientMessage::PlayerPosition(camera_position.into()))
+					.and_then(|_| server_connection.send(&ClientMessage::PlayerDirection(camera_direction.into())));
+				if let Err(error) = result {
+					error!("could not send player position to the server: {}", error);
+				}
+
+				match server_connection.update_world(&self.world) {
+					Ok(true) => generate_mesh = true,
+					Ok(false) => (),
+					Err(error) => error!("{}", error),
+				}
+			},
 		}
 
 		if self.world.poll_completed_tasks() {
 			generate_mesh = true;
 		}
 
+		// only the chunks which changed are uploaded, so there is no need to wait for more changes like with 1 big mesh
 		if generate_mesh {
-			self.mesh_update_countdown = Some(Self::MESH_UPDATE_FRAME_DELAY);
-		} else if let Some(ref mut countdown) = self.mesh_update_countdown {
-			*countdown -= 1;
-			if *countdown == 0 {
-				self.mesh_update_countdown = None;
-				self.generate_mesh();
-			}
+			self.update_chunk_meshes();
 		}
-
-		self.renderer.render(&[(&self.world_mesh, &self.block_textures)]);
 	}
 }
@@ -0,0 +1,178 @@
+use std::f64::consts::TAU;
+use std::time::Duration;
+
+use nalgebra::Vector3;
+
+// the world clock goes up by 1 every tick
+pub const TICKS_PER_SECOND: u64 = 20;
+// the world is simulated in steps of this length, no matter how fast frames are drawn
+pub const TICK_TIME: Duration = Duration::from_micros(1_000_000 / TICKS_PER_SECOND);
+// a day and a night last 20 minutes together
+pub const TICKS_PER_DAY: u64 = 20 * 60 * TICKS_PER_SECOND;
+
+// times of day, a day starts at sunrise
+pub const SUNRISE: u64 = 0;
+pub const NOON: u64 = TICKS_PER_DAY / 4;
+pub const SUNSET: u64 = TICKS_PER_DAY / 2;
+pub const MIDNIGHT: u64 = TICKS_PER_DAY * 3 / 4;
+
+// new worlds start a little after sunrise
+pub const START_TIME: u64 = TICKS_PER_DAY / 24;
+
+// the most time a tick loop catches up on after a pause, ticks past this are skipped
+pub const MAX_UNTICKED_TIME: Duration = Duration::from_secs(1);
+
+// sky light is multiplied by this in the middle of the night
+const NIGHT_BRIGHTNESS: f32 = 0.3;
+const DAY_SKY_COLOR: [f32; 3] = [0.5, 0.7, 1.0];
+const NIGHT_SKY_COLOR: [f32; 3] = [0.02, 0.02, 0.06];
+const SUNSET_SKY_COLOR: [f32; 3] = [0.9, 0.5, 0.3];
+
+// the names which can be used for a time of day when setting the time
+pub fn parse_time_of_day(name: &str) -> Option<u64> {
+	match name {
+		"sunrise" => Some(SUNRISE),
+		"day" => Some(START_TIME),
+		"noon" => Some(NOON),
+		"sunset" => Some(SUNSET),
+		"night" => Some(SUNSET + TICKS_PER_DAY / 24),
+		"midnight" => Some(MIDNIGHT),
+		_ => name.parse().ok(),
+	}
+}
+
+// keeps track of when ticks are due, so the world is ticked at a fixed rate however often add_time is called
+#[derive(Debug, Default)]
+pub struct TickAccumulator {
+	// time that has passed since the last tick
+	unticked_time: Duration,
+}
+
+impl TickAccumulator {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	// if more than MAX_UNTICKED_TIME builds up, only 1 tick is run instead of catching up on all of them
+	pub fn add_time(&mut self, time: Duration) {
+		self.unticked_time += time;
+		if self.unticked_time > MAX_UNTICKED_TIME {
+			warn!("running more than {} seconds behind, skipping ticks", MAX_UNTICKED_TIME.as_secs());
+			self.unticked_time = TICK_TIME;
+		}
+	}
+
+	// returns true if a tick is due, and counts it as run
+	pub fn take_tick(&mut self) -> bool {
+		if self.unticked_time >= TICK_TIME {
+			self.unticked_time -= TICK_TIME;
+			true
+		} else {
+			false
+		}
+	}
+
+	// how far it is from the last tick to the next tick, from 0 to 1
+	pub fn tick_progress(&self) -> f64 {
+		self.unticked_time.as_secs_f64() / TICK_TIME.as_secs_f64()
+	}
+
+	pub fn time_until_tick(&self) -> Duration {
+		TICK_TIME.saturating_sub(self.unticked_time)
+	}
+}
+
+fn lerp_color(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+	[0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+// how the sky looks at a point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sky {
+	// points towards the sun, which goes around the x axis, rising at x positive and setting at x negative
+	// the moon is on the other side of the sky
+	pub sun_direction: Vector3<f64>,
+	pub color: [f32; 3],
+	// sky light is multiplied by this, from NIGHT_BRIGHTNESS at night to 1 during the day
+	pub brightness: f32,
+}
+
+impl Sky {
+	pub fn at(time: u64) -> Self {
+		let angle = (time % TICKS_PER_DAY) as f64 / TICKS_PER_DAY as f64 * TAU;
+		// tilted a little towards z so faces on the z axis aren't always lit the same
+		let sun_direction = Vector3::new(angle.cos(), angle.sin(), 0.2).normalize();
+		let sun_height = sun_direction.y as f32;
+
+		// goes from night to day while the sun is close to the horizon
+		let daylight = ((sun_height + 0.2) / 0.4).clamp(0.0, 1.0);
+		// the sky turns orange around sunrise and sunset
+		let dusk = 1.0 - (sun_height.abs() / 0.3).min(1.0);
+
+		let color = lerp_color(NIGHT_SKY_COLOR, DAY_SKY_COLOR, daylight);
+		Self {
+			sun_direction,
+			color: lerp_color(color, SUNSET_SKY_COLOR, dusk * 0.6),
+			brightness: NIGHT_BRIGHTNESS + (1.0 - NIGHT_BRIGHTNESS) * daylight,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn day_and_night() {
+		let noon = Sky::at(NOON);
+		assert!(noon.sun_direction.y > 0.9);
+		assert!((noon.brightness - 1.0).abs() < 1e-6);
+
+		let midnight = Sky::at(MIDNIGHT);
+		assert!(midnight.sun_direction.y < -0.9);
+		assert_eq!(midnight.brightness, NIGHT_BRIGHTNESS);
+
+		// every day is the same
+		assert_eq!(Sky::at(NOON + 3 * TICKS_PER_DAY), noon);
+
+		assert_eq!(parse_time_of_day("midnight"), Some(MIDNIGHT));
+		assert_eq!(parse_time_of_day("1234"), Some(1234));
+		assert_eq!(parse_time_of_day("tomorrow"), None);
+	}
+
+	fn take_ticks(ticks: &mut TickAccumulator) -> usize {
+		let mut count = 0;
+		while ticks.take_tick() {
+			count += 1;
+		}
+		count
+	}
+
+	#[test]
+	fn fixed_timestep() {
+		let mut ticks = TickAccumulator::new();
+		assert_eq!(take_ticks(&mut ticks), 0);
+		assert_eq!(ticks.time_until_tick(), TICK_TIME);
+
+		// time left over after the ticks is kept for the next ones
+		ticks.add_time(TICK_TIME * 5 / 2);
+		assert_eq!(take_ticks(&mut ticks), 2);
+		assert!((ticks.tick_progress() - 0.5).abs() < 1e-9);
+		assert_eq!(ticks.time_until_tick(), TICK_TIME / 2);
+
+		ticks.add_time(TICK_TIME / 4);
+		assert_eq!(take_ticks(&mut ticks), 0);
+		ticks.add_time(TICK_TIME / 4);
+		assert_eq!(take_ticks(&mut ticks), 1);
+		assert_eq!(ticks.time_until_tick(), TICK_TIME);
+
+		// up to MAX_UNTICKED_TIME is caught up on
+		ticks.add_time(MAX_UNTICKED_TIME);
+		assert_eq!(take_ticks(&mut ticks) as u64, TICKS_PER_SECOND * MAX_UNTICKED_TIME.as_secs());
+
+		// but after a longer stall only 1 tick runs
+		ticks.add_time(MAX_UNTICKED_TIME * 10);
+		assert_eq!(take_ticks(&mut ticks), 1);
+		assert_eq!(ticks.tick_progress(), 0.0);
+	}
+}
@@ -0,0 +1,128 @@
+use super::block::BlockFace;
+use super::block_storage::{block_index, CHUNK_VOLUME};
+use super::chunk::CHUNK_SIZE;
+
+// says which faces of a chunk can be seen from which other faces by looking through translucent blocks in the chunk
+// the renderer uses this to skip chunks which are hidden behind solid terrain
+//
+// bit a * 6 + b is set if face b can be seen from face a
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkConnectivity(u64);
+
+impl ChunkConnectivity {
+	// every face can see every other face, like in a chunk of air
+	pub const ALL: Self = Self((1 << 36) - 1);
+	// no face can see any other face, like in a chunk of stone
+	pub const NONE: Self = Self(0);
+
+	fn bit(a: BlockFace, b: BlockFace) -> u64 {
+		1 << (usize::from(a) * 6 + usize::from(b))
+	}
+
+	pub fn connect(&mut self, a: BlockFace, b: BlockFace) {
+		self.0 |= Self::bit(a, b) | Self::bit(b, a);
+	}
+
+	pub fn can_see(&self, a: BlockFace, b: BlockFace) -> bool {
+		self.0 & Self::bit(a, b) != 0
+	}
+
+	// is_translucent is called with the index of every block in the chunk
+	//
+	// each group of translucent blocks which touch each other is flood filled,
+	// and all the faces of the chunk that the group touches can see each other
+	pub fn from_fn<F: FnMut(usize) -> bool>(mut is_translucent: F) -> Self {
+		let translucent = (0..CHUNK_VOLUME).map(&mut is_translucent).collect::<Vec<_>>();
+		let mut visited = vec![false; CHUNK_VOLUME];
+		let mut stack = Vec::new();
+		let mut out = Self::NONE;
+
+		for start in 0..CHUNK_VOLUME {
+			if visited[start] || !translucent[start] {
+				continue;
+			}
+
+			visited[start] = true;
+			stack.push(start);
+			// bit n is set if the group touches face n
+			let mut touched_faces = 0u8;
+
+			while let Some(index) = stack.pop() {
+				let position = [index / (CHUNK_SIZE * CHUNK_SIZE), (index / CHUNK_SIZE) % CHUNK_SIZE, index % CHUNK_SIZE];
+
+				for face in BlockFace::iter() {
+					let offset = face.block_pos_offset();
+					let mut neighbor = position;
+					let mut on_edge = false;
+
+					for axis in 0..3 {
+						match offset[axis] {
+							1 if position[axis] == CHUNK_SIZE - 1 => on_edge = true,
+							-1 if position[axis] == 0 => on_edge = true,
+							n => neighbor[axis] = (position[axis] as i64 + n) as usize,
+						}
+					}
+
+					if on_edge {
+						touched_faces |= 1 << usize::from(face);
+						continue;
+					}
+
+					let neighbor_index = block_index(neighbor[0], neighbor[1], neighbor[2]);
+					if translucent[neighbor_index] && !visited[neighbor_index] {
+						visited[neighbor_index] = true;
+						stack.push(neighbor_index);
+					}
+				}
+			}
+
+			for a in BlockFace::iter() {
+				for b in BlockFace::iter() {
+					if touched_faces & (1 << usize::from(a)) != 0 && touched_faces & (1 << usize::from(b)) != 0 {
+						out.connect(a, b);
+					}
+				}
+			}
+		}
+
+		out
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn uniform_chunks() {
+		assert_eq!(ChunkConnectivity::from_fn(|_| true), ChunkConnectivity::ALL);
+		assert_eq!(ChunkConnectivity::from_fn(|_| false), ChunkConnectivity::NONE);
+	}
+
+	#[test]
+	fn wall_splits_chunk() {
+		// a solid wall at x = 16 splits the chunk into 2 halves
+		let connectivity = ChunkConnectivity::from_fn(|index| index / (CHUNK_SIZE * CHUNK_SIZE) != 16);
+
+		assert!(!connectivity.can_see(BlockFace::XNeg, BlockFace::XPos));
+		assert!(connectivity.can_see(BlockFace::XNeg, BlockFace::YPos));
+		assert!(connectivity.can_see(BlockFace::XPos, BlockFace::ZNeg));
+		assert!(connectivity.can_see(BlockFace::YPos, BlockFace::YNeg));
+		assert!(connectivity.can_see(BlockFace::ZPos, BlockFace::XNeg));
+	}
+
+	#[test]
+	fn tunnel() {
+		// a 1 block tunnel going through the chunk along the z axis, with a branch going up
+		let connectivity = ChunkConnectivity::from_fn(|index| {
+			let (x, y, z) = (index / (CHUNK_SIZE * CHUNK_SIZE), (index / CHUNK_SIZE) % CHUNK_SIZE, index % CHUNK_SIZE);
+			(x == 5 && y == 5) || (x == 5 && z == 20 && y >= 5)
+		});
+
+		assert!(connectivity.can_see(BlockFace::ZNeg, BlockFace::ZPos));
+		assert!(connectivity.can_see(BlockFace::ZPos, BlockFace::YPos));
+		assert!(!connectivity.can_see(BlockFace::ZPos, BlockFace::XPos));
+		assert!(!connectivity.can_see(BlockFace::YNeg, BlockFace::YPos));
+		assert!(!connectivity.can_see(BlockFace::XNeg, BlockFace::XPos));
+	}
+}
@@ -0,0 +1,73 @@
+use nalgebra::Vector3;
+
+use super::*;
+use crate::game::block::Block;
+use crate::game::clock::TICK_TIME;
+
+// slightly smaller than a block, so it can fall down a 1 block wide hole
+const FALLING_BLOCK_SIZE: f64 = 0.98;
+
+// a block which falls until it lands on something, then turns back into a block
+pub struct FallingBlock {
+	id: EntityId,
+	body: PhysicsBody,
+	block: Block,
+}
+
+impl FallingBlock {
+	// starts falling from where the block is, this doesn't remove the block
+	pub fn from_block(world: &World, position: BlockPos, block: Block) -> Box<dyn Entity> {
+		let position = position.cast::<f64>() + Vector3::new(0.5, 0.0, 0.5);
+		Box::new(Self::load(world.new_entity_id(), position, Vector3::zeros(), block))
+	}
+
+	pub fn load(id: EntityId, position: Position, velocity: Vector3<f64>, block: Block) -> Self {
+		let mut body = PhysicsBody::new(position, FALLING_BLOCK_SIZE, FALLING_BLOCK_SIZE);
+		body.velocity = velocity;
+		FallingBlock {
+			id,
+			body,
+			block,
+		}
+	}
+
+	pub fn block(&self) -> &Block {
+		&self.block
+	}
+}
+
+impl Entity for FallingBlock {
+	fn id(&self) -> EntityId {
+		self.id
+	}
+
+	fn body(&self) -> &PhysicsBody {
+		&self.body
+	}
+
+	// if it lands somewhere a block is already, like on top of a slab, the block is lost
+	fn update(&mut self, world: &World) -> bool {
+		self.body.walk(world, Vector3::zeros(), false, TICK_TIME.as_secs_f64());
+		if !self.body.on_ground() {
+			return true;
+		}
+
+		let position = self.body.position.into_block_pos();
+		if matches!(world.get_block(position), Some(block) if block.is_air()) {
+			world.set_block(position, self.block.clone());
+		}
+		false
+	}
+
+	fn save(&self, world: &World) -> SavedEntity {
+		SavedEntity {
+			id: self.id.as_u64(),
+			position: self.body.position.into(),
+			velocity: self.body.velocity.into(),
+			kind: SavedEntityKind::FallingBlock {
+				block_id: world.block_ids().saved_id(&self.block),
+				block_state: self.block.state().bits(),
+			},
+		}
+	}
+}
@@ -1,4 +1,166 @@
-mod player;
-pub use player::*;
+use nalgebra::Vector3;
+use rustc_hash::{FxHashMap, FxHashSet};
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
 
-pub trait Entity: Send + Sync {}
+use crate::prelude::*;
+use super::physics::{Aabb, PhysicsBody};
+use super::world::World;
+use super::block::BlockState;
+
+mod falling_block;
+pub use falling_block::*;
+
+// entities can't be bigger than this on any axis, so the entities touching a box are in the chunks around the box
+pub const MAX_ENTITY_SIZE: f64 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EntityId(u64);
+
+impl EntityId {
+	// ids are given out by the world, see World::new_entity_id
+	pub fn from_u64(id: u64) -> Self {
+		EntityId(id)
+	}
+
+	pub fn as_u64(&self) -> u64 {
+		self.0
+	}
+}
+
+// the state of an entity which is written to disk with the chunk it is in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedEntity {
+	pub id: u64,
+	pub position: [f64; 3],
+	pub velocity: [f64; 3],
+	pub kind: SavedEntityKind,
+}
+
+// what type the entity is, and anything saved only by that type
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SavedEntityKind {
+	FallingBlock {
+		block_id: u16,
+		block_state: u8,
+	},
+}
+
+pub trait Entity: Send + Sync {
+	fn id(&self) -> EntityId;
+
+	// the position of the body is the center of the bottom of the entity
+	fn body(&self) -> &PhysicsBody;
+
+	fn position(&self) -> Position {
+		self.body().position
+	}
+
+	fn velocity(&self) -> Vector3<f64> {
+		self.body().velocity
+	}
+
+	fn aabb(&self) -> Aabb {
+		self.body().aabb()
+	}
+
+	// called once every tick while the chunk the entity is in is loaded
+	// returns false if the entity should be removed from the world
+	// the entity is only saved again if its position or velocity changed
+	fn update(&mut self, world: &World) -> bool;
+
+	fn save(&self, world: &World) -> SavedEntity;
+}
+
+// creates the entity that was saved
+pub fn load_entity(world: &World, saved: &SavedEntity) -> Result<Box<dyn Entity>> {
+	let id = EntityId(saved.id);
+	let position = Position::from(saved.position);
+	let velocity = Vector3::from(saved.velocity);
+
+	match &saved.kind {
+		SavedEntityKind::FallingBlock { block_id, block_state } => {
+			let block = world.block_ids().block(*block_id, BlockState::from_bits(*block_state))
+				.with_context(|| format!("falling block entity {} has invalid block {} with state {}", saved.id, block_id, block_state))?;
+			Ok(Box::new(FallingBlock::load(id, position, velocity, block)))
+		},
+	}
+}
+
+// all the entities in a world, indexed by the chunk their position is in
+#[derive(Default)]
+pub struct EntityMap {
+	entities: FxHashMap<EntityId, Box<dyn Entity>>,
+	chunks: FxHashMap<ChunkPos, FxHashSet<EntityId>>,
+}
+
+impl EntityMap {
+	// entities can't be moved while they are in the map, so they stay in the chunk they were inserted in
+	pub fn insert(&mut self, entity: Box<dyn Entity>) {
+		let id = entity.id();
+		self.remove(id);
+		self.chunks.entry(entity.position().into_chunk_pos()).or_default().insert(id);
+		self.entities.insert(id, entity);
+	}
+
+	pub fn remove(&mut self, id: EntityId) -> Option<Box<dyn Entity>> {
+		let entity = self.entities.remove(&id)?;
+		let chunk = entity.position().into_chunk_pos();
+
+		let chunk_entities = self.chunks.get_mut(&chunk).expect("entity is missing from its chunk");
+		chunk_entities.remove(&id);
+		if chunk_entities.is_empty() {
+			self.chunks.remove(&chunk);
+		}
+		Some(entity)
+	}
+
+	pub fn get(&self, id: EntityId) -> Option<&dyn Entity> {
+		self.entities.get(&id).map(|entity| &**entity)
+	}
+
+	pub fn ids_in_chunk(&self, chunk: ChunkPos) -> Vec<EntityId> {
+		self.chunks.get(&chunk)
+			.map(|ids| ids.iter().copied().collect())
+			.unwrap_or_default()
+	}
+
+	pub fn in_chunk(&self, chunk: ChunkPos) -> impl Iterator<Item = &dyn Entity> {
+		self.chunks.get(&chunk)
+			.into_iter()
+			.flat_map(|ids| ids.iter().map(|id| &*self.entities[id]))
+	}
+
+	// removes all the entities in the chunk
+	pub fn take_chunk(&mut self, chunk: ChunkPos) -> Vec<Box<dyn Entity>> {
+		self.chunks.remove(&chunk)
+			.into_iter()
+			.flatten()
+			.map(|id| self.entities.remove(&id).expect("chunk has an entity which is not in the map"))
+			.collect()
+	}
+
+	// the chunks which have at least 1 entity in them
+	pub fn chunks(&self) -> impl Iterator<Item = ChunkPos> + '_ {
+		self.chunks.keys().copied()
+	}
+
+	// the entities whose box overlaps the area
+	pub fn in_box(&self, area: &Aabb) -> Vec<EntityId> {
+		let margin = Vector3::repeat(MAX_ENTITY_SIZE);
+		let min_chunk = (area.min - margin).into_chunk_pos();
+		let max_chunk = (area.max + margin).into_chunk_pos();
+
+		let mut out = Vec::new();
+		for x in min_chunk.x..=max_chunk.x {
+			for y in min_chunk.y..=max_chunk.y {
+				for z in min_chunk.z..=max_chunk.z {
+					out.extend(self.in_chunk(ChunkPos::new(x, y, z))
+						.filter(|entity| entity.aabb().intersects(area))
+						.map(|entity| entity.id()));
+				}
+			}
+		}
+		out
+	}
+}
@@ -0,0 +1,480 @@
+use std::collections::VecDeque;
+
+use rustc_hash::{FxHashMap, FxHashSet};
+use parking_lot::{Mutex, Condvar};
+
+use super::block::{BlockFace, MAX_LIGHT_EMISSION};
+use super::block_storage::{block_index, CHUNK_VOLUME};
+use super::world::World;
+use super::CHUNK_SIZE;
+use crate::prelude::*;
+
+// the brightest light level, which is the sky light of blocks the sun shines on directly
+pub const MAX_LIGHT: u8 = 15;
+
+// emitted light is stored in the same bits as the rest of the block light
+const _: () = assert!(MAX_LIGHT_EMISSION <= MAX_LIGHT);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightKind {
+	// comes down from the top of the world, and goes straight down through transparent blocks without getting any dimmer
+	Sky,
+	// comes from blocks which emit light
+	Block,
+}
+
+// the sky light and block light of a block, with 4 bits each
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct LightLevel(u8);
+
+impl LightLevel {
+	pub fn new(sky: u8, block: u8) -> Self {
+		assert!(sky <= MAX_LIGHT && block <= MAX_LIGHT, "invalid light level passed to LightLevel::new()");
+		Self(sky << 4 | block)
+	}
+
+	pub fn sky(&self) -> u8 {
+		self.0 >> 4
+	}
+
+	pub fn block(&self) -> u8 {
+		self.0 & 0xf
+	}
+
+	pub fn get(&self, kind: LightKind) -> u8 {
+		match kind {
+			LightKind::Sky => self.sky(),
+			LightKind::Block => self.block(),
+		}
+	}
+
+	pub fn with(&self, kind: LightKind, level: u8) -> Self {
+		match kind {
+			LightKind::Sky => Self::new(level, self.block()),
+			LightKind::Block => Self::new(self.sky(), level),
+		}
+	}
+}
+
+// a copy of the light in a chunk and how the blocks in it affect light, which light updates work on
+pub struct ChunkLightData {
+	pub light: Box<[LightLevel]>,
+	// if light can go into each block, which it can for every block that isn't an opaque cube
+	pub transparent: Box<[bool]>,
+	pub emission: Box<[u8]>,
+}
+
+struct CachedChunk {
+	data: ChunkLightData,
+	// the chunk local box of blocks whose light has changed, not including max
+	changed: Option<(BlockPos, BlockPos)>,
+}
+
+// the index in the chunk of a chunk local block position
+fn local_index(block: BlockPos) -> usize {
+	let (x, y, z) = block.as_indicies().unwrap();
+	block_index(x, y, z)
+}
+
+// keeps track of which chunks are used by a light update, so light updates in different parts of the world can run at the same time
+// a light update claims every chunk it reads light from, so no other light update can change the light it is working on
+#[derive(Default)]
+pub struct LightClaims {
+	claimed: Mutex<FxHashSet<ChunkPos>>,
+	released: Condvar,
+}
+
+impl LightClaims {
+	// waits until none of the chunks are claimed, then claims all of them
+	// this must not be called while holding another claim, or 2 light updates could wait for each other
+	pub fn claim(&self, chunks: FxHashSet<ChunkPos>) -> LightClaim<'_> {
+		let mut claimed = self.claimed.lock();
+		while chunks.iter().any(|chunk| claimed.contains(chunk)) {
+			self.released.wait(&mut claimed);
+		}
+
+		claimed.extend(chunks.iter().copied());
+		LightClaim {
+			claims: self,
+			chunks,
+		}
+	}
+}
+
+// chunks claimed by a light update, which are released when this is dropped
+pub struct LightClaim<'a> {
+	claims: &'a LightClaims,
+	chunks: FxHashSet<ChunkPos>,
+}
+
+impl LightClaim<'_> {
+	// claims 1 more chunk without waiting, returns false if another light update has it
+	fn try_claim(&mut self, chunk: ChunkPos) -> bool {
+		if self.chunks.contains(&chunk) {
+			return true;
+		}
+
+		if !self.claims.claimed.lock().insert(chunk) {
+			return false;
+		}
+		self.chunks.insert(chunk);
+		true
+	}
+}
+
+impl Drop for LightClaim<'_> {
+	fn drop(&mut self) {
+		let mut claimed = self.claims.claimed.lock();
+		for chunk in self.chunks.iter() {
+			claimed.remove(chunk);
+		}
+		self.claims.released.notify_all();
+	}
+}
+
+// spreads light through the loaded chunks
+//
+// the light of each chunk is copied the first time the update touches it, and written back to the chunks when it is finished
+// every chunk that is copied is claimed first, so light updates never overwrite each other's changes
+// if a chunk is claimed by another update, this update can't finish, and has to be run again with that chunk claimed from the start
+//
+// light doesn't go into chunks that aren't loaded, so the light next to a chunk that was unloaded is left as it was
+pub struct LightUpdate<'a> {
+	world: &'a World,
+	// None for chunks that aren't loaded
+	chunks: FxHashMap<ChunkPos, Option<CachedChunk>>,
+	// blocks whose light has to be spread to their neighbors
+	increase_queue: VecDeque<BlockPos>,
+	// blocks whose light was removed, with the light they had, so the light that came from them can be removed too
+	decrease_queue: VecDeque<(BlockPos, u8)>,
+	claim: LightClaim<'a>,
+	// chunks the update needed which were claimed by another update
+	missed: Vec<ChunkPos>,
+}
+
+impl<'a> LightUpdate<'a> {
+	// the chunks in the claim can be used right away, other chunks are claimed when they are first used
+	pub fn new(world: &'a World, claim: LightClaim<'a>) -> Self {
+		Self {
+			world,
+			chunks: FxHashMap::default(),
+			increase_queue: VecDeque::new(),
+			decrease_queue: VecDeque::new(),
+			claim,
+			missed: Vec::new(),
+		}
+	}
+
+	// a chunk claimed by another update is treated like it isn't loaded, the result is thrown away in finish anyway
+	fn chunk(&mut self, position: ChunkPos) -> Option<&mut CachedChunk> {
+		let world = self.world;
+		let claim = &mut self.claim;
+		let missed = &mut self.missed;
+		self.chunks.entry(position)
+			.or_insert_with(|| {
+				let chunk = world.chunks.get(&position)?;
+				if !claim.try_claim(position) {
+					missed.push(position);
+					return None;
+				}
+
+				Some(CachedChunk {
+					data: chunk.chunk.light_data(),
+					changed: None,
+				})
+			})
+			.as_mut()
+	}
+
+	fn is_loaded(&mut self, position: ChunkPos) -> bool {
+		self.chunk(position).is_some()
+	}
+
+	// returns None if the block is in a chunk that isn't loaded
+	fn light(&mut self, block: BlockPos) -> Option<LightLevel> {
+		let (chunk, local) = block.as_chunk_block_pos();
+		Some(self.chunk(chunk)?.data.light[local_index(local)])
+	}
+
+	fn get(&mut self, block: BlockPos, kind: LightKind) -> Option<u8> {
+		Some(self.light(block)?.get(kind))
+	}
+
+	fn set(&mut self, block: BlockPos, kind: LightKind, level: u8) {
+		let (chunk, local) = block.as_chunk_block_pos();
+		let chunk = match self.chunk(chunk) {
+			Some(chunk) => chunk,
+			None => return,
+		};
+
+		let light = &mut chunk.data.light[local_index(local)];
+		if light.get(kind) == level {
+			return;
+		}
+		*light = light.with(kind, level);
+
+		let end = local + BlockPos::repeat(1);
+		chunk.changed = Some(match chunk.changed {
+			Some((min, max)) => (min.inf(&local), max.sup(&end)),
+			None => (local, end),
+		});
+	}
+
+	fn is_transparent(&mut self, block: BlockPos) -> bool {
+		let (chunk, local) = block.as_chunk_block_pos();
+		match self.chunk(chunk) {
+			Some(chunk) => chunk.data.transparent[local_index(local)],
+			None => false,
+		}
+	}
+
+	// the light a block has no matter what is around it
+	fn own_light(&mut self, block: BlockPos, kind: LightKind) -> u8 {
+		let (chunk, local) = block.as_chunk_block_pos();
+		match (kind, self.chunk(chunk)) {
+			(LightKind::Block, Some(chunk)) => chunk.data.emission[local_index(local)],
+			_ => 0,
+		}
+	}
+
+	// the light that goes from a block with the given light level to the block next to it on the given face
+	fn spread_level(kind: LightKind, level: u8, face: BlockFace) -> u8 {
+		if kind == LightKind::Sky && face == BlockFace::YNeg && level == MAX_LIGHT {
+			MAX_LIGHT
+		} else {
+			level.saturating_sub(1)
+		}
+	}
+
+	// spreads the light of every block in the increase queue until it stops getting brighter
+	fn run_increase(&mut self, kind: LightKind) {
+		while let Some(block) = self.increase_queue.pop_front() {
+			let level = match self.get(block, kind) {
+				Some(level) if level > 0 => level,
+				_ => continue,
+			};
+
+			for face in BlockFace::iter() {
+				let neighbor = block + face.block_pos_offset();
+				let new_level = Self::spread_level(kind, level, face);
+				if !self.is_transparent(neighbor) {
+					continue;
+				}
+
+				if new_level > self.get(neighbor, kind).unwrap() {
+					self.set(neighbor, kind, new_level);
+					self.increase_queue.push_back(neighbor);
+				}
+			}
+		}
+	}
+
+	// removes the light which came from the blocks in the decrease queue,
+	// and queues the blocks around the dark area which still have light so it can be spread back in by run_increase
+	fn run_decrease(&mut self, kind: LightKind) {
+		while let Some((block, old_level)) = self.decrease_queue.pop_front() {
+			for face in BlockFace::iter() {
+				let neighbor = block + face.block_pos_offset();
+				let level = match self.get(neighbor, kind) {
+					Some(level) if level > 0 => level,
+					_ => continue,
+				};
+
+				if level < old_level || Self::spread_level(kind, old_level, face) == level {
+					let own_level = self.own_light(neighbor, kind);
+					self.set(neighbor, kind, own_level);
+					self.decrease_queue.push_back((neighbor, level));
+					if own_level > 0 {
+						self.increase_queue.push_back(neighbor);
+					}
+				} else {
+					self.increase_queue.push_back(neighbor);
+				}
+			}
+		}
+	}
+
+	// lights a chunk which was just added to the world, and spreads its light into the chunks around it
+	pub fn light_new_chunk(&mut self, position: ChunkPos) {
+		let chunk = match self.chunk(position) {
+			Some(chunk) => chunk,
+			None => return,
+		};
+
+		// the chunk could have been lit before it was unloaded, and that light could be out of date
+		chunk.data.light.fill(LightLevel::default());
+		chunk.changed = Some((BlockPos::repeat(0), BlockPos::repeat(CHUNK_SIZE as i64)));
+
+		let min_block = position * CHUNK_SIZE as i64;
+		let max_block = min_block + BlockPos::repeat(CHUNK_SIZE as i64 - 1);
+		let above_loaded = self.is_loaded(position + ChunkPos::new(0, 1, 0));
+		let below_loaded = self.is_loaded(position - ChunkPos::new(0, 1, 0));
+
+		for kind in [LightKind::Sky, LightKind::Block] {
+			if kind == LightKind::Block {
+				for x in min_block.x..=max_block.x {
+					for y in min_block.y..=max_block.y {
+						for z in min_block.z..=max_block.z {
+							let block = BlockPos::new(x, y, z);
+							let own_level = self.own_light(block, kind);
+							if own_level > 0 {
+								self.set(block, kind, own_level);
+								self.increase_queue.push_back(block);
+							}
+						}
+					}
+				}
+			}
+
+			// sunlight comes in from the top if nothing is above the chunk
+			// if a chunk is loaded above this one later, this is fixed by the code below when that chunk is lit
+			if kind == LightKind::Sky && !above_loaded {
+				for x in min_block.x..=max_block.x {
+					for z in min_block.z..=max_block.z {
+						let block = BlockPos::new(x, max_block.y, z);
+						if self.is_transparent(block) {
+							self.set(block, kind, MAX_LIGHT);
+							self.increase_queue.push_back(block);
+						}
+					}
+				}
+			}
+
+			// the light of the blocks around the chunk spreads into it
+			for face in BlockFace::iter() {
+				let offset = face.block_pos_offset();
+				let (min, max) = face_blocks(min_block, max_block, face);
+				for x in min.x..=max.x {
+					for y in min.y..=max.y {
+						for z in min.z..=max.z {
+							self.increase_queue.push_back(BlockPos::new(x, y, z) + offset);
+						}
+					}
+				}
+			}
+
+			self.run_increase(kind);
+
+			// the chunk below assumed the sun was shining on top of it,
+			// so full sky light there is removed wherever this chunk doesn't pass full sky light down
+			if kind == LightKind::Sky && below_loaded {
+				for x in min_block.x..=max_block.x {
+					for z in min_block.z..=max_block.z {
+						let bottom = BlockPos::new(x, min_block.y, z);
+						let below = bottom - BlockPos::new(0, 1, 0);
+						if self.get(below, kind) == Some(MAX_LIGHT) && self.get(bottom, kind) != Some(MAX_LIGHT) {
+							self.set(below, kind, 0);
+							self.decrease_queue.push_back((below, MAX_LIGHT));
+						}
+					}
+				}
+
+				self.run_decrease(kind);
+				self.run_increase(kind);
+			}
+		}
+	}
+
+	// updates the light around a block after it was changed
+	pub fn light_changed_block(&mut self, block: BlockPos) {
+		// the block was just changed, so how it affects light is copied again
+		let (chunk_position, local) = block.as_chunk_block_pos();
+		let world = self.world;
+		let chunk = match self.chunk(chunk_position) {
+			Some(chunk) => chunk,
+			None => return,
+		};
+		{
+			let loaded_chunk = match world.chunks.get(&chunk_position) {
+				Some(loaded_chunk) => loaded_chunk,
+				None => return,
+			};
+			let (transparent, emission) = loaded_chunk.chunk.light_properties(local);
+			chunk.data.transparent[local_index(local)] = transparent;
+			chunk.data.emission[local_index(local)] = emission;
+		}
+
+		let above_loaded = self.is_loaded(chunk_position + ChunkPos::new(0, 1, 0));
+		let is_top = local.y == CHUNK_SIZE as i64 - 1;
+
+		for kind in [LightKind::Sky, LightKind::Block] {
+			let old_level = self.get(block, kind).unwrap();
+			let own_level = self.own_light(block, kind);
+
+			self.set(block, kind, own_level);
+			if old_level > own_level {
+				self.decrease_queue.push_back((block, old_level));
+			}
+			if own_level > 0 {
+				self.increase_queue.push_back(block);
+			}
+
+			if self.is_transparent(block) {
+				// the light around the block can spread into it now
+				for face in BlockFace::iter() {
+					self.increase_queue.push_back(block + face.block_pos_offset());
+				}
+
+				if kind == LightKind::Sky && is_top && !above_loaded {
+					self.set(block, kind, MAX_LIGHT);
+					self.increase_queue.push_back(block);
+				}
+			}
+
+			self.run_decrease(kind);
+			self.run_increase(kind);
+		}
+	}
+
+	// writes the new light back to the chunks,
+	// and returns the boxes of blocks whose light changed in world coordinates, not including max
+	// if the update needed chunks claimed by another update, nothing is written and those chunks are returned instead
+	pub fn finish(self) -> Result<Vec<(BlockPos, BlockPos)>, Vec<ChunkPos>> {
+		if !self.missed.is_empty() {
+			return Err(self.missed);
+		}
+
+		let mut out = Vec::new();
+
+		for (position, chunk) in self.chunks {
+			let chunk = match chunk {
+				Some(chunk) => chunk,
+				None => continue,
+			};
+
+			let (min, max) = match chunk.changed {
+				Some(changed) => changed,
+				None => continue,
+			};
+
+			// the chunk could have been removed while the light was updating
+			if let Some(loaded_chunk) = self.world.chunks.get(&position) {
+				loaded_chunk.chunk.set_light(chunk.data.light);
+				let chunk_block = position * CHUNK_SIZE as i64;
+				out.push((chunk_block + min, chunk_block + max));
+			}
+		}
+
+		Ok(out)
+	}
+}
+
+// the min and max blocks on a face of the box from min_block to max_block, including max
+fn face_blocks(min_block: BlockPos, max_block: BlockPos, face: BlockFace) -> (BlockPos, BlockPos) {
+	let mut min = min_block;
+	let mut max = max_block;
+	match face {
+		BlockFace::XPos => min.x = max.x,
+		BlockFace::XNeg => max.x = min.x,
+		BlockFace::YPos => min.y = max.y,
+		BlockFace::YNeg => max.y = min.y,
+		BlockFace::ZPos => min.z = max.z,
+		BlockFace::ZNeg => max.z = min.z,
+	}
+	(min, max)
+}
+
+// all the light in a chunk is dark until the chunk is lit
+pub fn empty_chunk_light() -> Box<[LightLevel]> {
+	vec![LightLevel::default(); CHUNK_VOLUME].into_boxed_slice()
+}
@@ -1,82 +1,142 @@
+#[cfg(feature = "client")]
 use std::time::{Instant, Duration};
+#[cfg(feature = "client")]
 use std::sync::Arc;
 
+#[cfg(feature = "client")]
 use winit::window::WindowId;
+#[cfg(feature = "client")]
 use winit::{
 	window::Window,
 	event::*,
 	event_loop::ControlFlow,
-	dpi::PhysicalSize,
 };
 
+#[cfg(feature = "client")]
 use world::World;
+#[cfg(feature = "client")]
 use client::Client;
+#[cfg(feature = "client")]
+use network::ServerConnection;
+#[cfg(feature = "client")]
+use clock::TickAccumulator;
 
+#[cfg(feature = "client")]
 mod client;
 mod player;
-mod entity;
+pub mod entity;
 mod block;
-pub use block::{BlockFace, BlockVertex, num_textures};
+pub use block::{BlockFace, BlockVertex, num_texture_layers};
 mod chunk;
 pub use chunk::CHUNK_SIZE;
-mod parallel;
-mod world;
+mod connectivity;
+pub use connectivity::ChunkConnectivity;
+mod block_storage;
+mod light;
+pub mod clock;
+pub mod physics;
+pub mod parallel;
+pub mod world;
 mod worldgen;
+mod storage;
+pub mod network;
 
-// Game is in charge of calling frame_update and physics_update on the correct intervals
+// folder the world is saved in
+#[cfg(feature = "client")]
+const WORLD_FOLDER: &str = "test-world";
+
+// Game is in charge of ticking the world at a fixed rate, drawing frames at the framerate,
 // and dispatching input events
+#[cfg(feature = "client")]
 pub struct Game {
 	window_id: WindowId,
 	frame_time: Duration,
-	last_update_time: Instant,
+	last_frame_time: Instant,
+	ticks: TickAccumulator,
 	world: Arc<World>,
 	client: Client,
 }
 
+#[cfg(feature = "client")]
 impl Game {
-	pub fn new(framerate: u64, window: &Window) -> Self {
+	// if server_address is None a local world is played, otherwise the game connects to the server
+	// the world saves where the player is by player_name
+	pub fn new(framerate: u64, window: &Window, server_address: Option<&str>, player_name: &str) -> Self {
 		let frame_time = Duration::from_micros(1_000_000 / framerate);
 
-		let world = World::new_test().expect("could not load the test world");
+		let (world, connection) = match server_address {
+			Some(address) => {
+				let addresses = network::resolve_address(address).expect("could not find the server");
+				let connection = ServerConnection::connect(addresses.as_slice(), player_name)
+					.expect("could not connect to the server");
+
+				(World::new_remote(connection.block_ids().clone()), Some(connection))
+			},
+			None => {
+				let world = if World::exists(WORLD_FOLDER) {
+					World::open(WORLD_FOLDER)
+				} else {
+					World::create(WORLD_FOLDER, 0)
+				}.expect("could not load the world");
+
+				(world, None)
+			},
+		};
 		// leave 1 for the main thread
 		parallel::init(world.clone(), num_cpus::get() - 1);
 
-		let client = Client::new(window, world.clone());
+		let client = Client::new(window, world.clone(), connection, player_name);
 
 		Self {
 			window_id: window.id(),
 			frame_time,
-			last_update_time: Instant::now() - frame_time,
+			last_frame_time: Instant::now() - frame_time,
+			ticks: TickAccumulator::new(),
 			world,
 			client,
 		}
 	}
 
-	pub fn input(&mut self, event: &WindowEvent) {
-		self.client.input(event);
+	// saves the world before the game closes
+	pub fn exit(&mut self) -> ControlFlow {
+		self.client.disconnect();
+		if let Err(error) = self.world.save() {
+			error!("could not save the world: {}", error);
+		}
+		ControlFlow::Exit
 	}
 
-	// TODO: implement correctly, with redrawing every so often
-	pub fn frame_update(&mut self, new_window_size: Option<PhysicalSize<u32>>) {
-		self.client.frame_update(new_window_size);
+	pub fn input(&mut self, event: &WindowEvent) {
+		self.client.input(event);
 	}
 
-	pub fn try_physics_update(&mut self) -> ControlFlow {
+	// runs the ticks that are due since the last frame, then draws a frame
+	pub fn frame_update(&mut self) {
 		let current_time = Instant::now();
-		let time_delta = current_time - self.last_update_time;
+		let frame_delta = current_time - self.last_frame_time;
+		self.last_frame_time = current_time;
+
+		self.ticks.add_time(frame_delta);
+		while self.ticks.take_tick() {
+			self.client.tick();
+		}
+
+		self.client.frame_update(frame_delta, self.ticks.tick_progress());
+	}
 
-		if time_delta > self.frame_time {
-			self.client.physics_update(time_delta);
-			self.last_update_time = current_time;
+	// draws a frame if it is time for the next one
+	pub fn try_frame_update(&mut self) -> ControlFlow {
+		if Instant::now() - self.last_frame_time >= self.frame_time {
+			self.frame_update();
 		}
-		ControlFlow::WaitUntil(self.last_update_time + self.frame_time)
+		ControlFlow::WaitUntil(self.last_frame_time + self.frame_time)
 	}
 
 	pub fn event_update(&mut self, event: Event<()>) -> ControlFlow {
 		match event {
 			Event::RedrawRequested(window_id) if window_id == self.window_id => {
-				self.frame_update(None);
-				self.try_physics_update()
+				self.frame_update();
+				ControlFlow::WaitUntil(self.last_frame_time + self.frame_time)
 			},
 			Event::WindowEvent {
 				ref event,
@@ -92,14 +152,14 @@ impl Game {
 								..
 							},
 						..
-					} => return ControlFlow::Exit,
-					WindowEvent::Resized(new_size) => self.frame_update(Some(*new_size)),
-					WindowEvent::ScaleFactorChanged { new_inner_size, .. } => self.frame_update(Some(**new_inner_size)),
+					} => return self.exit(),
+					WindowEvent::Resized(new_size) => self.client.resize(*new_size),
+					WindowEvent::ScaleFactorChanged { new_inner_size, .. } => self.client.resize(**new_inner_size),
 					_ => self.input(event),
 				}
-				self.try_physics_update()
+				self.try_frame_update()
 			},
-			_ => self.try_physics_update(),
+			_ => self.try_frame_update(),
 		}
 	}
 }
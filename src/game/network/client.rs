@@ -0,0 +1,164 @@
+use std::{
+	io::BufWriter,
+	net::{TcpStream, ToSocketAddrs, Shutdown},
+	thread,
+	time::Duration,
+};
+
+use anyhow::{Result, bail};
+use crossbeam::channel::{self, Receiver, TryRecvError};
+
+use crate::prelude::*;
+use super::protocol::{ClientMessage, ServerMessage, PROTOCOL_VERSION, read_message, write_message};
+use crate::game::{
+	world::World,
+	block::{BlockIdMap, BlockState},
+	storage::decompress,
+};
+
+// how long to wait for the server to answer the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// connection from a client to a server
+pub struct ServerConnection {
+	writer: BufWriter<TcpStream>,
+	// messages read by the reader thread
+	incoming: Receiver<ServerMessage>,
+	player_id: u64,
+	// position the server placed the player at when joining
+	spawn_position: Position,
+	// the world clock of the server when joining
+	time: u64,
+	// the block ids used by the server, which the remote world has to be created with
+	block_ids: BlockIdMap,
+}
+
+impl ServerConnection {
+	// connects to the server and waits for the handshake to finish
+	pub fn connect<A: ToSocketAddrs>(address: A, player_name: &str) -> Result<Self> {
+		let mut stream = TcpStream::connect(address)?;
+		stream.set_nodelay(true)?;
+		stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+		write_message(&mut stream, &ClientMessage::Handshake {
+			protocol_version: PROTOCOL_VERSION,
+			player_name: player_name.to_owned(),
+		})?;
+
+		let (player_id, spawn_position, block_names, time) = match read_message(&mut stream)? {
+			ServerMessage::HandshakeAccepted { player_id, position, block_names, time } => (player_id, position.into(), block_names, time),
+			ServerMessage::HandshakeRejected { reason } => bail!("server rejected connection: {}", reason),
+			message => bail!("server sent {:?} instead of answering the handshake", message),
+		};
+
+		let block_ids = BlockIdMap::new(block_names)?;
+		let missing_names = block_ids.missing_names().collect::<Vec<_>>();
+		if !missing_names.is_empty() {
+			warn!("server has blocks which this client doesn't have, they will look like air: {}", missing_names.join(", "));
+		}
+
+		stream.set_read_timeout(None)?;
+		let writer = BufWriter::new(stream.try_clone()?);
+
+		let (sender, incoming) = channel::unbounded();
+		thread::spawn(move || {
+			// the channel is closed when this thread exits, which tells the client the server is gone
+			loop {
+				match read_message(&mut stream) {
+					Ok(message) => if sender.send(message).is_err() {
+						return;
+					},
+					Err(error) => {
+						info!("disconnected from server: {}", error);
+						return;
+					},
+				}
+			}
+		});
+
+		Ok(Self {
+			writer,
+			incoming,
+			player_id,
+			spawn_position,
+			time,
+			block_ids,
+		})
+	}
+
+	pub fn player_id(&self) -> u64 {
+		self.player_id
+	}
+
+	pub fn spawn_position(&self) -> Position {
+		self.spawn_position
+	}
+
+	pub fn time(&self) -> u64 {
+		self.time
+	}
+
+	pub fn block_ids(&self) -> &BlockIdMap {
+		&self.block_ids
+	}
+
+	pub fn send(&mut self, message: &ClientMessage) -> Result<()> {
+		write_message(&mut self.writer, message)
+	}
+
+	// returns the next message from the server without blocking, or an error if the server has closed the connection
+	pub fn try_recv(&self) -> Result<Option<ServerMessage>> {
+		match self.incoming.try_recv() {
+			Ok(message) => Ok(Some(message)),
+			Err(TryRecvError::Empty) => Ok(None),
+			Err(TryRecvError::Disconnected) => bail!("lost connection to the server"),
+		}
+	}
+
+	// waits up to timeout for the next message from the server
+	pub fn recv_timeout(&self, timeout: Duration) -> Option<ServerMessage> {
+		self.incoming.recv_timeout(timeout).ok()
+	}
+
+	// applies all the messages recieved from the server to the remote copy of the world
+	// returns true if the mesh should be updated
+	pub fn update_world(&self, world: &World) -> Result<bool> {
+		let mut update_mesh = false;
+
+		while let Some(message) = self.try_recv()? {
+			match message {
+				ServerMessage::ChunkData { position, data } => {
+					let position = ChunkPos::from(position);
+					if let Err(error) = world.insert_remote_chunk(position, &decompress(&data)?) {
+						error!("server sent invalid chunk {:?}: {}", position, error);
+					}
+				},
+				ServerMessage::UnloadChunk(position) => world.remove_remote_chunk(position.into()),
+				ServerMessage::BlockChange { position, block_id, block_state } => {
+					let position = BlockPos::from(position);
+					let block = world.block_ids().block(block_id, BlockState::from_bits(block_state));
+					match block {
+						Some(block) => if world.set_block(position, block) {
+							update_mesh = true;
+						},
+						None => error!("server sent invalid block id {} with state {}", block_id, block_state),
+					}
+				},
+				// TODO: render other players
+				ServerMessage::PlayerPosition { .. } => (),
+				ServerMessage::PlayerDisconnected(_) => (),
+				ServerMessage::Time(time) => world.set_time(time),
+				ServerMessage::HandshakeAccepted { .. } | ServerMessage::HandshakeRejected { .. } => {
+					bail!("server sent a handshake message after the handshake was finished");
+				},
+			}
+		}
+
+		Ok(update_mesh)
+	}
+
+	pub fn disconnect(mut self) {
+		let _ = self.send(&ClientMessage::Disconnect);
+		let _ = self.writer.get_ref().shutdown(Shutdown::Both);
+	}
+}
@@ -0,0 +1,181 @@
+// lets clients play on a world running in another process
+//
+// the server sends the client every chunk in range of its player, and any changes to those chunks,
+// the client keeps a remote copy of the world made of only those chunks which it uses for rendering
+pub mod protocol;
+mod server;
+pub use server::Server;
+mod client;
+pub use client::ServerConnection;
+
+use std::net::{SocketAddr, IpAddr, ToSocketAddrs};
+
+use anyhow::Result;
+
+// port used when the server address doesn't have one
+pub const DEFAULT_PORT: u16 = 25566;
+
+// looks up a server address given as an ip address or host name, with or without a port
+pub fn resolve_address(address: &str) -> Result<Vec<SocketAddr>> {
+	if let Ok(address) = address.parse::<SocketAddr>() {
+		return Ok(vec![address]);
+	}
+
+	// ipv6 addresses contain ':' without having a port, and may be in brackets
+	if let Ok(ip) = address.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+		return Ok(vec![SocketAddr::new(ip, DEFAULT_PORT)]);
+	}
+
+	let host_and_port = address.rsplit_once(':')
+		.and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)));
+	let addresses = match host_and_port {
+		Some(host_and_port) => host_and_port.to_socket_addrs()?,
+		None => (address, DEFAULT_PORT).to_socket_addrs()?,
+	};
+	Ok(addresses.collect())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		thread,
+		sync::Arc,
+		sync::atomic::{AtomicBool, Ordering},
+		time::{Duration, Instant},
+	};
+
+	use super::*;
+	use super::protocol::ClientMessage;
+	use crate::prelude::*;
+	use crate::util::TestFolder;
+	use crate::game::{
+		world::World,
+		parallel,
+		clock,
+		block::{Block, Air, Stone},
+		CHUNK_SIZE,
+	};
+
+	fn wait_until<F: FnMut() -> bool>(mut f: F) {
+		let start = Instant::now();
+		while !f() {
+			assert!(start.elapsed() < Duration::from_secs(60), "timed out");
+			thread::sleep(Duration::from_millis(10));
+		}
+	}
+
+	#[test]
+	fn resolve_addresses() {
+		let resolve = |address| resolve_address(address).unwrap();
+		assert_eq!(resolve("127.0.0.1:5000"), vec!["127.0.0.1:5000".parse().unwrap()]);
+		assert_eq!(resolve("127.0.0.1"), vec![SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT))]);
+		assert_eq!(resolve("[::1]:5000"), vec!["[::1]:5000".parse().unwrap()]);
+		assert_eq!(resolve("::1"), vec![SocketAddr::new("::1".parse().unwrap(), DEFAULT_PORT)]);
+		assert_eq!(resolve("[::1]"), vec![SocketAddr::new("::1".parse().unwrap(), DEFAULT_PORT)]);
+		assert!(resolve("localhost").iter().all(|address| address.port() == DEFAULT_PORT));
+		assert!(resolve("localhost:5000").iter().all(|address| address.port() == 5000));
+	}
+
+	// runs a server and a client in the same process over localhost
+	#[test]
+	fn loopback() {
+		let folder = TestFolder::new("loopback-test");
+
+		let world = World::create(&folder, 0).unwrap();
+		parallel::init(world.clone(), 2);
+
+		let mut server = Server::bind(world.clone(), "127.0.0.1:0").unwrap();
+		let address = server.local_address();
+
+		let running = Arc::new(AtomicBool::new(true));
+		let server_thread = {
+			let running = running.clone();
+			let world = world.clone();
+			thread::spawn(move || {
+				while running.load(Ordering::Acquire) {
+					server.tick();
+					world.poll_completed_tasks();
+					thread::sleep(Duration::from_millis(5));
+				}
+			})
+		};
+
+		let mut connection = ServerConnection::connect(address, "test player").unwrap();
+
+		// only 1 player with each name can be in the world
+		assert!(ServerConnection::connect(address, "test player").is_err());
+		let remote_world = World::new_remote(connection.block_ids().clone());
+		assert_eq!(connection.time(), world.time());
+
+		// the chunk the player is in should eventually be sent to the client
+		let chunk_position = connection.spawn_position().into_chunk_pos();
+		wait_until(|| {
+			connection.update_world(&remote_world).unwrap();
+			remote_world.chunks.contains_key(&chunk_position)
+		});
+		assert_eq!(
+			remote_world.chunks.get(&chunk_position).unwrap().chunk.to_bytes(),
+			world.chunks.get(&chunk_position).unwrap().chunk.to_bytes(),
+		);
+
+		// changing a block should change it on the server and send the change back to the client
+		// the player has to be able to reach the block, so it is the one at the player's eyes
+		let block_position = connection.spawn_position().into_block_pos();
+		let get_block = |world: &World, position: BlockPos| -> Block {
+			world.get_block(position).unwrap()
+		};
+		let changed_block = |position: BlockPos| -> Block {
+			if get_block(&world, position).is_air() {
+				Stone::new().into()
+			} else {
+				Air::new().into()
+			}
+		};
+		let set_block = |connection: &mut ServerConnection, position: BlockPos, block: &Block| {
+			connection.send(&ClientMessage::SetBlock {
+				position: position.into(),
+				block_id: remote_world.block_ids().saved_id(block),
+				block_state: block.state().bits(),
+			}).unwrap();
+		};
+
+		// blocks out of reach of the player can't be changed, even in chunks the player has loaded
+		let far_position = block_position + BlockPos::new(0, 0, 3 * CHUNK_SIZE as i64);
+		wait_until(|| world.chunks.contains_key(&far_position.as_chunk_pos()));
+		let far_block = get_block(&world, far_position);
+		set_block(&mut connection, far_position, &changed_block(far_position));
+
+		let new_block = changed_block(block_position);
+		set_block(&mut connection, block_position, &new_block);
+		wait_until(|| {
+			connection.update_world(&remote_world).unwrap();
+			get_block(&remote_world, block_position) == new_block
+		});
+		assert!(get_block(&world, block_position) == new_block);
+		assert!(get_block(&world, far_position) == far_block);
+
+		// blocks changed by the world itself, like a falling block landing, are sent to the client too
+		let world_block_position = chunk_position * CHUNK_SIZE as i64;
+		let world_block = changed_block(world_block_position);
+		assert!(world.set_block(world_block_position, world_block.clone()));
+		wait_until(|| {
+			connection.update_world(&remote_world).unwrap();
+			get_block(&remote_world, world_block_position) == world_block
+		});
+
+		// the server keeps the clock of the client in sync
+		world.set_time_of_day(clock::MIDNIGHT);
+		wait_until(|| {
+			connection.update_world(&remote_world).unwrap();
+			remote_world.time() == world.time()
+		});
+
+		// the player should be removed from the world once the client disconnects
+		connection.disconnect();
+		wait_until(|| world.player_positions().is_empty());
+
+		running.store(false, Ordering::Release);
+		server_thread.join().unwrap();
+		world.tasks.stop();
+	}
+}
@@ -0,0 +1,96 @@
+use std::io::{Read, Write};
+
+use anyhow::{Result, bail};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+// version of the network protocol, clients and servers with different versions can't talk to each other
+// this must be incramented whenever a message changes
+pub const PROTOCOL_VERSION: u32 = 6;
+
+// messages bigger than this are treated as an error instead of allocating a huge buffer
+const MAX_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
+
+// messages sent by the client to the server
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+	// must be the first message sent after connecting
+	Handshake {
+		protocol_version: u32,
+		// the server saves where each player is by name
+		player_name: String,
+	},
+	PlayerPosition([f64; 3]),
+	// the direction the player is looking in, used to send chunks in front of the player first
+	PlayerDirection([f64; 3]),
+	// block ids are the ids the server saves blocks with
+	SetBlock {
+		position: [i64; 3],
+		block_id: u16,
+		block_state: u8,
+	},
+	Disconnect,
+}
+
+// messages sent by the server to the client
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+	HandshakeAccepted {
+		player_id: u64,
+		position: [f64; 3],
+		// name of the block with each id the server uses in chunk data and block changes
+		block_names: Vec<String>,
+		// the world clock of the server
+		time: u64,
+	},
+	// the connection is closed after this is sent
+	HandshakeRejected {
+		reason: String,
+	},
+	// zlib compressed chunk data in the same format that is used to save chunks
+	ChunkData {
+		position: [i64; 3],
+		data: Vec<u8>,
+	},
+	UnloadChunk([i64; 3]),
+	BlockChange {
+		position: [i64; 3],
+		block_id: u16,
+		block_state: u8,
+	},
+	// position of another player
+	PlayerPosition {
+		player_id: u64,
+		position: [f64; 3],
+	},
+	PlayerDisconnected(u64),
+	// sent every second so the clock of the client doesn't drift away from the server's
+	Time(u64),
+}
+
+// each message is sent as a little endian u32 length followed by the bincode encoded message
+pub fn write_message<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<()> {
+	let data = bincode::serialize(message)?;
+	let len: u32 = data.len().try_into()?;
+	if len > MAX_MESSAGE_SIZE {
+		bail!("message is {} bytes, which is bigger than the maximum of {} bytes", len, MAX_MESSAGE_SIZE);
+	}
+
+	writer.write_all(&len.to_le_bytes())?;
+	writer.write_all(&data)?;
+	writer.flush()?;
+	Ok(())
+}
+
+// blocks until a whole message has been read
+pub fn read_message<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<T> {
+	let mut len_bytes = [0; 4];
+	reader.read_exact(&mut len_bytes)?;
+	let len = u32::from_le_bytes(len_bytes);
+	if len > MAX_MESSAGE_SIZE {
+		bail!("recieved message is {} bytes, which is bigger than the maximum of {} bytes", len, MAX_MESSAGE_SIZE);
+	}
+
+	let mut data = vec![0; len as usize];
+	reader.read_exact(&mut data)?;
+	Ok(bincode::deserialize(&data)?)
+}
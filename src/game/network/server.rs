@@ -0,0 +1,397 @@
+use std::{
+	io::BufWriter,
+	net::{TcpListener, TcpStream, SocketAddr, ToSocketAddrs, Shutdown},
+	sync::Arc,
+	thread,
+	time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use crossbeam::channel::{self, Receiver, Sender, TryRecvError, TrySendError};
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::prelude::*;
+use super::protocol::{ClientMessage, ServerMessage, PROTOCOL_VERSION, read_message, write_message};
+use crate::game::{
+	world::World,
+	player::{PlayerId, in_reach},
+	block::{Block, BlockState},
+	storage::compress,
+	clock::TICKS_PER_SECOND,
+};
+
+// the client has this long to send the handshake after connecting
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PLAYER_NAME_LENGTH: usize = 32;
+// limits how many chunks are sent to each client every tick so one client can't stall the server
+const MAX_CHUNKS_PER_TICK: usize = 16;
+// a client with this many messages waiting to be written isn't reading them, and is disconnected
+const MAX_QUEUED_MESSAGES: usize = 1024;
+// new chunks aren't sent to a client while it has more than this many messages waiting,
+// so a client on a slow connection gets its chunks slower instead of being disconnected
+const MAX_QUEUED_BEFORE_CHUNKS: usize = 64;
+
+// a client which has finished the handshake
+struct RemoteClient {
+	player_id: PlayerId,
+	address: SocketAddr,
+	// only used to close the connection, which makes the reader and writer threads exit
+	stream: TcpStream,
+	// messages waiting to be written by the writer thread of this client
+	// messages are written on another thread so a client that stops reading can't block the server tick
+	outgoing: Sender<ServerMessage>,
+	// messages read by the reader thread of this client
+	incoming: Receiver<ClientMessage>,
+	// chunks the client currently has a copy of
+	sent_chunks: FxHashSet<ChunkPos>,
+	// the last position of each other player which was sent to the client, so only players that moved are sent again
+	sent_positions: FxHashMap<PlayerId, Position>,
+}
+
+impl RemoteClient {
+	fn send(&mut self, message: ServerMessage) -> Result<()> {
+		match self.outgoing.try_send(message) {
+			Ok(()) => Ok(()),
+			Err(TrySendError::Full(_)) => bail!("client is not reading messages fast enough"),
+			Err(TrySendError::Disconnected(_)) => bail!("connection closed"),
+		}
+	}
+}
+
+// accepts clients over tcp and keeps them in sync with the world
+//
+// connections are accepted on a background thread, everything else happens in tick,
+// which should be called once every server tick
+pub struct Server {
+	world: Arc<World>,
+	local_address: SocketAddr,
+	new_clients: Receiver<RemoteClient>,
+	clients: Vec<RemoteClient>,
+	// number of times tick has been called
+	ticks: u64,
+}
+
+impl Server {
+	// starts listening for clients on the given address
+	pub fn bind<A: ToSocketAddrs>(world: Arc<World>, address: A) -> Result<Self> {
+		let listener = TcpListener::bind(address)?;
+		let local_address = listener.local_addr()?;
+		info!("listening for clients on {}", local_address);
+
+		let (sender, new_clients) = channel::unbounded();
+		let thread_world = world.clone();
+		thread::spawn(move || accept_clients(thread_world, listener, sender));
+		world.track_changed_blocks();
+
+		Ok(Self {
+			world,
+			local_address,
+			new_clients,
+			clients: Vec::new(),
+			ticks: 0,
+		})
+	}
+
+	// the address the server is listening on, this is useful if it was bound to port 0
+	pub fn local_address(&self) -> SocketAddr {
+		self.local_address
+	}
+
+	pub fn client_count(&self) -> usize {
+		self.clients.len()
+	}
+
+	// handles all messages from clients and sends them any changes to the world
+	pub fn tick(&mut self) {
+		self.clients.extend(self.new_clients.try_iter());
+
+		let mut disconnected = Vec::new();
+		let send_time = self.ticks % TICKS_PER_SECOND == 0;
+		self.ticks += 1;
+
+		for (i, client) in self.clients.iter_mut().enumerate() {
+			if let Err(error) = Self::handle_messages(&self.world, client) {
+				info!("client {} disconnected: {}", client.address, error);
+				disconnected.push(i);
+			}
+		}
+
+		// the blocks changed by clients and by the world since the last tick, which are sent to every client that has the chunk
+		// a block whose chunk was unloaded since it changed isn't in range of any client anymore
+		let block_changes = self.world.take_changed_blocks().into_iter()
+			.filter_map(|position| Some((position, self.world.get_block(position)?)))
+			.collect::<Vec<_>>();
+
+		for (i, client) in self.clients.iter_mut().enumerate() {
+			if disconnected.contains(&i) {
+				continue;
+			}
+
+			if let Err(error) = Self::update_client(&self.world, client, &block_changes, send_time) {
+				info!("could not send updates to client {}, disconnecting it: {}", client.address, error);
+				disconnected.push(i);
+			}
+		}
+
+		disconnected.sort_unstable();
+		for i in disconnected.into_iter().rev() {
+			let client = self.clients.remove(i);
+			// makes the reader and writer threads of the client exit
+			let _ = client.stream.shutdown(Shutdown::Both);
+			self.world.disconnect(client.player_id);
+
+			for other_client in self.clients.iter_mut() {
+				other_client.sent_positions.remove(&client.player_id);
+				// if this fails the client will be removed on the next tick
+				let _ = other_client.send(ServerMessage::PlayerDisconnected(client.player_id.as_u64()));
+			}
+		}
+	}
+
+	// returns an error if the client has disconnected
+	fn handle_messages(world: &World, client: &mut RemoteClient) -> Result<()> {
+		loop {
+			let message = match client.incoming.try_recv() {
+				Ok(message) => message,
+				Err(TryRecvError::Empty) => return Ok(()),
+				Err(TryRecvError::Disconnected) => bail!("connection closed"),
+			};
+
+			match message {
+				ClientMessage::Handshake { .. } => bail!("sent a second handshake"),
+				ClientMessage::PlayerPosition(position) => {
+					world.set_player_position(client.player_id, position.into());
+				},
+				ClientMessage::PlayerDirection(direction) => {
+					world.set_player_direction(client.player_id, direction.into());
+				},
+				ClientMessage::SetBlock { position, block_id, block_state } => {
+					let position = BlockPos::from(position);
+					let block = world.block_ids().block(block_id, BlockState::from_bits(block_state));
+					let block = match block {
+						Some(block) => block,
+						None => bail!("sent invalid block id {} with state {}", block_id, block_state),
+					};
+
+					// a client can only change blocks its player can reach, so it can't edit chunks loaded for other players
+					// the client has already changed the block on its side, so it is sent the real block again
+					let player_position = world.get_player_position(client.player_id);
+					if !matches!(player_position, Some(player_position) if in_reach(player_position, position)) {
+						if let Some(block) = world.get_block(position) {
+							if client.sent_chunks.contains(&position.as_chunk_pos()) {
+								client.send(block_change(world, position, &block))?;
+							}
+						}
+						continue;
+					}
+
+					// changes to chunks that aren't loaded are ignored
+					world.set_block(position, block);
+				},
+				ClientMessage::Disconnect => bail!("client disconnected"),
+			}
+		}
+	}
+
+	// sends block changes, other players, chunks that have come in or out of range, and the time if send_time is set to the client
+	fn update_client(world: &World, client: &mut RemoteClient, block_changes: &[(BlockPos, Block)], send_time: bool) -> Result<()> {
+		if send_time {
+			client.send(ServerMessage::Time(world.time()))?;
+		}
+
+		for (position, block) in block_changes.iter() {
+			if client.sent_chunks.contains(&position.as_chunk_pos()) {
+				client.send(block_change(world, *position, block))?;
+			}
+		}
+
+		for (player_id, position) in world.player_positions() {
+			if player_id != client.player_id && client.sent_positions.get(&player_id) != Some(&position) {
+				client.send(ServerMessage::PlayerPosition {
+					player_id: player_id.as_u64(),
+					position: position.into(),
+				})?;
+				client.sent_positions.insert(player_id, position);
+			}
+		}
+
+		let (min_chunk, max_chunk) = match world.player_load_area(client.player_id) {
+			Some(area) => area,
+			None => bail!("player is not in the world"),
+		};
+		let in_range = |chunk: &ChunkPos| {
+			chunk.x >= min_chunk.x && chunk.y >= min_chunk.y && chunk.z >= min_chunk.z
+				&& chunk.x < max_chunk.x && chunk.y < max_chunk.y && chunk.z < max_chunk.z
+		};
+
+		let out_of_range = client.sent_chunks.iter()
+			.filter(|chunk| !in_range(chunk))
+			.copied()
+			.collect::<Vec<_>>();
+		for chunk in out_of_range {
+			client.sent_chunks.remove(&chunk);
+			client.send(ServerMessage::UnloadChunk(chunk.into()))?;
+		}
+
+		let mut sent_count = 0;
+		'outer: for x in min_chunk.x..max_chunk.x {
+			for y in min_chunk.y..max_chunk.y {
+				for z in min_chunk.z..max_chunk.z {
+					if sent_count == MAX_CHUNKS_PER_TICK || client.outgoing.len() > MAX_QUEUED_BEFORE_CHUNKS {
+						break 'outer;
+					}
+
+					let position = ChunkPos::new(x, y, z);
+					if client.sent_chunks.contains(&position) {
+						continue;
+					}
+
+					// chunks that are still being generated are sent on a later tick
+					let data = match world.chunks.get(&position) {
+						Some(chunk) => chunk.chunk.to_bytes(),
+						None => continue,
+					};
+
+					client.send(ServerMessage::ChunkData {
+						position: position.into(),
+						data: compress(&data)?,
+					})?;
+					client.sent_chunks.insert(position);
+					sent_count += 1;
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
+
+fn block_change(world: &World, position: BlockPos, block: &Block) -> ServerMessage {
+	ServerMessage::BlockChange {
+		position: position.into(),
+		block_id: world.block_ids().saved_id(block),
+		block_state: block.state().bits(),
+	}
+}
+
+fn accept_clients(world: Arc<World>, listener: TcpListener, new_clients: Sender<RemoteClient>) {
+	for stream in listener.incoming() {
+		let stream = match stream {
+			Ok(stream) => stream,
+			Err(error) => {
+				warn!("could not accept client: {}", error);
+				continue;
+			},
+		};
+
+		// each handshake runs on its own thread so a slow client can't stop others from joining
+		let world = world.clone();
+		let new_clients = new_clients.clone();
+		thread::spawn(move || match handshake(&world, stream) {
+			Ok(client) => {
+				// the server has been dropped
+				if let Err(error) = new_clients.send(client) {
+					let client = error.into_inner();
+					let _ = client.stream.shutdown(Shutdown::Both);
+					world.disconnect(client.player_id);
+				}
+			},
+			Err(error) => warn!("client failed handshake: {}", error),
+		});
+	}
+}
+
+// waits for the handshake from the client, adds the player to the world and starts the reader thread for the client
+fn handshake(world: &World, mut stream: TcpStream) -> Result<RemoteClient> {
+	let address = stream.peer_addr()?;
+	stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+	stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+	stream.set_nodelay(true)?;
+
+	let player_name = match read_message(&mut stream)? {
+		ClientMessage::Handshake { protocol_version, player_name } if protocol_version == PROTOCOL_VERSION => player_name,
+		ClientMessage::Handshake { protocol_version, .. } => {
+			write_message(&mut stream, &ServerMessage::HandshakeRejected {
+				reason: format!("server uses protocol version {}, but the client uses version {}", PROTOCOL_VERSION, protocol_version),
+			})?;
+			bail!("client {} uses protocol version {}", address, protocol_version);
+		},
+		message => bail!("client {} sent {:?} instead of a handshake", address, message),
+	};
+
+	let player_id = if player_name.is_empty() || player_name.chars().count() > MAX_PLAYER_NAME_LENGTH {
+		Err(anyhow!("player names must be between 1 and {} characters long", MAX_PLAYER_NAME_LENGTH))
+	} else {
+		world.connect(&player_name)
+	};
+	let player_id = match player_id {
+		Ok(player_id) => player_id,
+		Err(error) => {
+			write_message(&mut stream, &ServerMessage::HandshakeRejected {
+				reason: error.to_string(),
+			})?;
+			bail!("client {} can't join as {}: {}", address, player_name, error);
+		},
+	};
+	// the player can only be missing if something else disconnected it already
+	let position = match world.get_player_position(player_id) {
+		Some(position) => position,
+		None => {
+			world.disconnect(player_id);
+			bail!("player {} for client {} was removed before the handshake finished", player_id.as_u64(), address);
+		},
+	};
+
+	let mut writer = BufWriter::new(stream.try_clone()?);
+	if let Err(error) = write_message(&mut writer, &ServerMessage::HandshakeAccepted {
+		player_id: player_id.as_u64(),
+		position: position.into(),
+		block_names: world.block_ids().names().to_vec(),
+		time: world.time(),
+	}) {
+		world.disconnect(player_id);
+		return Err(error);
+	}
+
+	stream.set_read_timeout(None)?;
+	stream.set_write_timeout(None)?;
+
+	let (outgoing, outgoing_receiver) = channel::bounded(MAX_QUEUED_MESSAGES);
+	thread::spawn(move || {
+		// the channel is closed when this thread exits, which tells the server the client is gone
+		for message in outgoing_receiver.iter() {
+			if write_message(&mut writer, &message).is_err() {
+				break;
+			}
+		}
+		// makes the reader thread exit if the write failed
+		let _ = writer.get_ref().shutdown(Shutdown::Both);
+	});
+
+	let (sender, incoming) = channel::unbounded();
+	let reader_stream = stream.try_clone()?;
+	thread::spawn(move || {
+		let mut stream = reader_stream;
+		// the channel is closed when this thread exits, which tells the server the client is gone
+		loop {
+			match read_message(&mut stream) {
+				Ok(message) => if sender.send(message).is_err() {
+					return;
+				},
+				Err(_) => return,
+			}
+		}
+	});
+
+	info!("client {} connected as {} with player id {}", address, player_name, player_id.as_u64());
+
+	Ok(RemoteClient {
+		player_id,
+		address,
+		stream,
+		outgoing,
+		incoming,
+		sent_chunks: FxHashSet::default(),
+		sent_positions: FxHashMap::default(),
+	})
+}
@@ -1,29 +1,28 @@
-use std::lazy::SyncLazy;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::Duration;
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::mem;
 
-use crossbeam::{
-	deque::{Injector, Steal},
-	queue::SegQueue,
-};
+use crossbeam::queue::SegQueue;
+use parking_lot::Mutex;
+use nalgebra::Vector3;
+use rustc_hash::FxHashMap;
 
 use crate::prelude::*;
-use super::{world::World, BlockFace};
-use super::chunk::{VisitedBlockMap, CHUNK_SIZE};
+use super::world::World;
+use super::chunk::MeshLayers;
 
-static TASK_QUEUE: SyncLazy<Injector<Task>> = SyncLazy::new(|| Injector::new());
-static COMPLETED_TASKS: SegQueue<Task> = SegQueue::new();
-
-// TODO: allow easy way of chaining tasks
 #[derive(Debug, Clone)]
 pub enum Task {
 	// generate a mesh for the given chunk
 	ChunkMesh(ChunkPos),
-	ChunkMeshFace {
-		min_chunk: ChunkPos,
-		max_chunk: ChunkPos,
-		face: BlockFace,
+	// regenerate only some layers of the mesh of a chunk, after blocks they depend on have changed
+	ChunkMeshLayers {
+		chunk: ChunkPos,
+		layers: MeshLayers,
 	},
 	// use world generate to generate chunk
 	GenerateChunk(ChunkPos),
@@ -31,6 +30,275 @@ pub enum Task {
 		min_chunk: ChunkPos,
 		max_chunk: ChunkPos,
 	},
+	// write all modified chunks which have been unloaded to disk
+	SaveCachedChunks,
+}
+
+// called once the task has finished, on the thread which calls World::poll_completed_tasks
+pub type TaskCallback = Box<dyn FnOnce(&World) + Send>;
+
+// refers to a task which was added to a task queue
+// handles are never reused, so a handle to a finished task stays finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskHandle(u64);
+
+// position and look direction of a player, used to decide which tasks to run first
+#[derive(Debug, Clone, Copy)]
+pub struct Viewer {
+	pub chunk: ChunkPos,
+	// normalized, or zero if the player hasn't said which way they are looking
+	pub direction: Vector3<f64>,
+	pub min_chunk: ChunkPos,
+	pub max_chunk: ChunkPos,
+}
+
+impl Viewer {
+	fn contains(&self, chunk: ChunkPos) -> bool {
+		chunk.x >= self.min_chunk.x && chunk.y >= self.min_chunk.y && chunk.z >= self.min_chunk.z
+			&& chunk.x < self.max_chunk.x && chunk.y < self.max_chunk.y && chunk.z < self.max_chunk.z
+	}
+
+	// lower numbers run first
+	fn priority(&self, chunk: ChunkPos) -> f64 {
+		let offset = (chunk - self.chunk).cast::<f64>();
+		let distance = offset.magnitude();
+		if distance == 0.0 {
+			return 0.0;
+		}
+
+		// chunks behind the player are treated as twice as far away as chunks in front of them
+		let facing = self.direction.dot(&offset) / distance;
+		distance * (1.5 - 0.5 * facing)
+	}
+}
+
+// lower numbers run first
+fn task_priority(viewers: &[Viewer], task: &Task) -> f64 {
+	let chunk = match task {
+		Task::ChunkMesh(chunk) | Task::GenerateChunk(chunk) | Task::ChunkMeshLayers { chunk, .. } => *chunk,
+		// unloading and saving are never urgent, so they run after everything else
+		Task::UnloadChunks { .. } | Task::SaveCachedChunks => return f64::INFINITY,
+	};
+
+	// worlds with no players, like the copy of a world on a client, just run tasks in order
+	if viewers.is_empty() {
+		return 0.0;
+	}
+
+	// chunks out of range of every player have most likely been unloaded already,
+	// so they run last and are usually cancelled once they are popped
+	viewers.iter()
+		.filter(|viewer| viewer.contains(chunk))
+		.map(|viewer| viewer.priority(chunk))
+		.min_by(f64::total_cmp)
+		.unwrap_or(f64::INFINITY)
+}
+
+#[derive(Debug)]
+struct QueuedTask {
+	priority: f64,
+	// handles are given out in order, so tasks with the same priority run in the order they were added
+	handle: TaskHandle,
+}
+
+impl PartialEq for QueuedTask {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == cmp::Ordering::Equal
+	}
+}
+
+impl Eq for QueuedTask {}
+
+impl PartialOrd for QueuedTask {
+	fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for QueuedTask {
+	// BinaryHeap pops the greatest element first, so lower priorities and earlier tasks compare as greater
+	fn cmp(&self, other: &Self) -> cmp::Ordering {
+		other.priority.total_cmp(&self.priority)
+			.then(other.handle.cmp(&self.handle))
+	}
+}
+
+struct TaskNode {
+	// taken out once the task starts running
+	task: Option<Task>,
+	// number of tasks this task is waiting on
+	remaining_dependencies: usize,
+	// tasks waiting on this task
+	dependents: Vec<TaskHandle>,
+	on_complete: Option<TaskCallback>,
+}
+
+// every task which hasn't finished yet, and which tasks are waiting on which
+#[derive(Default)]
+struct TaskGraph {
+	nodes: FxHashMap<TaskHandle, TaskNode>,
+	// tasks with no remaining dependencies which haven't been given a priority yet
+	// priorities are calculated by the task runners, since the code adding tasks may be holding the player lock
+	new_ready: Vec<TaskHandle>,
+	ready: BinaryHeap<QueuedTask>,
+	next_handle: u64,
+}
+
+impl TaskGraph {
+	// removes the task and lets the tasks waiting on it run once they have no other dependencies
+	fn remove(&mut self, handle: TaskHandle) -> Option<TaskNode> {
+		let node = self.nodes.remove(&handle)?;
+
+		for dependent in node.dependents.iter() {
+			if let Some(dependent_node) = self.nodes.get_mut(dependent) {
+				dependent_node.remaining_dependencies -= 1;
+				if dependent_node.remaining_dependencies == 0 {
+					self.new_ready.push(*dependent);
+				}
+			}
+		}
+
+		Some(node)
+	}
+
+	// gives a priority to all newly ready tasks, or to every ready task if reprioritize is true
+	fn update_priorities(&mut self, viewers: &[Viewer], reprioritize: bool) {
+		let mut handles = mem::take(&mut self.new_ready);
+		if reprioritize {
+			handles.extend(mem::take(&mut self.ready).into_iter().map(|queued| queued.handle));
+		}
+
+		for handle in handles {
+			// the task may have been cancelled
+			let task = match self.nodes.get(&handle) {
+				Some(node) => node.task.as_ref().unwrap(),
+				None => continue,
+			};
+
+			self.ready.push(QueuedTask {
+				priority: task_priority(viewers, task),
+				handle,
+			});
+		}
+	}
+}
+
+// tasks which are run on the task runner threads of a world
+//
+// a task can wait for other tasks to finish before it runs, and can have a callback which is run once it finishes
+// ready tasks run in order of how close they are to players
+#[derive(Default)]
+pub struct TaskQueue {
+	graph: Mutex<TaskGraph>,
+	// callbacks of finished tasks, which are run by run_callbacks
+	completed: SegQueue<TaskCallback>,
+	// set when players have moved, so the priorities of all queued tasks have to be recalculated
+	reprioritize: AtomicBool,
+	stopped: AtomicBool,
+}
+
+impl TaskQueue {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn run(&self, task: Task) -> TaskHandle {
+		self.spawn(task, &[], None)
+	}
+
+	// adds a task which runs after all of its dependencies have finished or been cancelled
+	pub fn spawn(&self, task: Task, dependencies: &[TaskHandle], on_complete: Option<TaskCallback>) -> TaskHandle {
+		let mut graph = self.graph.lock();
+
+		let handle = TaskHandle(graph.next_handle);
+		graph.next_handle += 1;
+
+		// dependencies which are not in the graph have already finished
+		let mut remaining_dependencies = 0;
+		for dependency in dependencies {
+			if let Some(node) = graph.nodes.get_mut(dependency) {
+				node.dependents.push(handle);
+				remaining_dependencies += 1;
+			}
+		}
+
+		graph.nodes.insert(handle, TaskNode {
+			task: Some(task),
+			remaining_dependencies,
+			dependents: Vec::new(),
+			on_complete,
+		});
+
+		if remaining_dependencies == 0 {
+			graph.new_ready.push(handle);
+		}
+
+		handle
+	}
+
+	// cancels the task if it hasn't started running yet, its callback is not called
+	// tasks waiting on the cancelled task still run
+	// returns true if the task was cancelled
+	pub fn cancel(&self, handle: TaskHandle) -> bool {
+		let mut graph = self.graph.lock();
+		match graph.nodes.get(&handle) {
+			Some(node) if node.task.is_some() => graph.remove(handle).is_some(),
+			_ => false,
+		}
+	}
+
+	// returns true if the task has finished running or was cancelled
+	pub fn is_finished(&self, handle: TaskHandle) -> bool {
+		!self.graph.lock().nodes.contains_key(&handle)
+	}
+
+	// returns true if there are no tasks left to run and all callbacks have been run
+	pub fn is_idle(&self) -> bool {
+		self.graph.lock().nodes.is_empty() && self.completed.is_empty()
+	}
+
+	// called when a player moves or looks somewhere else so the closest chunks are worked on first
+	pub fn reprioritize(&self) {
+		self.reprioritize.store(true, Ordering::Release);
+	}
+
+	// makes all the task runners exit once they finish their current task
+	pub fn stop(&self) {
+		self.stopped.store(true, Ordering::Release);
+	}
+
+	// runs the callbacks of all the tasks which have finished since this was last called
+	pub fn run_callbacks(&self, world: &World) {
+		while let Some(callback) = self.completed.pop() {
+			callback(world);
+		}
+	}
+
+	fn next_task(&self, viewers: &[Viewer]) -> Option<(TaskHandle, Task)> {
+		let mut graph = self.graph.lock();
+		let reprioritize = self.reprioritize.swap(false, Ordering::AcqRel);
+		graph.update_priorities(viewers, reprioritize);
+
+		while let Some(queued) = graph.ready.pop() {
+			// cancelled tasks are left in the heap until they are popped
+			if let Some(node) = graph.nodes.get_mut(&queued.handle) {
+				return Some((queued.handle, node.task.take().unwrap()));
+			}
+		}
+		None
+	}
+
+	fn finish(&self, handle: TaskHandle) {
+		let node = self.graph.lock().remove(handle);
+		if let Some(callback) = node.and_then(|node| node.on_complete) {
+			self.completed.push(callback);
+		}
+	}
+
+	// called instead of finish for a task which was popped but not run, so its callback is not called
+	fn discard(&self, handle: TaskHandle) {
+		self.graph.lock().remove(handle);
+	}
 }
 
 pub fn init(world: Arc<World>, num_tasks: usize) {
@@ -41,84 +309,144 @@ pub fn init(world: Arc<World>, num_tasks: usize) {
 	}
 }
 
-// appends the given task to the task queue
-pub fn run_task(task: Task) {
-	TASK_QUEUE.push(task);
-}
-
-pub fn pull_completed_task() -> Option<Task> {
-	COMPLETED_TASKS.pop()
-}
-
 // waits for a task to apear, than runs it
 fn task_runner(world: Arc<World>) {
 	let sleep_duration = Duration::from_millis(2);
 
-	loop {
-		match TASK_QUEUE.steal() {
-			Steal::Success(task) => execute_task(&world, task),
-			Steal::Empty => thread::sleep(sleep_duration),
-			Steal::Retry => continue,
+	while !world.tasks.stopped.load(Ordering::Acquire) {
+		// the viewers are cached by the world, so this doesn't have to lock the players
+		let viewers = world.viewers();
+		let task = world.tasks.next_task(&viewers);
+
+		match task {
+			// the chunk may have been unloaded since the task was added
+			Some((handle, task)) if !world.is_task_needed(handle, &task) => world.tasks.discard(handle),
+			Some((handle, task)) => {
+				execute_task(&world, handle, task);
+				world.tasks.finish(handle);
+			},
+			None => thread::sleep(sleep_duration),
 		}
 	}
 }
 
 // executes the given task
-fn execute_task(world: &Arc<World>, task: Task) {
+fn execute_task(world: &Arc<World>, handle: TaskHandle, task: Task) {
 	match task {
-		Task::ChunkMesh(chunk) => {
-			world.chunks.get(&chunk).map(|chunk| chunk.value().chunk.chunk_mesh_update());
-			COMPLETED_TASKS.push(task);
-		},
-		Task::ChunkMeshFace { face, min_chunk, max_chunk } => {
-			let mut visit_map = VisitedBlockMap::new();
+		Task::ChunkMesh(chunk) => world.mesh_chunk(chunk, MeshLayers::ALL),
+		Task::ChunkMeshLayers { chunk, layers } => world.mesh_chunk(chunk, layers),
+		Task::GenerateChunk(chunk) => world.load_chunk(chunk, handle),
+		Task::UnloadChunks { min_chunk, max_chunk } => {
+			let mut cached_chunk = false;
 
 			for x in min_chunk.x..max_chunk.x {
 				for y in min_chunk.y..max_chunk.y {
 					for z in min_chunk.z..max_chunk.z {
-						let chunk_pos = BlockPos::new(x, y, z);
-						if let Some(chunk) = world.chunks.get(&chunk_pos) {
-							let index = if face.is_positive_face() {
-								CHUNK_SIZE - 1
-							} else {
-								0
-							};
-
-							chunk.chunk.mesh_update_inner(face, index, &mut visit_map);
-						}
+						cached_chunk |= world.unload_chunk(ChunkPos::new(x, y, z));
 					}
 				}
 			}
 
-			COMPLETED_TASKS.push(task);
+			if cached_chunk {
+				world.tasks.run(Task::SaveCachedChunks);
+			}
+		},
+		Task::SaveCachedChunks => if let Err(error) = world.save_cached_chunks() {
+			// the chunks stay cached, so they are tried again the next time cached chunks are saved
+			error!("could not save unloaded chunks: {}", error);
 		},
-		Task::GenerateChunk(chunk) => {
-			let chunk = world.chunks.entry(chunk)
-				.or_insert_with(|| world.world_generator
-					.generate_chunk(world.clone(), chunk));
+	}
+}
 
-			// when first inserting load count starts at 0
-			chunk.inc_load_count();
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::AtomicUsize;
 
-			COMPLETED_TASKS.push(task);
-		},
-		Task::UnloadChunks { min_chunk, max_chunk } => {
-			for x in min_chunk.x..max_chunk.x {
-				for y in min_chunk.y..max_chunk.y {
-					for z in min_chunk.z..max_chunk.z {
-						let position = ChunkPos::new(x, y, z);
-
-						if let Some(loaded_chunk) = world.chunks.get(&position) {
-							if loaded_chunk.dec_load_count() == 0 {
-								drop(loaded_chunk);
-								world.chunks.remove(&position);
-							}
-						}
-					}
-				}
-			}
+	use super::*;
+	use crate::game::block::BlockIdMap;
 
-			COMPLETED_TASKS.push(task);
-		},
+	#[test]
+	fn closest_chunks_in_front_run_first() {
+		let viewers = [Viewer {
+			chunk: ChunkPos::new(0, 0, 0),
+			direction: Vector3::new(1.0, 0.0, 0.0),
+			min_chunk: ChunkPos::new(-4, -4, -4),
+			max_chunk: ChunkPos::new(4, 4, 4),
+		}];
+
+		let priority = |x| task_priority(&viewers, &Task::GenerateChunk(ChunkPos::new(x, 0, 0)));
+
+		assert!(priority(1) < priority(2));
+		// a chunk in front of the player runs before one the same distance behind them
+		assert!(priority(2) < priority(-2));
+		// chunks which left the load area run last
+		assert_eq!(priority(10), f64::INFINITY);
+		assert_eq!(task_priority(&viewers, &Task::SaveCachedChunks), f64::INFINITY);
+	}
+
+	#[test]
+	fn dependencies_run_first() {
+		let queue = TaskQueue::new();
+		let first = queue.run(Task::GenerateChunk(ChunkPos::new(0, 0, 0)));
+		let second = queue.run(Task::GenerateChunk(ChunkPos::new(1, 0, 0)));
+		let mesh = queue.spawn(Task::ChunkMesh(ChunkPos::new(0, 0, 0)), &[first, second], None);
+
+		let (handle, _) = queue.next_task(&[]).unwrap();
+		assert_eq!(handle, first);
+		queue.finish(handle);
+
+		let (handle, _) = queue.next_task(&[]).unwrap();
+		assert_eq!(handle, second);
+		// the mesh task is still waiting on the second task
+		assert!(queue.next_task(&[]).is_none());
+		queue.finish(handle);
+
+		let (handle, _) = queue.next_task(&[]).unwrap();
+		assert_eq!(handle, mesh);
+		queue.finish(handle);
+		assert!(queue.is_idle());
+	}
+
+	#[test]
+	fn cancel() {
+		let queue = TaskQueue::new();
+		let generate = queue.run(Task::GenerateChunk(ChunkPos::new(0, 0, 0)));
+		let mesh = queue.spawn(Task::ChunkMesh(ChunkPos::new(0, 0, 0)), &[generate], None);
+
+		assert!(queue.cancel(generate));
+		assert!(queue.is_finished(generate));
+		assert!(!queue.cancel(generate));
+
+		// the task that was waiting can run now
+		let (handle, _) = queue.next_task(&[]).unwrap();
+		assert_eq!(handle, mesh);
+		// running tasks can't be cancelled
+		assert!(!queue.cancel(mesh));
+		queue.finish(handle);
+		assert!(queue.is_idle());
+	}
+
+	#[test]
+	fn callbacks() {
+		static CALLED: AtomicUsize = AtomicUsize::new(0);
+
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let queue = TaskQueue::new();
+		let finished = queue.spawn(Task::SaveCachedChunks, &[], Some(Box::new(|_| {
+			CALLED.fetch_add(1, Ordering::Relaxed);
+		})));
+		let cancelled = queue.spawn(Task::SaveCachedChunks, &[finished], Some(Box::new(|_| {
+			CALLED.fetch_add(10, Ordering::Relaxed);
+		})));
+
+		let (handle, _) = queue.next_task(&[]).unwrap();
+		queue.finish(handle);
+		assert!(queue.cancel(cancelled));
+
+		// callbacks are only run when asked to
+		assert_eq!(CALLED.load(Ordering::Relaxed), 0);
+		queue.run_callbacks(&world);
+		assert_eq!(CALLED.load(Ordering::Relaxed), 1);
+		assert!(queue.is_idle());
 	}
 }
@@ -0,0 +1,296 @@
+use nalgebra::Vector3;
+
+use crate::prelude::*;
+use super::block::{Block, BlockGeometry};
+use super::world::World;
+
+// meters / second^2
+pub const GRAVITY: f64 = 32.0;
+// falling speed can't go above this, in meters / second
+pub const TERMINAL_VELOCITY: f64 = 60.0;
+// upwards speed of a jump, which goes a little over 1 block high
+pub const JUMP_SPEED: f64 = 9.0;
+// bodies on the ground walk up anything this high without jumping, like slabs and stairs
+pub const STEP_HEIGHT: f64 = 0.6;
+// longest time a single step is simulated for, so a long pause doesn't send a body through the floor
+const MAX_STEP_TIME: f64 = 0.1;
+
+// boxes have to overlap by more than this to collide,
+// so rounding errors don't make a body stuck on a wall it is sliding along
+const EPSILON: f64 = 1e-7;
+
+// axis aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+	pub min: Position,
+	pub max: Position,
+}
+
+impl Aabb {
+	pub fn new(min: Position, max: Position) -> Self {
+		Self { min, max }
+	}
+
+	// the box of a full block
+	pub fn block(block: BlockPos) -> Self {
+		let min = block.cast::<f64>();
+		Self::new(min, min + Vector3::new(1.0, 1.0, 1.0))
+	}
+
+	pub fn translated(&self, offset: Vector3<f64>) -> Self {
+		Self::new(self.min + offset, self.max + offset)
+	}
+
+	// the box covering everything this box passes through when it moves by offset
+	pub fn swept(&self, offset: Vector3<f64>) -> Self {
+		let moved = self.translated(offset);
+		Self::new(self.min.inf(&moved.min), self.max.sup(&moved.max))
+	}
+
+	fn overlaps_on(&self, other: &Aabb, axis: usize) -> bool {
+		self.min[axis] < other.max[axis] - EPSILON && self.max[axis] > other.min[axis] + EPSILON
+	}
+
+	pub fn intersects(&self, other: &Aabb) -> bool {
+		(0..3).all(|axis| self.overlaps_on(other, axis))
+	}
+
+	// how far this box can move along the axis, up to distance, before it hits other
+	// boxes which already overlap this box are ignored, so a body stuck in a block can get out
+	fn clip_distance(&self, other: &Aabb, axis: usize, distance: f64) -> f64 {
+		if !(0..3).filter(|other_axis| *other_axis != axis).all(|other_axis| self.overlaps_on(other, other_axis)) {
+			return distance;
+		}
+
+		if distance > 0.0 && other.min[axis] >= self.max[axis] - EPSILON {
+			distance.min((other.min[axis] - self.max[axis]).max(0.0))
+		} else if distance < 0.0 && other.max[axis] <= self.min[axis] + EPSILON {
+			distance.max((other.max[axis] - self.min[axis]).min(0.0))
+		} else {
+			distance
+		}
+	}
+}
+
+// adds the boxes a body collides with in the block to boxes
+// blocks with cross geometry, like plants, can be walked through
+pub fn block_collision_boxes(block: &Block, position: BlockPos, boxes: &mut Vec<Aabb>) {
+	if block.is_air() {
+		return;
+	}
+
+	match block.geometry() {
+		BlockGeometry::Cube => boxes.push(Aabb::block(position)),
+		BlockGeometry::Boxes { boxes: shape_boxes, .. } => {
+			let offset = position.cast::<f64>();
+			boxes.extend(shape_boxes.iter()
+				.map(|shape_box| Aabb::new(offset + shape_box.min_offset(), offset + shape_box.max_offset())));
+		},
+		BlockGeometry::Cross => (),
+	}
+}
+
+// how far a box moving by offset gets before it hits the boxes, moving along y first, then x and z
+fn sweep(aabb: Aabb, offset: Vector3<f64>, boxes: &[Aabb]) -> Vector3<f64> {
+	let mut aabb = aabb;
+	let mut moved = Vector3::zeros();
+
+	for axis in [1, 0, 2] {
+		let distance = boxes.iter()
+			.fold(offset[axis], |distance, other| aabb.clip_distance(other, axis, distance));
+
+		moved[axis] = distance;
+		let mut axis_offset = Vector3::zeros();
+		axis_offset[axis] = distance;
+		aabb = aabb.translated(axis_offset);
+	}
+
+	moved
+}
+
+// something that moves through the world and collides with solid blocks
+#[derive(Debug, Clone)]
+pub struct PhysicsBody {
+	// center of the bottom of the body
+	pub position: Position,
+	// meters / second
+	pub velocity: Vector3<f64>,
+	// size along the x and z axes
+	width: f64,
+	height: f64,
+	on_ground: bool,
+}
+
+impl PhysicsBody {
+	pub fn new(position: Position, width: f64, height: f64) -> Self {
+		Self {
+			position,
+			velocity: Vector3::zeros(),
+			width,
+			height,
+			on_ground: false,
+		}
+	}
+
+	pub fn aabb(&self) -> Aabb {
+		let half_width = self.width / 2.0;
+		Aabb::new(
+			self.position - Vector3::new(half_width, 0.0, half_width),
+			self.position + Vector3::new(half_width, self.height, half_width),
+		)
+	}
+
+	// true if the body was standing on something after it last moved
+	pub fn on_ground(&self) -> bool {
+		self.on_ground
+	}
+
+	// moves the body by offset, stopping where it hits solid blocks, and returns how far it moved
+	// a body on the ground steps up onto blocks up to STEP_HEIGHT tall
+	pub fn move_by(&mut self, world: &World, offset: Vector3<f64>) -> Vector3<f64> {
+		let aabb = self.aabb();
+		let step = Vector3::new(0.0, STEP_HEIGHT, 0.0);
+		// the area covers a step up and down as well as the movement
+		let boxes = world.collision_boxes(&aabb.swept(offset).swept(step).swept(-step));
+
+		let mut moved = sweep(aabb, offset, &boxes);
+
+		let blocked_horizontally = moved.x != offset.x || moved.z != offset.z;
+		if self.on_ground && blocked_horizontally && offset.y <= 0.0 {
+			// go up, across, then back down, and keep it if that gets further than walking into the block
+			let up = sweep(aabb, step, &boxes);
+			let across = sweep(aabb.translated(up), offset.xzonly(), &boxes);
+			let down = sweep(aabb.translated(up + across), -up, &boxes);
+
+			if across.xzonly().norm_squared() > moved.xzonly().norm_squared() + EPSILON {
+				moved = up + across + down;
+			}
+		}
+
+		self.position += moved;
+		for axis in 0..3 {
+			if moved[axis] != offset[axis] {
+				self.velocity[axis] = 0.0;
+			}
+		}
+
+		// a body standing on something can't move down at all
+		let probe = Vector3::new(0.0, -0.01, 0.0);
+		let below = self.aabb();
+		let below_boxes = world.collision_boxes(&below.swept(probe));
+		self.on_ground = sweep(below, probe, &below_boxes).y > probe.y;
+
+		moved
+	}
+
+	// simulates time_delta seconds of the body walking at walk_velocity, with gravity pulling it down
+	// only the horizontal part of walk_velocity is used, and the body only jumps if it is on the ground
+	pub fn walk(&mut self, world: &World, walk_velocity: Vector3<f64>, jump: bool, time_delta: f64) {
+		let time_delta = time_delta.min(MAX_STEP_TIME);
+
+		if jump && self.on_ground {
+			self.velocity.y = JUMP_SPEED;
+		}
+		self.velocity.y = (self.velocity.y - GRAVITY * time_delta).max(-TERMINAL_VELOCITY);
+		self.velocity.x = walk_velocity.x;
+		self.velocity.z = walk_velocity.z;
+
+		self.move_by(world, self.velocity * time_delta);
+	}
+
+	// moves the body without any collision, like when flying
+	pub fn teleport(&mut self, position: Position) {
+		self.position = position;
+		self.velocity = Vector3::zeros();
+		self.on_ground = false;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+
+	use super::*;
+	use crate::game::CHUNK_SIZE;
+	use crate::game::block::{BlockIdMap, Air, Stone, Half};
+
+	// a chunk at the origin filled with stone below y 4
+	fn floor_world() -> Arc<World> {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let [air_low, air_high] = Block::from(Air::new()).id().to_le_bytes();
+		let [stone_low, stone_high] = Block::from(Stone::new()).id().to_le_bytes();
+
+		let data = (0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE)
+			.flat_map(|index| if (index / CHUNK_SIZE) % CHUNK_SIZE < 4 {
+				[stone_low, stone_high, 0]
+			} else {
+				[air_low, air_high, 0]
+			})
+			.collect::<Vec<_>>();
+		world.insert_remote_chunk(ChunkPos::new(0, 0, 0), &data).unwrap();
+		world
+	}
+
+	fn walk_for(body: &mut PhysicsBody, world: &World, walk_velocity: Vector3<f64>, seconds: f64) {
+		for _ in 0..(seconds * 20.0) as usize {
+			body.walk(world, walk_velocity, false, 0.05);
+		}
+	}
+
+	#[test]
+	fn falls_and_jumps() {
+		let world = floor_world();
+		let mut body = PhysicsBody::new(Position::new(8.5, 12.0, 8.5), 0.6, 1.8);
+
+		walk_for(&mut body, &world, Vector3::zeros(), 2.0);
+		assert!(body.on_ground());
+		assert!((body.position.y - 4.0).abs() < 1e-9);
+		assert_eq!(body.velocity.y, 0.0);
+
+		// a jump goes over 1 block, then lands back on the floor
+		body.walk(&world, Vector3::zeros(), true, 0.05);
+		assert!(!body.on_ground());
+		let mut highest = body.position.y;
+		for _ in 0..20 {
+			body.walk(&world, Vector3::zeros(), false, 0.05);
+			highest = highest.max(body.position.y);
+		}
+		assert!(highest > 5.0);
+		walk_for(&mut body, &world, Vector3::zeros(), 1.0);
+		assert!((body.position.y - 4.0).abs() < 1e-9);
+	}
+
+	#[test]
+	fn slides_along_walls() {
+		let world = floor_world();
+		// a wall 2 blocks high along x = 12
+		for y in 4..6 {
+			for z in 0..CHUNK_SIZE as i64 {
+				assert!(world.set_block(BlockPos::new(12, y, z), Stone::new().into()));
+			}
+		}
+
+		let mut body = PhysicsBody::new(Position::new(8.5, 4.0, 8.5), 0.6, 1.8);
+		walk_for(&mut body, &world, Vector3::new(4.0, 0.0, 4.0), 1.0);
+
+		// the body stops against the wall without climbing it, but keeps moving along it
+		assert!((body.position.x - 11.7).abs() < 1e-9);
+		assert!((body.position.y - 4.0).abs() < 1e-9);
+		assert!(body.position.z > 12.0);
+	}
+
+	#[test]
+	fn steps_onto_slabs() {
+		let world = floor_world();
+		let slab = Block::from_name("stone slab").unwrap().with_half(Half::Bottom).unwrap();
+		for z in 0..CHUNK_SIZE as i64 {
+			assert!(world.set_block(BlockPos::new(12, 4, z), slab.clone()));
+		}
+
+		let mut body = PhysicsBody::new(Position::new(8.5, 4.0, 8.5), 0.6, 1.8);
+		walk_for(&mut body, &world, Vector3::new(4.0, 0.0, 0.0), 1.0);
+		assert!(body.position.x > 12.0);
+		assert!((body.position.y - 4.5).abs() < 1e-9);
+		assert!(body.on_ground());
+	}
+}
@@ -1,7 +1,12 @@
 use std::sync::atomic::{AtomicU64, Ordering};
 
+use nalgebra::Vector3;
+
 use crate::prelude::*;
 
+// how far from the eyes of a player a block can be for the player to change it
+pub const PLAYER_REACH: f64 = 15.0;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PlayerId(u64);
 
@@ -12,20 +17,31 @@ impl PlayerId {
 	pub fn new() -> PlayerId {
 		PlayerId(NEXT_PLAYER_ID.fetch_add(1, Ordering::Relaxed))
 	}
+
+	pub fn as_u64(&self) -> u64 {
+		self.0
+	}
 }
 
 pub struct Player {
 	id: PlayerId,
+	// players are saved by name, so they are put back in the same place when they join again
+	// players without a name aren't saved
+	name: Option<String>,
 	pub position: Position,
+	// normalized direction the player is looking in, or zero if it is not known
+	pub direction: Vector3<f64>,
 	// render distance in x, y, and z direction
 	render_distance: ChunkPos,
 }
 
 impl Player {
-	pub fn new() -> Player {
+	pub fn new(name: Option<String>, position: Position) -> Player {
 		Player {
 			id: PlayerId::new(),
-			position: Position::new(0.0, 0.0, 0.0),
+			name,
+			position,
+			direction: Vector3::zeros(),
 			//render_distance: ChunkPos::new(8, 5, 8),
 			//render_distance: ChunkPos::new(2, 2, 2),
 			render_distance: ChunkPos::new(5, 3, 5),
@@ -36,6 +52,10 @@ impl Player {
 		self.id
 	}
 
+	pub fn name(&self) -> Option<&str> {
+		self.name.as_deref()
+	}
+
 	pub fn chunk_position(&self) -> ChunkPos {
 		self.position.into_chunk_pos()
 	}
@@ -43,4 +63,23 @@ impl Player {
 	pub fn render_distance(&self) -> ChunkPos {
 		self.render_distance
 	}
+
+	// returns the min and max chunk that are kept loaded around the player, not including max
+	pub fn load_area(&self) -> (ChunkPos, ChunkPos) {
+		(
+			self.chunk_position() - self.render_distance,
+			self.chunk_position() + self.render_distance,
+		)
+	}
+}
+
+// returns true if any part of the block is within reach of a player whose eyes are at position
+pub fn in_reach(position: Position, block: BlockPos) -> bool {
+	let min = block.cast::<f64>();
+	let closest = Vector3::new(
+		position.x.clamp(min.x, min.x + 1.0),
+		position.y.clamp(min.y, min.y + 1.0),
+		position.z.clamp(min.z, min.z + 1.0),
+	);
+	(position - closest).norm() <= PLAYER_REACH
 }
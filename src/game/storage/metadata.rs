@@ -0,0 +1,113 @@
+use std::{
+	fs,
+	path::{Path, PathBuf},
+};
+
+use anyhow::{Result, Context};
+use serde::{Serialize, Deserialize};
+
+const METADATA_FILE: &str = "world.toml";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedPlayer {
+	// missing in worlds from before players were saved by name
+	#[serde(default)]
+	pub name: String,
+	pub position: [f64; 3],
+}
+
+// general information about a world which is not part of any chunk
+//
+// fields added since the first version have defaults, so a world from before they were added
+// fails the format version check with a clear error instead of failing to parse
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldMetadata {
+	// version of the on disk format of the world, worlds with a different version can't be opened
+	pub format_version: u32,
+	// version of the world generator which generated the world
+	pub generator_version: u32,
+	pub seed: u32,
+	pub spawn_position: [f64; 3],
+	// name of the block with each id used in the saved chunks
+	#[serde(default)]
+	pub block_names: Vec<String>,
+	// number of ticks the world has run for, see clock
+	#[serde(default)]
+	pub time: u64,
+	// the id the next entity created in the world gets
+	#[serde(default)]
+	pub next_entity_id: u64,
+	// toml requires tables to come after all other values, so this has to be the last field
+	pub players: Vec<SavedPlayer>,
+}
+
+impl WorldMetadata {
+	fn path_in<T: AsRef<Path>>(folder: T) -> PathBuf {
+		let mut path = folder.as_ref().to_owned();
+		path.push(METADATA_FILE);
+		path
+	}
+
+	// returns true if there is a world saved in the given folder
+	pub fn exists<T: AsRef<Path>>(folder: T) -> bool {
+		Self::path_in(folder).is_file()
+	}
+
+	pub fn load<T: AsRef<Path>>(folder: T) -> Result<Self> {
+		let path = Self::path_in(folder);
+		let text = fs::read_to_string(&path)
+			.with_context(|| format!("could not read world metadata file {}", path.display()))?;
+		toml::from_str(&text)
+			.with_context(|| format!("world metadata file {} is invalid", path.display()))
+	}
+
+	// replaces the saved player with the same name, or adds it if there isn't one
+	pub fn update_player(&mut self, saved_player: SavedPlayer) {
+		match self.players.iter_mut().find(|elem| elem.name == saved_player.name) {
+			Some(elem) => *elem = saved_player,
+			None => self.players.push(saved_player),
+		}
+	}
+
+	pub fn save<T: AsRef<Path>>(&self, folder: T) -> Result<()> {
+		let path = Self::path_in(folder);
+		// write to a temporary file first so the old metadata is not lost if writing fails
+		let temp_path = path.with_extension("toml.tmp");
+		fs::write(&temp_path, toml::to_string(self)?)?;
+		fs::rename(&temp_path, &path)?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::TestFolder;
+
+	#[test]
+	fn save_and_load() {
+		let folder = TestFolder::new("metadata-test");
+		fs::create_dir_all(&folder).unwrap();
+		assert!(!WorldMetadata::exists(&folder));
+
+		let mut metadata = WorldMetadata {
+			format_version: 1,
+			generator_version: 2,
+			seed: 3,
+			spawn_position: [0.5, 64.0, -10.5],
+			block_names: vec![String::from("air"), String::from("stone")],
+			time: 1000,
+			next_entity_id: 7,
+			players: Vec::new(),
+		};
+		metadata.update_player(SavedPlayer { name: String::from("a"), position: [1.0, 2.0, 3.0] });
+		metadata.update_player(SavedPlayer { name: String::from("b"), position: [4.0, 5.0, 6.0] });
+		metadata.update_player(SavedPlayer { name: String::from("a"), position: [7.0, 8.0, 9.0] });
+		assert_eq!(metadata.players.len(), 2);
+		assert_eq!(metadata.players[0].position, [7.0, 8.0, 9.0]);
+
+		metadata.save(&folder).unwrap();
+		assert!(WorldMetadata::exists(&folder));
+		assert_eq!(WorldMetadata::load(&folder).unwrap(), metadata);
+	}
+}
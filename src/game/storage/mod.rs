@@ -0,0 +1,102 @@
+use std::{
+	fs,
+	io::{Read, Write},
+	path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use parking_lot::Mutex;
+use rustc_hash::FxHashMap;
+
+use crate::prelude::*;
+use region::{RegionFile, RegionPos, region_of};
+
+mod region;
+mod metadata;
+pub use metadata::{WorldMetadata, SavedPlayer};
+
+// version of the format used to store worlds on disk
+// this must be incramented whenever the format changes, since old worlds can't be read anymore
+pub const STORAGE_FORMAT_VERSION: u32 = 4;
+
+// compresses chunk data the same way it is compressed on disk
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+	let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+	encoder.write_all(data)?;
+	Ok(encoder.finish()?)
+}
+
+pub fn decompress(compressed: &[u8]) -> Result<Vec<u8>> {
+	let mut data = Vec::new();
+	ZlibDecoder::new(compressed).read_to_end(&mut data)?;
+	Ok(data)
+}
+
+// stores chunks on disk grouped into region files, with each chunk compressed individually
+pub struct WorldStorage {
+	region_folder: PathBuf,
+	// region files are opened lazily the first time a chunk in them is accessed
+	// TODO: close region files that haven't been used in a while
+	regions: Mutex<FxHashMap<RegionPos, RegionFile>>,
+}
+
+impl WorldStorage {
+	// opens the world storage in the given folder, creating it if it doesn't exist
+	pub fn open<T: AsRef<Path>>(folder: T) -> Result<Self> {
+		let mut region_folder = folder.as_ref().to_owned();
+		region_folder.push("region");
+		fs::create_dir_all(&region_folder)?;
+
+		Ok(Self {
+			region_folder,
+			regions: Mutex::new(FxHashMap::default()),
+		})
+	}
+
+	fn region_path(&self, region: RegionPos) -> PathBuf {
+		let mut path = self.region_folder.clone();
+		path.push(format!("r.{}.{}.{}.region", region.x, region.y, region.z));
+		path
+	}
+
+	// calls the function on the region file containing the given chunk, opening the file if needed
+	fn with_region<T, F>(&self, chunk: ChunkPos, f: F) -> Result<T>
+		where F: FnOnce(&mut RegionFile) -> Result<T> {
+		let region_pos = region_of(chunk);
+		let mut regions = self.regions.lock();
+
+		if let Some(region) = regions.get_mut(&region_pos) {
+			f(region)
+		} else {
+			let mut region = RegionFile::open(self.region_path(region_pos))?;
+			let out = f(&mut region);
+			regions.insert(region_pos, region);
+			out
+		}
+	}
+
+	// returns the uncompressed data of the chunk, or None if it has not been saved before
+	pub fn load_chunk(&self, chunk: ChunkPos) -> Result<Option<Vec<u8>>> {
+		let compressed = match self.with_region(chunk, |region| region.read_chunk(chunk))? {
+			Some(data) => data,
+			None => return Ok(None),
+		};
+
+		Ok(Some(decompress(&compressed)?))
+	}
+
+	pub fn save_chunk(&self, chunk: ChunkPos, data: &[u8]) -> Result<()> {
+		// compress before taking the lock so other threads can keep using the region files
+		let compressed = compress(data)?;
+		self.with_region(chunk, |region| region.write_chunk(chunk, &compressed))
+	}
+
+	// makes sure everything written so far is on disk
+	pub fn flush(&self) -> Result<()> {
+		for region in self.regions.lock().values_mut() {
+			region.flush()?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,222 @@
+use std::{
+	fs::{File, OpenOptions},
+	io::{Read, Write, Seek, SeekFrom},
+	path::Path,
+};
+
+use anyhow::{Result, bail};
+
+use crate::prelude::*;
+
+// number of chunks along each axis of a region
+pub const REGION_SIZE: i64 = 8;
+const REGION_CHUNK_COUNT: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+
+// the file is divided into sectors, chunk data always starts on a sector boundary
+const SECTOR_SIZE: u64 = 4096;
+// size in bytes of 1 entry in the header
+const HEADER_ENTRY_SIZE: usize = 8;
+// the header takes up exactly 1 sector
+const HEADER_SECTORS: u32 = 1;
+
+// position of a region in region coordinates, increases in incraments of 1
+pub type RegionPos = ChunkPos;
+
+pub fn region_of(chunk: ChunkPos) -> RegionPos {
+	RegionPos::new(
+		chunk.x.div_euclid(REGION_SIZE),
+		chunk.y.div_euclid(REGION_SIZE),
+		chunk.z.div_euclid(REGION_SIZE),
+	)
+}
+
+// index of the chunk in the region header
+fn header_index(chunk: ChunkPos) -> usize {
+	let x = chunk.x.rem_euclid(REGION_SIZE);
+	let y = chunk.y.rem_euclid(REGION_SIZE);
+	let z = chunk.z.rem_euclid(REGION_SIZE);
+	((x * REGION_SIZE + y) * REGION_SIZE + z) as usize
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HeaderEntry {
+	// 0 means the chunk is not present in this region
+	sector_offset: u32,
+	sector_count: u32,
+}
+
+impl HeaderEntry {
+	fn is_present(&self) -> bool {
+		self.sector_offset != 0
+	}
+}
+
+// a region file stores a REGION_SIZE^3 cube of chunks
+//
+// layout:
+// header: 1 sector, made of REGION_CHUNK_COUNT entries of (u32 sector offset, u32 sector count), little endian
+// chunk data: u32 length of the payload followed by the payload, padded to a sector boundary
+pub struct RegionFile {
+	file: File,
+	header: Box<[HeaderEntry; REGION_CHUNK_COUNT]>,
+	// number of sectors currently in the file
+	sector_count: u32,
+}
+
+impl RegionFile {
+	pub fn open<T: AsRef<Path>>(path: T) -> Result<Self> {
+		let mut file = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.create(true)
+			.truncate(false)
+			.open(path)?;
+
+		let file_len = file.metadata()?.len();
+		let mut header = Box::new([HeaderEntry::default(); REGION_CHUNK_COUNT]);
+
+		if file_len == 0 {
+			// new region, write an empty header
+			file.write_all(&[0; REGION_CHUNK_COUNT * HEADER_ENTRY_SIZE])?;
+		} else {
+			let mut header_bytes = vec![0; REGION_CHUNK_COUNT * HEADER_ENTRY_SIZE];
+			file.read_exact(&mut header_bytes)?;
+
+			for (entry, bytes) in header.iter_mut().zip(header_bytes.chunks_exact(HEADER_ENTRY_SIZE)) {
+				entry.sector_offset = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+				entry.sector_count = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+			}
+		}
+
+		let sector_count = ((file_len + SECTOR_SIZE - 1) / SECTOR_SIZE).max(HEADER_SECTORS as u64);
+
+		Ok(Self {
+			file,
+			header,
+			sector_count: sector_count.try_into()?,
+		})
+	}
+
+	// reads the payload of the chunk, or returns None if the chunk has never been saved
+	pub fn read_chunk(&mut self, chunk: ChunkPos) -> Result<Option<Vec<u8>>> {
+		let entry = self.header[header_index(chunk)];
+		if !entry.is_present() {
+			return Ok(None);
+		}
+
+		self.file.seek(SeekFrom::Start(entry.sector_offset as u64 * SECTOR_SIZE))?;
+
+		let mut len_bytes = [0; 4];
+		self.file.read_exact(&mut len_bytes)?;
+		let len = u32::from_le_bytes(len_bytes) as u64;
+
+		if len + 4 > entry.sector_count as u64 * SECTOR_SIZE {
+			bail!("chunk {:?} has length {} which is longer than its {} allocated sectors", chunk, len, entry.sector_count);
+		}
+
+		let mut data = vec![0; len as usize];
+		self.file.read_exact(&mut data)?;
+		Ok(Some(data))
+	}
+
+	// TODO: reuse sectors that are freed when a chunk is moved to the end of the file
+	pub fn write_chunk(&mut self, chunk: ChunkPos, data: &[u8]) -> Result<()> {
+		let index = header_index(chunk);
+		let old_entry = self.header[index];
+
+		let total_len = data.len() as u64 + 4;
+		let needed_sectors: u32 = ((total_len + SECTOR_SIZE - 1) / SECTOR_SIZE).try_into()?;
+
+		let sector_offset = if old_entry.is_present() && needed_sectors <= old_entry.sector_count {
+			// the chunk still fits in its old spot
+			old_entry.sector_offset
+		} else {
+			let offset = self.sector_count;
+			self.sector_count += needed_sectors;
+			offset
+		};
+
+		let len: u32 = data.len().try_into()?;
+		let padding = needed_sectors as usize * SECTOR_SIZE as usize - total_len as usize;
+
+		self.file.seek(SeekFrom::Start(sector_offset as u64 * SECTOR_SIZE))?;
+		self.file.write_all(&len.to_le_bytes())?;
+		self.file.write_all(data)?;
+		self.file.write_all(&vec![0; padding])?;
+
+		let entry = HeaderEntry {
+			sector_offset,
+			sector_count: needed_sectors,
+		};
+		self.header[index] = entry;
+
+		// update the header entry after the data is written, so a crash while appending won't corrupt the old chunk
+		self.file.seek(SeekFrom::Start((index * HEADER_ENTRY_SIZE) as u64))?;
+		self.file.write_all(&entry.sector_offset.to_le_bytes())?;
+		self.file.write_all(&entry.sector_count.to_le_bytes())?;
+
+		Ok(())
+	}
+
+	pub fn flush(&mut self) -> Result<()> {
+		self.file.sync_data()?;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+
+	use super::*;
+	use crate::util::TestFolder;
+
+	// bytes which are different for every chunk and length, so data from the wrong place doesn't read back the same
+	fn chunk_data(seed: u8, len: usize) -> Vec<u8> {
+		(0..len).map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect()
+	}
+
+	#[test]
+	fn write_and_read() {
+		let folder = TestFolder::new("region-test");
+		fs::create_dir_all(&folder).unwrap();
+		let path = folder.join("region");
+
+		// all in the region at -1, -1, -1
+		let small = ChunkPos::new(-1, -8, -3);
+		let grown = ChunkPos::new(-2, -1, -1);
+		let empty = ChunkPos::new(-8, -8, -8);
+		assert_eq!(region_of(small), region_of(grown));
+		assert_eq!(region_of(small), RegionPos::new(-1, -1, -1));
+
+		let mut region = RegionFile::open(&path).unwrap();
+		region.write_chunk(small, &chunk_data(1, 100)).unwrap();
+		region.write_chunk(grown, &chunk_data(2, 10)).unwrap();
+		region.flush().unwrap();
+		drop(region);
+
+		let mut region = RegionFile::open(&path).unwrap();
+		assert_eq!(region.read_chunk(small).unwrap(), Some(chunk_data(1, 100)));
+		assert_eq!(region.read_chunk(grown).unwrap(), Some(chunk_data(2, 10)));
+		assert_eq!(region.read_chunk(empty).unwrap(), None);
+
+		// the chunk no longer fits in its 1 sector, so it is moved to the end of the file
+		let grown_data = chunk_data(3, 3 * SECTOR_SIZE as usize);
+		region.write_chunk(grown, &grown_data).unwrap();
+		assert_eq!(region.read_chunk(grown).unwrap(), Some(grown_data.clone()));
+		assert_eq!(region.read_chunk(small).unwrap(), Some(chunk_data(1, 100)));
+
+		// a smaller chunk fits in the old spot, and adding a chunk after that doesn't overwrite either
+		region.write_chunk(small, &chunk_data(4, 50)).unwrap();
+		region.write_chunk(empty, &chunk_data(5, SECTOR_SIZE as usize)).unwrap();
+		region.flush().unwrap();
+		drop(region);
+
+		let mut region = RegionFile::open(&path).unwrap();
+		assert_eq!(region.read_chunk(small).unwrap(), Some(chunk_data(4, 50)));
+		assert_eq!(region.read_chunk(grown).unwrap(), Some(grown_data));
+		assert_eq!(region.read_chunk(empty).unwrap(), Some(chunk_data(5, SECTOR_SIZE as usize)));
+		// the header, small, the sector grown moved out of, 4 sectors for grown and 2 for empty, since the lengths take 4 bytes
+		assert_eq!(fs::metadata(&path).unwrap().len(), 9 * SECTOR_SIZE);
+	}
+}
@@ -1,202 +1,976 @@
 use std::{
-	fs::{File, OpenOptions},
-	path::Path,
+	path::{Path, PathBuf},
 	sync::{Arc, Weak},
+	sync::atomic::{AtomicBool, AtomicU64, Ordering},
+	thread,
+	time::Duration,
 };
 
-use rustc_hash::FxHashMap;
-use dashmap::DashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use nalgebra::Vector3;
-use anyhow::Result;
-use parking_lot::RwLock;
+use anyhow::{Result, Context, bail};
+use parking_lot::{RwLock, RwLockWriteGuard, Mutex, MutexGuard};
 
 use super::{
-	chunk::{Chunk, LoadedChunk, ChunkData, VisitedBlockMap},
-	entity::Entity,
-	block::{BlockFaceMesh, BlockFace, Block, BlockTrait},
-	worldgen::WorldGenerator,
+	chunk::{Chunk, LoadedChunk, ChunkData, ChunkFaces, MeshLayers},
+	entity::{Entity, EntityId, EntityMap, SavedEntity},
+	block::{BlockFace, Block, BlockIdMap},
+	worldgen::{WorldGenerator, GENERATOR_VERSION},
+	storage::{WorldStorage, WorldMetadata, SavedPlayer, STORAGE_FORMAT_VERSION},
 	player::{Player, PlayerId}, CHUNK_SIZE,
-	parallel::{Task, run_task, pull_completed_task},
+	parallel::{Task, TaskQueue, TaskHandle, TaskCallback, Viewer},
+	connectivity::ChunkConnectivity,
+	light::{LightUpdate, LightClaims},
+	clock::{TICKS_PER_DAY, START_TIME},
+	physics::{Aabb, block_collision_boxes},
 };
 use crate::prelude::*;
 
-#[derive(Debug)]
-pub struct ChunkMeshFaceData {
-	min_chunk: ChunkPos,
-	max_chunk: ChunkPos,
-	face: BlockFace,
+// max size of world in chunks
+// 16,384 meters in each x and y direction
+// 2,048 meters in z direction
+pub const WORLD_MAX_SIZE: Vector3<u64> = Vector3::new(512, 64, 512);
+
+// cosine of the angle a player has to turn before tasks are reprioritized, about 20 degrees
+const REPRIORITIZE_DIRECTION_COS: f64 = 0.94;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkStatus {
+	// waiting for the generate task to run
+	Queued(TaskHandle),
+	Generating(TaskHandle),
+	Loaded,
+	// number of mesh tasks that are running on the chunk
+	Meshing(u32),
+	// waiting for the unload task to run
+	Unloading,
 }
 
-impl ChunkMeshFaceData {
-	fn into_task(&self) -> Task {
-		Task::ChunkMeshFace {
-			min_chunk: self.min_chunk,
-			max_chunk: self.max_chunk,
-			face: self.face,
-		}
+// the chunk is in World::chunks if its status is Loaded, Meshing, or Unloading
+#[derive(Debug, Clone, Copy)]
+struct ChunkState {
+	status: ChunkStatus,
+	// number of times load_chunks was called on the chunk without unload_chunks being called,
+	// which is usually the number of players in range of the chunk
+	// a chunk with a ref count of 0 is unloaded as soon as whatever it is doing now is finished
+	ref_count: u32,
+	// set until a mesh task starts meshing the whole chunk
+	// remeshing only part of the chunk is skipped until then, since the whole mesh will be made anyways
+	needs_mesh: bool,
+}
+
+// where the world is saved on disk
+struct WorldSave {
+	folder: PathBuf,
+	storage: WorldStorage,
+}
+
+// returns the mesh layers of every chunk which could change when the blocks between min_block and max_block change, not including max_block
+//
+// a layer depends on the blocks in it, and for culling and ambient occlusion on the blocks in front of its faces,
+// including the blocks 1 past its edges, which are in the chunks next to it
+fn mesh_layers_affected_by(min_block: BlockPos, max_block: BlockPos) -> FxHashMap<ChunkPos, MeshLayers> {
+	let mut out = FxHashMap::default();
+
+	for face in BlockFace::iter() {
+		let offset = face.block_pos_offset();
+		// the axes in the plane of the face
+		let plane = offset.map(|n| 1 - n.abs());
+
+		// the faces of the changed blocks
+		add_mesh_layers(&mut out, face, min_block, max_block);
+		// faces behind the changed blocks, and next to them for ambient occlusion
+		add_mesh_layers(&mut out, face, min_block - offset - plane, max_block - offset + plane);
 	}
+
+	out
 }
 
-#[derive(Debug)]
-struct ChunkLoadJob {
-	min_chunk: ChunkPos,
-	max_chunk: ChunkPos,
-	remaining_chunks: u64,
-	// data to run the mesh facing task after the chunk is done loading in
-	// TODO: handle when there is more than 1 face to mesh
-	mesh_face_task: Option<ChunkMeshFaceData>,
+// adds the mesh layers affected by the light in each of the boxes, which are (min, max) not including max
+fn add_light_mesh_layers(layers: &mut FxHashMap<ChunkPos, MeshLayers>, light_changes: &[(BlockPos, BlockPos)]) {
+	// faces are lit by the light of the block in front of them, which is included in the layers affected by a block
+	for (min_block, max_block) in light_changes.iter() {
+		for (chunk, chunk_layers) in mesh_layers_affected_by(*min_block, *max_block) {
+			layers.entry(chunk).or_default().union(chunk_layers);
+		}
+	}
 }
 
-impl ChunkLoadJob {
-	fn contains_chunk(&self, chunk: ChunkPos) -> bool {
-		chunk.x >= self.min_chunk.x
-			&& chunk.y >= self.min_chunk.y
-			&& chunk.z >= self.min_chunk.z
-			&& chunk.x < self.max_chunk.x
-			&& chunk.y < self.max_chunk.y
-			&& chunk.z < self.max_chunk.z
+// adds the layers which contain blocks between min_block and max_block, not including max_block
+fn add_mesh_layers(layers: &mut FxHashMap<ChunkPos, MeshLayers>, face: BlockFace, min_block: BlockPos, max_block: BlockPos) {
+	let chunk_size = CHUNK_SIZE as i64;
+	let min_chunk = min_block.map(|n| n.div_euclid(chunk_size));
+	let max_chunk = (max_block - BlockPos::repeat(1)).map(|n| n.div_euclid(chunk_size));
+
+	let min_layer = min_block.get_face_component(face);
+	let max_layer = max_block.get_face_component(face);
+
+	for x in min_chunk.x..=max_chunk.x {
+		for y in min_chunk.y..=max_chunk.y {
+			for z in min_chunk.z..=max_chunk.z {
+				let chunk = ChunkPos::new(x, y, z);
+				let chunk_start = (chunk * chunk_size).get_face_component(face);
+				let chunk_layers = layers.entry(chunk).or_default();
+
+				for layer in min_layer.max(chunk_start)..max_layer.min(chunk_start + chunk_size) {
+					chunk_layers.insert(face, (layer - chunk_start) as usize);
+				}
+			}
+		}
 	}
 }
 
-// max size of world in chunks
-// 16,384 meters in each x and y direction
-// 2,048 meters in z direction
-pub const WORLD_MAX_SIZE: Vector3<u64> = Vector3::new(512, 64, 512);
+// splits the chunks which are in box a but not in box b into at most 6 non overlapping boxes
+// boxes are (min, max), not including max
+fn box_difference(a: (ChunkPos, ChunkPos), b: (ChunkPos, ChunkPos)) -> Vec<(ChunkPos, ChunkPos)> {
+	let (mut min, mut max) = a;
+	let (b_min, b_max) = b;
+
+	let overlaps = (0..3).all(|axis| min[axis] < b_max[axis] && b_min[axis] < max[axis]);
+	if !overlaps {
+		return vec![a];
+	}
+
+	let mut out = Vec::new();
+
+	// cut off the slabs on each side of b one axis at a time, after which what is left of a is inside b
+	for axis in 0..3 {
+		if min[axis] < b_min[axis] {
+			let mut slab_max = max;
+			slab_max[axis] = b_min[axis];
+			out.push((min, slab_max));
+			min[axis] = b_min[axis];
+		}
+
+		if max[axis] > b_max[axis] {
+			let mut slab_min = min;
+			slab_min[axis] = b_max[axis];
+			out.push((slab_min, max));
+			max[axis] = b_max[axis];
+		}
+	}
+
+	out
+}
 
 pub struct World {
 	self_weak: Weak<Self>,
 	players: RwLock<FxHashMap<PlayerId, Player>>,
-	entities: RwLock<Vec<Box<dyn Entity>>>,
+	// copied from the players whenever a player changes chunk, direction, joins or leaves,
+	// so the task runners don't have to lock the players every time they look for a task
+	viewers: RwLock<Arc<Vec<Viewer>>>,
+	// taken before the players lock is released when a player's load area changes, and held while its chunks are loaded and unloaded
+	// this keeps the players unlocked while chunks are loaded, but the loads and unloads still happen in the order the players changed,
+	// so a chunk is never unloaded by a move before the move that loaded it
+	load_area_lock: Mutex<()>,
+	entities: RwLock<EntityMap>,
+	// held while entities are updated, since each entity is taken out of the map while it updates,
+	// and the chunk it is in can't be unloaded without it until it is put back
+	entity_update_lock: Mutex<()>,
+	// the id the next entity gets, which is copied to the metadata when the world is saved
+	next_entity_id: AtomicU64,
 	pub chunks: FxDashMap<ChunkPos, LoadedChunk>,
 	cached_chunks: RwLock<FxHashMap<ChunkPos, ChunkData>>,
-	chunk_load_jobs: RwLock<Vec<ChunkLoadJob>>,
-	chunk_unload_jobs: RwLock<Vec<ChunkLoadJob>>,
-	pub(super) world_generator: WorldGenerator,
-	// backing file of the world
-	file: File,
+	pub tasks: TaskQueue,
+	// what is happening to every chunk that is loaded or being loaded
+	// chunks are only added to or removed from chunks while this is locked
+	chunk_states: Mutex<FxHashMap<ChunkPos, ChunkState>>,
+	// set when a chunk mesh has changed, until it is checked by poll_completed_tasks
+	mesh_changed: AtomicBool,
+	// blocks changed by set_block since they were last taken, so a server can send them to its clients
+	// None unless track_changed_blocks was called, since nothing would ever take them
+	changed_blocks: Mutex<Option<FxHashSet<BlockPos>>>,
+	world_generator: WorldGenerator,
+	// the world clock, which is copied to the metadata when the world is saved
+	time: AtomicU64,
+	metadata: RwLock<WorldMetadata>,
+	// the ids blocks are saved with, which is also what is sent to clients
+	block_ids: BlockIdMap,
+	// the chunks used by each light update that is running, so light updates touching the same chunks run 1 at a time
+	light_claims: LightClaims,
+	// None for a copy of a world which is running on a remote server, those are never saved
+	save: Option<WorldSave>,
 }
 
 impl World {
-	pub fn load_from_file<T: AsRef<Path>>(file_name: T) -> Result<Arc<Self>> {
-		let file = OpenOptions::new()
-			.read(true)
-			.write(true)
-			.open(file_name)?;
+	// returns true if there is a world saved in the given folder
+	pub fn exists<T: AsRef<Path>>(folder: T) -> bool {
+		WorldMetadata::exists(folder)
+	}
 
-		Ok(Arc::new_cyclic(|weak| Self {
-			self_weak: weak.clone(),
-			players: RwLock::new(FxHashMap::default()),
-			entities: RwLock::new(Vec::new()),
-			chunks: FxDashMap::default(),
-			cached_chunks: RwLock::new(FxHashMap::default()),
-			chunk_load_jobs: RwLock::new(Vec::new()),
-			chunk_unload_jobs: RwLock::new(Vec::new()),
-			world_generator: WorldGenerator::new(0),
-			file,
-		}))
+	// creates a new world in the given folder
+	pub fn create<T: AsRef<Path>>(folder: T, seed: u32) -> Result<Arc<Self>> {
+		let folder = folder.as_ref();
+		if Self::exists(folder) {
+			bail!("can't create world in {}, a world already exists there", folder.display());
+		}
+
+		let block_ids = BlockIdMap::from_registry();
+		let metadata = WorldMetadata {
+			format_version: STORAGE_FORMAT_VERSION,
+			generator_version: GENERATOR_VERSION,
+			seed,
+			spawn_position: [0.0, 0.0, 0.0],
+			block_names: block_ids.names().to_vec(),
+			time: START_TIME,
+			next_entity_id: 0,
+			players: Vec::new(),
+		};
+
+		let storage = WorldStorage::open(folder)?;
+		metadata.save(folder)?;
+
+		Ok(Self::new(metadata, block_ids, Some(WorldSave {
+			folder: folder.to_owned(),
+			storage,
+		})))
 	}
 
-	// TEMP
-	pub fn new_test() -> Result<Arc<Self>> {
-		let file = OpenOptions::new()
-			.read(true)
-			.write(true)
-			.open("test-world")?;
+	// opens the world saved in the given folder
+	pub fn open<T: AsRef<Path>>(folder: T) -> Result<Arc<Self>> {
+		let folder = folder.as_ref();
+		let mut metadata = WorldMetadata::load(folder)?;
+
+		if metadata.format_version != STORAGE_FORMAT_VERSION {
+			bail!(
+				"can't open world in {}, it uses storage format version {} but only version {} is supported",
+				folder.display(),
+				metadata.format_version,
+				STORAGE_FORMAT_VERSION,
+			);
+		}
+
+		if metadata.generator_version != GENERATOR_VERSION {
+			warn!(
+				"world in {} was generated with generator version {}, but the current generator version is {}, new chunks may not line up with old ones",
+				folder.display(),
+				metadata.generator_version,
+				GENERATOR_VERSION,
+			);
+		}
+
+		let block_ids = BlockIdMap::new(metadata.block_names.clone())
+			.with_context(|| format!("can't open world in {}, its block ids are invalid", folder.display()))?;
 
-		let out = Arc::new_cyclic(|weak| Self {
+		let missing_names = block_ids.missing_names().collect::<Vec<_>>();
+		if !missing_names.is_empty() {
+			warn!(
+				"world in {} contains blocks which don't exist anymore, they will be replaced with air: {}",
+				folder.display(),
+				missing_names.join(", "),
+			);
+		}
+
+		// blocks which were added since the world was last opened get new ids,
+		// which have to be saved before any chunks using them are saved
+		if block_ids.names() != metadata.block_names {
+			metadata.block_names = block_ids.names().to_vec();
+			metadata.save(folder)?;
+		}
+
+		let storage = WorldStorage::open(folder)?;
+
+		Ok(Self::new(metadata, block_ids, Some(WorldSave {
+			folder: folder.to_owned(),
+			storage,
+		})))
+	}
+
+	// creates an empty world which is filled in with chunks sent by a server
+	// block_ids must be the block ids used by the server
+	pub fn new_remote(block_ids: BlockIdMap) -> Arc<Self> {
+		Self::new(WorldMetadata {
+			format_version: STORAGE_FORMAT_VERSION,
+			generator_version: GENERATOR_VERSION,
+			seed: 0,
+			spawn_position: [0.0, 0.0, 0.0],
+			block_names: block_ids.names().to_vec(),
+			// the server sends its time once the client is connected
+			time: 0,
+			next_entity_id: 0,
+			players: Vec::new(),
+		}, block_ids, None)
+	}
+
+	fn new(metadata: WorldMetadata, block_ids: BlockIdMap, save: Option<WorldSave>) -> Arc<Self> {
+		Arc::new_cyclic(|weak| Self {
 			self_weak: weak.clone(),
 			players: RwLock::new(FxHashMap::default()),
-			entities: RwLock::new(Vec::new()),
+			viewers: RwLock::new(Arc::new(Vec::new())),
+			load_area_lock: Mutex::new(()),
+			entities: RwLock::new(EntityMap::default()),
+			entity_update_lock: Mutex::new(()),
+			next_entity_id: AtomicU64::new(metadata.next_entity_id),
 			chunks: FxDashMap::default(),
 			cached_chunks: RwLock::new(FxHashMap::default()),
-			chunk_load_jobs: RwLock::new(Vec::new()),
-			chunk_unload_jobs: RwLock::new(Vec::new()),
-			world_generator: WorldGenerator::new(0),
-			file,
+			tasks: TaskQueue::new(),
+			chunk_states: Mutex::new(FxHashMap::default()),
+			mesh_changed: AtomicBool::new(false),
+			changed_blocks: Mutex::new(None),
+			world_generator: WorldGenerator::new(metadata.seed),
+			time: AtomicU64::new(metadata.time),
+			metadata: RwLock::new(metadata),
+			block_ids,
+			light_claims: LightClaims::default(),
+			save,
+		})
+	}
+
+	pub fn block_ids(&self) -> &BlockIdMap {
+		&self.block_ids
+	}
+
+	fn arc(&self) -> Arc<Self> {
+		self.self_weak.upgrade().unwrap()
+	}
+
+	// runs a light update once no other light update is using the chunks around center,
+	// and returns the boxes of blocks whose light changed, which are (min, max) not including max
+	// light only spreads into the chunks next to center, except for sky light going down, so these are claimed before starting
+	fn update_light<F: Fn(&mut LightUpdate)>(&self, center: ChunkPos, f: F) -> Vec<(BlockPos, BlockPos)> {
+		let mut claim = FxHashSet::default();
+		for x in -1..=1 {
+			for y in -1..=1 {
+				for z in -1..=1 {
+					claim.insert(center + ChunkPos::new(x, y, z));
+				}
+			}
+		}
+
+		loop {
+			let mut update = LightUpdate::new(self, self.light_claims.claim(claim.clone()));
+			f(&mut update);
+			match update.finish() {
+				Ok(light_changes) => return light_changes,
+				// another update was using chunks this one needed, so it is run again with those chunks claimed from the start
+				Err(missed) => claim.extend(missed),
+			}
+		}
+	}
+
+	// loads the chunk from the unloaded chunk cache or from disk if it has been saved before, otherwise generates it
+	pub fn load_or_generate_chunk(&self, position: ChunkPos) -> LoadedChunk {
+		let chunk_data = self.load_chunk_data(position);
+		self.add_chunk_entities(chunk_data)
+	}
+
+	// like load_or_generate_chunk, but the entities are returned instead of being added to the world
+	fn load_chunk_data(&self, position: ChunkPos) -> ChunkData {
+		if let Some(chunk_data) = self.cached_chunks.write().remove(&position) {
+			return chunk_data;
+		}
+
+		if let Some(save) = &self.save {
+			match save.storage.load_chunk(position) {
+				Ok(Some(data)) => match ChunkData::from_bytes(self.arc(), position, &data) {
+					Ok(chunk_data) => return chunk_data,
+					Err(error) => error!("could not load saved chunk {:?}, regenerating it: {}", position, error),
+				},
+				Ok(None) => (),
+				Err(error) => error!("could not read chunk {:?} from disk, regenerating it: {}", position, error),
+			}
+		}
+
+		ChunkData {
+			chunk: self.world_generator.generate_chunk(self.arc(), position).chunk,
+			entities: Vec::new(),
+		}
+	}
+
+	// puts the entities of a chunk which is being loaded back in the world
+	fn add_chunk_entities(&self, chunk_data: ChunkData) -> LoadedChunk {
+		let mut entities = self.entities.write();
+		for entity in chunk_data.entities {
+			// ids given out since the chunk was saved may not have been saved in the metadata
+			self.next_entity_id.fetch_max(entity.id().as_u64() + 1, Ordering::AcqRel);
+			entities.insert(entity);
+		}
+		LoadedChunk::new(chunk_data.chunk)
+	}
+
+	// runs the generate task of the chunk if the chunk is still needed
+	pub fn load_chunk(&self, position: ChunkPos, task: TaskHandle) {
+		let mut chunk_states = self.chunk_states.lock();
+		match chunk_states.get_mut(&position) {
+			Some(state) => match state.status {
+				ChunkStatus::Queued(handle) if handle == task => state.status = ChunkStatus::Generating(handle),
+				// the task is from before the chunk was unloaded and loaded again
+				_ => return,
+			},
+			// the chunk was unloaded before it was generated
+			None => return,
+		}
+		drop(chunk_states);
+
+		let chunk = self.load_or_generate_chunk(position);
+
+		let (update_lock, mut chunk_states) = self.lock_chunk_states_for_removal(position, |state| state.ref_count == 0);
+		let state = chunk_states.get_mut(&position).expect("state of generating chunk was removed");
+		if state.ref_count == 0 {
+			// the chunk was unloaded while it was generating
+			let cached = self.cache_chunk(chunk.chunk);
+			chunk_states.remove(&position);
+			drop(chunk_states);
+			drop(update_lock);
+
+			if cached {
+				self.tasks.run(Task::SaveCachedChunks);
+			}
+		} else {
+			self.chunks.insert(position, chunk);
+			state.status = ChunkStatus::Loaded;
+			drop(chunk_states);
+
+			// the chunk's own mesh task waits for this task, so it is meshed with its light
+			let light_changes = self.update_light(position, |light| light.light_new_chunk(position));
+			self.mesh_update_around_chunk(position, &light_changes);
+		}
+	}
+
+	// runs a mesh task for the given layers of the chunk if it is loaded
+	pub fn mesh_chunk(&self, position: ChunkPos, layers: MeshLayers) {
+		let mut chunk_states = self.chunk_states.lock();
+		match chunk_states.get_mut(&position) {
+			Some(state) => {
+				match state.status {
+					ChunkStatus::Loaded => state.status = ChunkStatus::Meshing(1),
+					ChunkStatus::Meshing(count) => state.status = ChunkStatus::Meshing(count + 1),
+					_ => return,
+				}
+
+				if layers == MeshLayers::ALL {
+					state.needs_mesh = false;
+				}
+			},
+			None => return,
+		}
+		drop(chunk_states);
+
+		if let Some(chunk) = self.chunks.get(&position) {
+			chunk.chunk.mesh_update_layers(layers);
+		}
+
+		let (update_lock, mut chunk_states) = self.lock_chunk_states_for_removal(position, |state| {
+			state.status == ChunkStatus::Meshing(1) && state.ref_count == 0
 		});
+		// the chunk may have been removed from a remote world
+		let state = match chunk_states.get_mut(&position) {
+			Some(state) => state,
+			None => return,
+		};
+
+		match state.status {
+			ChunkStatus::Meshing(count) if count > 1 => state.status = ChunkStatus::Meshing(count - 1),
+			// the chunk was unloaded while it was meshing, and the unload task left it for us to remove
+			ChunkStatus::Meshing(_) if state.ref_count == 0 => {
+				let cached = self.chunks.remove(&position)
+					.map(|(_, removed_chunk)| self.cache_chunk(removed_chunk.chunk));
+				chunk_states.remove(&position);
+				drop(chunk_states);
+				drop(update_lock);
+
+				if let Some(cached) = cached {
+					self.mesh_update_around_chunk(position, &[]);
+					if cached {
+						self.tasks.run(Task::SaveCachedChunks);
+					}
+				}
+			},
+			ChunkStatus::Meshing(_) => state.status = ChunkStatus::Loaded,
+			// a remote chunk was removed and sent again while meshing
+			_ => (),
+		}
+	}
+
+	// runs the unload task of the chunk if it is still not needed
+	// returns true if the chunk was added to the unloaded chunk cache
+	pub fn unload_chunk(&self, position: ChunkPos) -> bool {
+		let (update_lock, mut chunk_states) = self.lock_chunk_states_for_removal(position, |state| state.status == ChunkStatus::Unloading);
+		match chunk_states.get(&position) {
+			Some(state) if state.status == ChunkStatus::Unloading => (),
+			// the chunk was loaded again, or is meshing and will be unloaded when that is finished
+			_ => return false,
+		}
 
-		Ok(out)
+		let cached = self.chunks.remove(&position)
+			.map(|(_, removed_chunk)| self.cache_chunk(removed_chunk.chunk));
+		chunk_states.remove(&position);
+		drop(chunk_states);
+		drop(update_lock);
+
+		match cached {
+			Some(cached) => {
+				self.mesh_update_around_chunk(position, &[]);
+				cached
+			},
+			None => false,
+		}
+	}
+
+	// locks chunk_states, and if the state of the chunk says it is about to be removed, also takes the entity update lock
+	// a removed chunk has to be in the cache with its entities before its state is removed, otherwise it could be loaded again
+	// from disk before that, and the update lock has to be taken first, so the chunk states are unlocked while waiting for it
+	fn lock_chunk_states_for_removal<F: Fn(&ChunkState) -> bool>(&self, position: ChunkPos, will_remove: F)
+		-> (Option<MutexGuard<'_, ()>>, MutexGuard<'_, FxHashMap<ChunkPos, ChunkState>>) {
+		let chunk_states = self.chunk_states.lock();
+		if !chunk_states.get(&position).map_or(false, &will_remove) {
+			return (None, chunk_states);
+		}
+		drop(chunk_states);
+
+		// the state may change while the chunk states are unlocked, so the caller has to check it again
+		let update_lock = self.entity_update_lock.lock();
+		(Some(update_lock), self.chunk_states.lock())
 	}
 
-	// TODO: refresh meshes of adjacent chunks when loading is finished
-	// TODO: handle unloading of chunks before they finish loading
-	// loads all chunks between min_chunk and max_chunk not including max_chunk,
-	// or incraments the load count if they are already loaded
-	pub fn load_chunks(&self, min_chunk: ChunkPos, max_chunk: ChunkPos, mesh_face_task: Option<ChunkMeshFaceData>) {
-		self.chunk_load_jobs.write().push(ChunkLoadJob {
-			min_chunk,
-			max_chunk,
-			remaining_chunks: ((max_chunk.x - min_chunk.x) * (max_chunk.y - min_chunk.y) * (max_chunk.z - min_chunk.z))
-				.try_into().unwrap(),
-			mesh_face_task,
+	// returns false if the task is for a chunk which doesn't need it anymore, so the task can be skipped
+	pub fn is_task_needed(&self, handle: TaskHandle, task: &Task) -> bool {
+		let chunk_states = self.chunk_states.lock();
+		match task {
+			Task::GenerateChunk(position) => matches!(
+				chunk_states.get(position),
+				Some(ChunkState { status: ChunkStatus::Queued(queued_handle), .. }) if *queued_handle == handle
+			),
+			Task::ChunkMesh(position) => matches!(
+				chunk_states.get(position),
+				Some(ChunkState { status: ChunkStatus::Loaded | ChunkStatus::Meshing(_), .. })
+			),
+			Task::ChunkMeshLayers { chunk, .. } => matches!(
+				chunk_states.get(chunk),
+				Some(ChunkState { status: ChunkStatus::Loaded | ChunkStatus::Meshing(_), needs_mesh: false, .. })
+			),
+			Task::UnloadChunks { .. } | Task::SaveCachedChunks => true,
+		}
+	}
+
+	// writes the chunk and the entities in it to disk if it has been modified since it was last saved
+	// the chunk is made dirty when an entity in it changes, see update_entities
+	pub fn save_chunk(&self, chunk: &Chunk, entities: &[SavedEntity]) -> Result<()> {
+		let save = match &self.save {
+			Some(save) => save,
+			None => return Ok(()),
+		};
+
+		if chunk.clear_dirty() {
+			let result = ChunkData::to_bytes(chunk, entities)
+				.and_then(|data| save.storage.save_chunk(chunk.chunk_position(), &data));
+			if let Err(error) = result {
+				// the chunk still needs to be saved
+				chunk.set_dirty();
+				return Err(error);
+			}
+		}
+		Ok(())
+	}
+
+	// called when a chunk is unloaded, which takes the entities in the chunk out of the world
+	// modified chunks are kept in memory until they are saved, other chunks are just dropped,
+	// since the entities in them are the same as the ones on disk
+	// returns true if the chunk was cached
+	// the entity update lock and chunk_states have to be held until the state of the chunk is removed,
+	// so the chunk can't be loaded again before it is cached, or have entities move into it after they are taken
+	fn cache_chunk(&self, chunk: Chunk) -> bool {
+		let entities = self.entities.write().take_chunk(chunk.chunk_position());
+		if self.save.is_none() || !chunk.is_dirty() {
+			return false;
+		}
+
+		self.cached_chunks.write().insert(chunk.chunk_position(), ChunkData {
+			chunk,
+			entities,
 		});
+		true
+	}
 
-		for x in min_chunk.x..max_chunk.x {
-			for y in min_chunk.y..max_chunk.y {
-				for z in min_chunk.z..max_chunk.z {
-					let position = ChunkPos::new(x, y, z);
+	fn saved_entities<'a>(&self, entities: impl Iterator<Item = &'a dyn Entity>) -> Vec<SavedEntity> {
+		entities.map(|entity| entity.save(self)).collect()
+	}
+
+	// entities can be spawned in chunks that aren't loaded, and they would never be saved if the chunk is never loaded,
+	// so they are put in the unloaded chunk cache with the chunk they are in
+	// this may have to generate the chunk, but it is rare for entities to be spawned outside of loaded chunks
+	fn cache_unloaded_entities(&self) {
+		if self.save.is_none() {
+			return;
+		}
 
-					run_task(Task::GenerateChunk(position));
+		// taken before chunk_states, which is the same order update_entities takes them in
+		let _update_lock = self.entity_update_lock.lock();
+
+		let positions = self.entities.read().chunks()
+			.filter(|chunk| !self.chunks.contains_key(chunk))
+			.collect::<Vec<_>>();
+
+		for position in positions {
+			// chunks that are loading take the entities in them once they are loaded
+			// the lock is held until the chunk is in the cache, so the chunk can't start loading without these entities
+			let chunk_states = self.chunk_states.lock();
+			if chunk_states.contains_key(&position) {
+				continue;
+			}
+
+			let mut chunk_data = self.load_chunk_data(position);
+			chunk_data.entities.extend(self.entities.write().take_chunk(position));
+			chunk_data.chunk.set_dirty();
+			self.cached_chunks.write().insert(position, chunk_data);
+		}
+	}
+
+	// writes all the cached chunks to disk and removes them from the cache
+	// chunks that fail to save stay in the cache, and the errors of all of them are returned once every chunk has been tried
+	pub fn save_cached_chunks(&self) -> Result<()> {
+		let positions = self.cached_chunks.read().keys().copied().collect::<Vec<_>>();
+		let mut errors = Vec::new();
+
+		for &position in positions.iter() {
+			// hold the read lock while saving, so the chunk can't be loaded again from disk before it is written
+			let cached_chunks = self.cached_chunks.read();
+			if let Some(chunk_data) = cached_chunks.get(&position) {
+				let entities = self.saved_entities(chunk_data.entities.iter().map(|entity| &**entity));
+				if let Err(error) = self.save_chunk(&chunk_data.chunk, &entities) {
+					errors.push(format!("chunk {:?}: {:#}", position, error));
+					continue;
 				}
 			}
+			drop(cached_chunks);
+
+			// if the chunk was loaded, modified, and unloaded again while saving, it is dirty again and has to stay in the cache
+			let mut cached_chunks = self.cached_chunks.write();
+			if matches!(cached_chunks.get(&position), Some(chunk_data) if !chunk_data.chunk.is_dirty()) {
+				cached_chunks.remove(&position);
+			}
 		}
+
+		if !errors.is_empty() {
+			bail!("could not save {} of {} cached chunks: {}", errors.len(), positions.len(), errors.join(", "));
+		}
+		Ok(())
 	}
 
-	// decraments the load counter of all chunks between min and max chunk, not including max
-	// and unloads them if the count reaches 0
-	// TODO: refresh meshes of adjacent chunks when unloading is finished
-	// TODO: handle unloading before loading is finished
-	pub fn unload_chunks(&self, min_chunk: ChunkPos, max_chunk: ChunkPos, mesh_face_task: Option<ChunkMeshFaceData>) {
-		self.chunk_load_jobs.write().push(ChunkLoadJob {
-			min_chunk,
-			max_chunk,
-			remaining_chunks: 1,
-			mesh_face_task,
-		});
+	// saves the world metadata and all modified chunks which are currently loaded or cached
+	pub fn save(&self) -> Result<()> {
+		let save = match &self.save {
+			Some(save) => save,
+			None => return Ok(()),
+		};
+
+		for chunk in self.chunks.iter() {
+			if chunk.chunk.is_dirty() {
+				let entities = self.saved_entities(self.entities.read().in_chunk(*chunk.key()));
+				self.save_chunk(&chunk.chunk, &entities)?;
+			}
+		}
+		self.cache_unloaded_entities();
+		self.save_cached_chunks()?;
+		save.storage.flush()?;
+
+		let mut metadata = self.metadata.write();
+		metadata.time = self.time();
+		metadata.next_entity_id = self.next_entity_id.load(Ordering::Acquire);
+		for player in self.players.read().values() {
+			if let Some(name) = player.name() {
+				metadata.update_player(SavedPlayer {
+					name: name.to_owned(),
+					position: player.position.into(),
+				});
+			}
+		}
+		metadata.save(&save.folder)
+	}
 
-		run_task(Task::UnloadChunks {
-			min_chunk,
-			max_chunk,
-		});
+	// runs 1 tick of the world, this should be called TICKS_PER_SECOND times a second by the thread that owns the world
+	// this is everything the world needs to keep running, so a server can run it without any client
+	pub fn tick(&self) {
+		self.time.fetch_add(1, Ordering::AcqRel);
+		self.tasks.run_callbacks(self);
+		self.update_entities();
 	}
 
-	// performs mesh updates on the passed in block as well as all adjacent blocks
-	// FIXME: this doesn't update everything it needs to with ambient occlusion on chunk boundaries
-	pub fn mesh_update_adjacent(&self, block: BlockPos) {
-		let block_chunk_local = block.as_chunk_local();
-		let mut visit_map = VisitedBlockMap::new();
-
-		if let Some(chunk) = self.chunks.get(&block.as_chunk_pos()) {
-			chunk.chunk.mesh_update_inner(BlockFace::XPos, block_chunk_local.x as usize, &mut visit_map);
-			chunk.chunk.mesh_update_inner(BlockFace::XNeg, block_chunk_local.x as usize, &mut visit_map);
-			chunk.chunk.mesh_update_inner(BlockFace::YPos, block_chunk_local.y as usize, &mut visit_map);
-			chunk.chunk.mesh_update_inner(BlockFace::YNeg, block_chunk_local.y as usize, &mut visit_map);
-			chunk.chunk.mesh_update_inner(BlockFace::ZPos, block_chunk_local.z as usize, &mut visit_map);
-			chunk.chunk.mesh_update_inner(BlockFace::ZNeg, block_chunk_local.z as usize, &mut visit_map);
-		}
-
-		for face in BlockFace::iter() {
-			// subtract to get opposite as normal offest
-			let offset_block = block - face.block_pos_offset();
-			if let Some(chunk) = self.chunks.get(&offset_block.as_chunk_pos()) {
-				chunk.chunk.mesh_update_inner(
-					face,
-					offset_block.as_chunk_local().get_face_component(face) as usize,
-					&mut visit_map
-				);
+	// updates every entity in a loaded chunk
+	// entities in chunks that aren't loaded wait there until the chunk is loaded
+	fn update_entities(&self) {
+		let _update_lock = self.entity_update_lock.lock();
+
+		let ids = {
+			let entities = self.entities.read();
+			entities.chunks()
+				.filter(|chunk| self.chunks.contains_key(chunk))
+				.flat_map(|chunk| entities.ids_in_chunk(chunk))
+				.collect::<Vec<_>>()
+		};
+
+		for id in ids {
+			// the entity is taken out of the map so the update can look at the other entities
+			let mut entity = match self.entities.write().remove(id) {
+				Some(entity) => entity,
+				None => continue,
+			};
+			let (old_position, old_velocity) = (entity.position(), entity.velocity());
+			let alive = entity.update(self);
+
+			// the chunks the entity is saved in have to be written again, entities which stay still don't cause a save
+			if !alive || entity.position() != old_position || entity.velocity() != old_velocity {
+				self.set_chunk_dirty(old_position.into_chunk_pos());
+				self.set_chunk_dirty(entity.position().into_chunk_pos());
+			}
+
+			if alive {
+				self.entities.write().insert(entity);
 			}
 		}
 	}
 
-	pub fn chunk_mesh_update(&self, min_chunk: ChunkPos, max_chunk: ChunkPos) {
+	fn set_chunk_dirty(&self, chunk: ChunkPos) {
+		if let Some(chunk) = self.chunks.get(&chunk) {
+			chunk.chunk.set_dirty();
+		}
+	}
+
+	// returns a unique entity id
+	pub fn new_entity_id(&self) -> EntityId {
+		EntityId::from_u64(self.next_entity_id.fetch_add(1, Ordering::AcqRel))
+	}
+
+	// adds the entity to the world, it starts updating once the chunk it is in is loaded
+	pub fn spawn_entity(&self, entity: Box<dyn Entity>) {
+		self.set_chunk_dirty(entity.position().into_chunk_pos());
+		self.entities.write().insert(entity);
+	}
+
+	// removes the entity from the world, returns false if it wasn't in the world
+	pub fn remove_entity(&self, id: EntityId) -> bool {
+		match self.entities.write().remove(id) {
+			Some(entity) => {
+				self.set_chunk_dirty(entity.position().into_chunk_pos());
+				true
+			},
+			None => false,
+		}
+	}
+
+	// calls the function on the entity, or returns None if it isn't in the world
+	// this can't be used on the entity that is updating from its own update
+	pub fn with_entity<T, F>(&self, id: EntityId, f: F) -> Option<T>
+		where F: FnOnce(&dyn Entity) -> T {
+		Some(f(self.entities.read().get(id)?))
+	}
+
+	// returns the entities whose box overlaps the area
+	pub fn entities_in_box(&self, area: &Aabb) -> Vec<EntityId> {
+		self.entities.read().in_box(area)
+	}
+
+	// number of ticks the world has run for
+	pub fn time(&self) -> u64 {
+		self.time.load(Ordering::Acquire)
+	}
+
+	pub fn set_time(&self, time: u64) {
+		self.time.store(time, Ordering::Release);
+	}
+
+	pub fn add_time(&self, ticks: u64) {
+		self.time.fetch_add(ticks, Ordering::AcqRel);
+	}
+
+	// ticks since the start of the current day
+	pub fn time_of_day(&self) -> u64 {
+		self.time() % TICKS_PER_DAY
+	}
+
+	// changes the time of day without changing the day, see clock::parse_time_of_day
+	pub fn set_time_of_day(&self, time_of_day: u64) {
+		let day = self.time() / TICKS_PER_DAY;
+		self.set_time(day * TICKS_PER_DAY + time_of_day % TICKS_PER_DAY);
+	}
+
+	pub fn spawn_position(&self) -> Position {
+		self.metadata.read().spawn_position.into()
+	}
+
+	// loads all chunks between min_chunk and max_chunk not including max_chunk
+	// each chunk stays loaded until unload_chunks has been called on it as many times as load_chunks
+	pub fn load_chunks(&self, min_chunk: ChunkPos, max_chunk: ChunkPos) {
+		let mut chunk_states = self.chunk_states.lock();
+		let mut queued_chunks = Vec::new();
+
+		for x in min_chunk.x..max_chunk.x {
+			for y in min_chunk.y..max_chunk.y {
+				for z in min_chunk.z..max_chunk.z {
+					let position = ChunkPos::new(x, y, z);
+
+					match chunk_states.get_mut(&position) {
+						Some(state) => {
+							state.ref_count += 1;
+							// the unload task will see the chunk is loaded and leave it alone
+							// mesh tasks are skipped while unloading, so the mesh could be out of date
+							if state.status == ChunkStatus::Unloading {
+								state.status = ChunkStatus::Loaded;
+								state.needs_mesh = true;
+								self.tasks.spawn(Task::ChunkMesh(position), &[], Some(Self::mesh_changed_callback()));
+							}
+						},
+						None => {
+							let handle = self.tasks.run(Task::GenerateChunk(position));
+							chunk_states.insert(position, ChunkState {
+								status: ChunkStatus::Queued(handle),
+								ref_count: 1,
+								needs_mesh: true,
+							});
+							queued_chunks.push(position);
+						},
+					}
+				}
+			}
+		}
+
+		// each chunk is meshed once it and all the chunks around it are generated, since meshing looks at the neighbors
+		// the neighbors would remesh the chunk when they are loaded anyways, but this way most of that is skipped
+		for position in queued_chunks {
+			let mut dependencies = Vec::new();
+			for x in -1..=1 {
+				for y in -1..=1 {
+					for z in -1..=1 {
+						let chunk = position + ChunkPos::new(x, y, z);
+						if let Some(ChunkStatus::Queued(handle) | ChunkStatus::Generating(handle)) = chunk_states.get(&chunk).map(|state| state.status) {
+							dependencies.push(handle);
+						}
+					}
+				}
+			}
+
+			self.tasks.spawn(Task::ChunkMesh(position), &dependencies, Some(Self::mesh_changed_callback()));
+		}
+	}
+
+	// undoes 1 call to load_chunks for all chunks between min and max chunk, not including max
+	// chunks which aren't loaded by anything else anymore are unloaded
+	pub fn unload_chunks(&self, min_chunk: ChunkPos, max_chunk: ChunkPos) {
+		let mut chunk_states = self.chunk_states.lock();
+		let mut unloading = false;
+
 		for x in min_chunk.x..max_chunk.x {
 			for y in min_chunk.y..max_chunk.y {
 				for z in min_chunk.z..max_chunk.z {
-					let chunk_pos = ChunkPos::new(x, y, z);
-					run_task(Task::ChunkMesh(chunk_pos));
+					let position = ChunkPos::new(x, y, z);
+
+					// unbalanced unloads are a bug in the caller, but they are ignored instead of crashing the game
+					let state = match chunk_states.get_mut(&position) {
+						Some(state) => state,
+						None => {
+							warn!("tried to unload chunk {:?} which was not loaded", position);
+							continue;
+						},
+					};
+
+					state.ref_count = match state.ref_count.checked_sub(1) {
+						Some(ref_count) => ref_count,
+						None => {
+							warn!("tried to unload chunk {:?} which is already being unloaded", position);
+							continue;
+						},
+					};
+					if state.ref_count > 0 {
+						continue;
+					}
+
+					match state.status {
+						// the mesh tasks waiting on the generate task will see the chunk is gone and do nothing
+						ChunkStatus::Queued(handle) => {
+							chunk_states.remove(&position);
+							self.tasks.cancel(handle);
+						},
+						ChunkStatus::Loaded => {
+							state.status = ChunkStatus::Unloading;
+							unloading = true;
+						},
+						// these remove the chunk once they are finished
+						ChunkStatus::Generating(_) | ChunkStatus::Meshing(_) => (),
+						// unloading chunks always have a ref count of 0, so checked_sub already failed
+						ChunkStatus::Unloading => (),
+					}
 				}
 			}
 		}
+
+		if unloading {
+			self.tasks.spawn(Task::UnloadChunks {
+				min_chunk,
+				max_chunk,
+			}, &[], Some(Self::mesh_changed_callback()));
+		}
+	}
+
+	// tells the client to update the mesh once a task is finished
+	fn mesh_changed_callback() -> TaskCallback {
+		Box::new(|world| world.mesh_changed.store(true, Ordering::Release))
+	}
+
+	// remeshes every layer which could have changed after the block changed,
+	// which includes the layers around it in other chunks, since they use it for ambient occlusion,
+	// and the layers lit by the blocks whose light changed
+	fn mesh_update_adjacent(&self, block: BlockPos, light_changes: &[(BlockPos, BlockPos)]) {
+		let mut affected_layers = mesh_layers_affected_by(block, block + BlockPos::repeat(1));
+		add_light_mesh_layers(&mut affected_layers, light_changes);
+
+		for (chunk_position, layers) in affected_layers {
+			if let Some(chunk) = self.chunks.get(&chunk_position) {
+				chunk.chunk.mesh_update_layers(layers);
+			}
+		}
+	}
+
+	// adds a chunk which was sent by the server to a remote world
+	pub fn insert_remote_chunk(&self, position: ChunkPos, data: &[u8]) -> Result<()> {
+		let chunk = LoadedChunk::new(Chunk::from_bytes(self.arc(), position, data)?);
+
+		let mut chunk_states = self.chunk_states.lock();
+		self.chunks.insert(position, chunk);
+		// if the server sent the chunk again, it may still be meshing
+		chunk_states.entry(position).or_insert(ChunkState {
+			status: ChunkStatus::Loaded,
+			ref_count: 1,
+			needs_mesh: true,
+		});
+		drop(chunk_states);
+
+		let light_changes = self.update_light(position, |light| light.light_new_chunk(position));
+		self.tasks.spawn(Task::ChunkMesh(position), &[], Some(Self::mesh_changed_callback()));
+		self.mesh_update_around_chunk(position, &light_changes);
+		Ok(())
+	}
+
+	// removes a chunk from a remote world once the server says it is out of range
+	pub fn remove_remote_chunk(&self, position: ChunkPos) {
+		let mut chunk_states = self.chunk_states.lock();
+		chunk_states.remove(&position);
+		let removed = self.chunks.remove(&position);
+		drop(chunk_states);
+
+		// the light that came from the chunk is left in the chunks around it until they are lit again
+		if removed.is_some() {
+			self.mesh_update_around_chunk(position, &[]);
+		}
+	}
+
+	// remeshes the parts of the chunks around the chunk which depend on it, after it was added to or removed from chunks,
+	// and the parts of other chunks whose light changed when it was lit
+	// the chunk itself is meshed by its own mesh task
+	fn mesh_update_around_chunk(&self, position: ChunkPos, light_changes: &[(BlockPos, BlockPos)]) {
+		let min_block = position * CHUNK_SIZE as i64;
+		let max_block = min_block + BlockPos::repeat(CHUNK_SIZE as i64);
+
+		let mut affected_layers = mesh_layers_affected_by(min_block, max_block);
+		add_light_mesh_layers(&mut affected_layers, light_changes);
+
+		for (chunk, layers) in affected_layers {
+			if chunk != position && self.chunks.contains_key(&chunk) {
+				self.tasks.spawn(Task::ChunkMeshLayers {
+					chunk,
+					layers,
+				}, &[], Some(Self::mesh_changed_callback()));
+			}
+		}
 	}
 
 	#[inline]
@@ -204,30 +978,38 @@ impl World {
 		where F: FnOnce(&Block) -> T {
 		let (chunk_position, block) = block.as_chunk_block_pos();
 
-		Some(f(&*self.chunks.get(&chunk_position)?
+		Some(f(&self.chunks.get(&chunk_position)?
 			.chunk.get_block(block.as_chunk_local())))
 	}
 
-	// calls the function on the given block position
-	// the block may be from another chunk
-	#[inline]
-	fn with_block_mut<T, F>(&mut self, block: BlockPos, f: F) -> Option<T>
-		where F: FnOnce(&mut Block) -> T {
-		let (chunk_position, block) = block.as_chunk_block_pos();
+	// sets the block at BlockPos and updates the light and mesh around it, returns bool on success
+	pub fn set_block(&self, block_pos: BlockPos, block: Block) -> bool {
+		let (chunk_pos, chunk_block_pos) = block_pos.as_chunk_block_pos();
 
-		Some(f(&mut *self.chunks.get(&chunk_position)?
-			.chunk.get_block_mut(block.as_chunk_local())))
+		match self.chunks.get(&chunk_pos) {
+			Some(chunk) => chunk.chunk.set_block(chunk_block_pos, block),
+			None => return false,
+		}
+
+		if let Some(changed_blocks) = self.changed_blocks.lock().as_mut() {
+			changed_blocks.insert(block_pos);
+		}
+
+		let light_changes = self.update_light(chunk_pos, |light| light.light_changed_block(block_pos));
+		self.mesh_update_adjacent(block_pos, &light_changes);
+		true
 	}
 
-	// sets the block at BlockPos, returns bool on success
-	pub fn set_block(&self, block_pos: BlockPos, block: Block) -> bool {
-		let (chunk_pos, block_pos) = block_pos.as_chunk_block_pos();
+	// makes set_block remember which blocks it changed until they are taken by take_changed_blocks
+	pub fn track_changed_blocks(&self) {
+		self.changed_blocks.lock().get_or_insert_with(FxHashSet::default);
+	}
 
-		if let Some(chunk) = self.chunks.get(&chunk_pos) {
-			chunk.chunk.set_block(block_pos, block);
-			true
-		} else {
-			false
+	// returns the blocks which were changed since this was last called, whether by players or by the world itself
+	pub fn take_changed_blocks(&self) -> Vec<BlockPos> {
+		match self.changed_blocks.lock().as_mut() {
+			Some(changed_blocks) => changed_blocks.drain().collect(),
+			None => Vec::new(),
 		}
 	}
 
@@ -294,200 +1076,977 @@ impl World {
 		}
 	}
 
-	// called by the client to force the world to recieve task completion notices
-	// returns true if the mesh should be updated by the client
-	pub fn poll_completed_tasks(&self) -> bool {
-		let mut update_mesh = false;
-		while let Some(task) = pull_completed_task() {
-			match task {
-				Task::ChunkMesh(_) => update_mesh = true,
-				Task::ChunkMeshFace { .. } => update_mesh = true,
-				Task::GenerateChunk(chunk) => {
-					let mut load_jobs = self.chunk_load_jobs.write();
-
-					let mut drain_iter = load_jobs.drain_filter(|job| {
-						// find out if the chunk is part of this job
-						if job.contains_chunk(chunk) {
-							job.remaining_chunks -= 1;
-							// remove the job if there are no more remaining chunks to temove
-							job.remaining_chunks == 0
-						} else {
-							false
-						}
-					});
-
-					if let Some(finished_job) = drain_iter.next() {
-						self.chunk_mesh_update(finished_job.min_chunk, finished_job.max_chunk);
-						if let Some(mesh_face_task) = finished_job.mesh_face_task {
-							run_task(mesh_face_task.into_task());
-						}
-					}
-				},
-				Task::UnloadChunks { min_chunk, max_chunk } => {
-					// recreate mesh because chunks have been removed, but we don't actually have to generate their meshes
-					update_mesh = true;
-
-					let mut unload_jobs = self.chunk_unload_jobs.write();
-
-					let mut drain_iter = unload_jobs.drain_filter(|job| {
-						// find out if the chunk is part of this job
-						job.min_chunk == min_chunk && job.max_chunk == max_chunk
-					});
+	// returns None if the block's chunk isn't loaded
+	pub fn get_block(&self, block: BlockPos) -> Option<Block> {
+		self.with_block(block, |block| block.clone())
+	}
 
-					if let Some(finished_job) = drain_iter.next() {
-						if let Some(mesh_face_task) = finished_job.mesh_face_task {
-							run_task(mesh_face_task.into_task());
-						}
+	// gets the collision boxes of every block touching the area
+	// blocks in chunks that aren't loaded are solid, so nothing falls out of the loaded part of the world
+	pub fn collision_boxes(&self, area: &Aabb) -> Vec<Aabb> {
+		let min = area.min.into_block_pos();
+		let max = area.max.into_block_pos();
+
+		let mut boxes = Vec::new();
+		for x in min.x..=max.x {
+			for y in min.y..=max.y {
+				for z in min.z..=max.z {
+					let block = BlockPos::new(x, y, z);
+					if self.with_block(block, |b| block_collision_boxes(b, block, &mut boxes)).is_none() {
+						boxes.push(Aabb::block(block));
 					}
 				}
 			}
 		}
-		update_mesh
+		boxes
+	}
+
+	// runs the callbacks of finished tasks, this should be called regularly by the thread that owns the world
+	// returns true if the mesh should be updated by the client
+	pub fn poll_completed_tasks(&self) -> bool {
+		self.tasks.run_callbacks(self);
+		self.mesh_changed.swap(false, Ordering::AcqRel)
+	}
+
+	// blocks until every task has finished and their callbacks have run
+	pub fn run_until_idle(&self) {
+		while !self.tasks.is_idle() {
+			self.poll_completed_tasks();
+			thread::sleep(Duration::from_millis(1));
+		}
 	}
 }
 
 impl World {
-	pub fn connect(&self) -> PlayerId {
-		let player = Player::new();
+	// the player is placed back where the player with the same name was last saved, or at spawn if they haven't joined before
+	// returns an error if a player with the same name is already in the world
+	pub fn connect(&self, name: &str) -> Result<PlayerId> {
+		let position = match self.metadata.read().players.iter().find(|elem| elem.name == name) {
+			Some(saved_player) => saved_player.position.into(),
+			None => self.spawn_position(),
+		};
+		let player = Player::new(Some(name.to_owned()), position);
+
+		let players = self.players.write();
+		if players.values().any(|player| player.name() == Some(name)) {
+			bail!("a player named {} is already in the world", name);
+		}
+		Ok(self.add_player(players, player))
+	}
 
-		let min_load_chunk = player.chunk_position() - player.render_distance();
-		let max_load_chunk = player.chunk_position() + player.render_distance();
-		self.load_chunks(min_load_chunk, max_load_chunk, None);
+	// adds a player at spawn which is never saved, so the area around spawn stays loaded
+	pub fn keep_spawn_loaded(&self) -> PlayerId {
+		let player = Player::new(None, self.spawn_position());
+		self.add_player(self.players.write(), player)
+	}
 
+	// takes the lock on the players so checking for other players and adding the player happen at the same time
+	fn add_player(&self, mut players: RwLockWriteGuard<FxHashMap<PlayerId, Player>>, player: Player) -> PlayerId {
 		let id = player.id();
-		self.players.write().insert(id, player);
+		let (min_load_chunk, max_load_chunk) = player.load_area();
+
+		players.insert(id, player);
+		self.update_viewers(&players);
+		let _load_area_lock = self.load_area_lock.lock();
+		drop(players);
+
+		self.load_chunks(min_load_chunk, max_load_chunk);
 		id
 	}
 
-	// TODO: allow changing from more than 1 chunk at at a time
-	// TODO: when going along diaganols, sometimes chunks are loaded and immediately unloaded
-	// TEMP: returns true if mesh has changed
-	// FIXME: ugly
+	// moves the player and loads and unloads chunks if it went into another chunk, this is how every player moves
+	// returns None if the player doesn't exist, and Some(true) if its load area changed, which means the mesh will change
 	pub fn set_player_position(&self, player_id: PlayerId, position: Position) -> Option<bool> {
 		let mut players = self.players.write();
 		let player = players.get_mut(&player_id)?;
 
-		let chunk_position = position.into_chunk_pos();
+		let old_chunk = player.chunk_position();
+		let new_chunk = position.into_chunk_pos();
+		player.position = position;
+		if old_chunk == new_chunk {
+			return Some(false);
+		}
 
-		let render_zone_corner = player.chunk_position() - player.render_distance();
-		let render_zone_length = 2 * player.render_distance();
+		let render_distance = player.render_distance();
+		self.update_viewers(&players);
+		let _load_area_lock = self.load_area_lock.lock();
+		drop(players);
+
+		self.move_load_area(
+			(old_chunk - render_distance, old_chunk + render_distance),
+			(new_chunk - render_distance, new_chunk + render_distance),
+		);
+		self.tasks.reprioritize();
+		Some(true)
+	}
 
-		if chunk_position.x != player.chunk_position().x {
-			let xaxis = ChunkPos::new(1, 0, 0);
+	// the direction the player is looking in is used to generate and mesh chunks in front of them first
+	pub fn set_player_direction(&self, player_id: PlayerId, direction: Vector3<f64>) -> Option<()> {
+		let mut players = self.players.write();
+		let player = players.get_mut(&player_id)?;
 
-			let pos_min_chunk = render_zone_corner + render_zone_length.xonly();
-			let pos_max_chunk = render_zone_corner + render_zone_length + xaxis;
+		let direction = direction.try_normalize(0.0).unwrap_or_else(Vector3::zeros);
+		// reprioritizing is expensive, so small changes in direction are ignored
+		if player.direction.dot(&direction) < REPRIORITIZE_DIRECTION_COS {
+			player.direction = direction;
+			self.update_viewers(&players);
+			self.tasks.reprioritize();
+		}
+		Some(())
+	}
 
-			let neg_min_chunk = render_zone_corner - xaxis;
-			let neg_max_chunk = render_zone_corner + render_zone_length.yzonly();
+	// moves the player to any position in the world, no matter how far away it is
+	// returns false if the player doesn't exist
+	pub fn teleport_player(&self, player_id: PlayerId, position: Position) -> bool {
+		self.set_player_position(player_id, position).is_some()
+	}
+
+	// unloads the chunks which are only in the old area and loads the chunks which are only in the new area
+	// chunks in both areas are left alone
+	fn move_load_area(&self, old_area: (ChunkPos, ChunkPos), new_area: (ChunkPos, ChunkPos)) {
+		for (min_chunk, max_chunk) in box_difference(old_area, new_area) {
+			self.unload_chunks(min_chunk, max_chunk);
+		}
+
+		for (min_chunk, max_chunk) in box_difference(new_area, old_area) {
+			self.load_chunks(min_chunk, max_chunk);
+		}
+	}
 
-			if chunk_position.x == player.chunk_position().x + 1 {
-				let neg_min_chunk = neg_min_chunk + xaxis;
-				let neg_max_chunk = neg_max_chunk + xaxis;
+	pub fn get_player_position(&self, player_id: PlayerId) -> Option<Position> {
+		Some(self.players.read().get(&player_id)?.position)
+	}
 
-				self.unload_chunks(neg_min_chunk, neg_max_chunk, None);
-				//self.chunk_mesh_update_face(BlockFace::XPos, neg_min_chunk - BlockPos::new(1, 0, 0), neg_max_chunk - BlockPos::new(1, 0, 0));
+	pub fn player_positions(&self) -> Vec<(PlayerId, Position)> {
+		self.players.read().values()
+			.map(|player| (player.id(), player.position))
+			.collect()
+	}
 
-				self.load_chunks(pos_min_chunk, pos_max_chunk, None);
-			} else if chunk_position.x == player.chunk_position().x - 1 {
-				let pos_min_chunk = pos_min_chunk - xaxis;
-				let pos_max_chunk = pos_max_chunk - xaxis;
+	// returns the min and max chunk that are kept loaded around the player, not including max
+	pub fn player_load_area(&self, player_id: PlayerId) -> Option<(ChunkPos, ChunkPos)> {
+		Some(self.players.read().get(&player_id)?.load_area())
+	}
 
-				self.unload_chunks(pos_min_chunk, pos_max_chunk, None);
-				//self.chunk_mesh_update_face(BlockFace::XNeg, neg_min_chunk + BlockPos::new(1, 0, 0), neg_max_chunk + BlockPos::new(1, 0, 0));
+	// gets the position and direction of every player so tasks close to players can be run first
+	pub fn viewers(&self) -> Arc<Vec<Viewer>> {
+		self.viewers.read().clone()
+	}
 
-				self.load_chunks(neg_min_chunk, neg_max_chunk, None);
-			} else {
-				todo!("moved to far for current player moving code");
+	// must be called with the players still locked after they change, so the viewers are updated in the same order the players were
+	fn update_viewers(&self, players: &FxHashMap<PlayerId, Player>) {
+		let viewers = players.values()
+			.map(|player| {
+				let (min_chunk, max_chunk) = player.load_area();
+				Viewer {
+					chunk: player.chunk_position(),
+					direction: player.direction,
+					min_chunk,
+					max_chunk,
+				}
+			})
+			.collect();
+		*self.viewers.write() = Arc::new(viewers);
+	}
+
+	// removes the player from the world and unloads the chunks around them
+	// the player's position is still saved so they are put back in the same place if they join again
+	pub fn disconnect(&self, player_id: PlayerId) {
+		let mut players = self.players.write();
+		let player = match players.remove(&player_id) {
+			Some(player) => player,
+			None => return,
+		};
+		self.update_viewers(&players);
+		let _load_area_lock = self.load_area_lock.lock();
+		drop(players);
+
+		if let Some(name) = player.name() {
+			self.metadata.write().update_player(SavedPlayer {
+				name: name.to_owned(),
+				position: player.position.into(),
+			});
+		}
+
+		let (min_chunk, max_chunk) = player.load_area();
+		self.unload_chunks(min_chunk, max_chunk);
+	}
+
+	// returns the meshes and connectivity of all the chunks whose mesh has changed since the last time this was called
+	pub fn changed_chunk_meshes(&self) -> Vec<(ChunkPos, ChunkFaces, ChunkConnectivity)> {
+		self.chunks.iter()
+			.filter_map(|item| {
+				let chunk = &item.value().chunk;
+				Some((*item.key(), chunk.take_changed_mesh()?, chunk.connectivity()))
+			})
+			.collect::<Vec<_>>()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	extern crate test;
+
+	use test::Bencher;
+	use super::*;
+	use crate::util::TestFolder;
+	use crate::game::block::{Stone, Air, Dirt};
+	use crate::game::light::MAX_LIGHT;
+	use crate::game::entity::{FallingBlock, SavedEntityKind};
+
+	fn chunks_in(boxes: &[(ChunkPos, ChunkPos)]) -> Vec<ChunkPos> {
+		let mut out = Vec::new();
+		for (min, max) in boxes {
+			for x in min.x..max.x {
+				for y in min.y..max.y {
+					for z in min.z..max.z {
+						out.push(ChunkPos::new(x, y, z));
+					}
+				}
+			}
+		}
+		out.sort_by_key(|chunk| (chunk.x, chunk.y, chunk.z));
+		out
+	}
+
+	// checks the difference against doing it chunk by chunk, which also makes sure no chunk is in 2 boxes
+	fn check_move(old_center: ChunkPos, new_center: ChunkPos) {
+		let render_distance = ChunkPos::new(3, 2, 3);
+		let old_area = (old_center - render_distance, old_center + render_distance);
+		let new_area = (new_center - render_distance, new_center + render_distance);
+
+		let old_chunks = chunks_in(&[old_area]);
+		let new_chunks = chunks_in(&[new_area]);
+
+		let unloaded = chunks_in(&box_difference(old_area, new_area));
+		let expected_unloaded = old_chunks.iter().filter(|chunk| !new_chunks.contains(chunk)).copied().collect::<Vec<_>>();
+		assert_eq!(unloaded, expected_unloaded);
+
+		let loaded = chunks_in(&box_difference(new_area, old_area));
+		let expected_loaded = new_chunks.iter().filter(|chunk| !old_chunks.contains(chunk)).copied().collect::<Vec<_>>();
+		assert_eq!(loaded, expected_loaded);
+	}
+
+	#[test]
+	fn move_one_chunk() {
+		check_move(ChunkPos::new(0, 0, 0), ChunkPos::new(1, 0, 0));
+		check_move(ChunkPos::new(0, 0, 0), ChunkPos::new(0, -1, 0));
+		check_move(ChunkPos::new(0, 0, 0), ChunkPos::new(0, 0, 0));
+	}
+
+	#[test]
+	fn move_diagonally() {
+		check_move(ChunkPos::new(0, 0, 0), ChunkPos::new(1, 0, 1));
+		check_move(ChunkPos::new(0, 0, 0), ChunkPos::new(-1, 1, 1));
+		check_move(ChunkPos::new(4, -2, 7), ChunkPos::new(3, -3, 6));
+	}
+
+	#[test]
+	fn move_multiple_chunks() {
+		check_move(ChunkPos::new(0, 0, 0), ChunkPos::new(4, 0, 0));
+		check_move(ChunkPos::new(0, 0, 0), ChunkPos::new(2, -3, 5));
+		// far enough that the areas don't overlap at all
+		check_move(ChunkPos::new(0, 0, 0), ChunkPos::new(100, 20, -100));
+	}
+
+	// small xorshift rng so each thread in the stress test gets a different but repeatable sequence
+	struct TestRng(u64);
+
+	impl TestRng {
+		fn next(&mut self, max: i64) -> i64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			(self.0 % max as u64) as i64
+		}
+
+		fn next_box(&mut self) -> (ChunkPos, ChunkPos) {
+			let min = ChunkPos::new(self.next(6) - 3, self.next(6) - 3, self.next(6) - 3);
+			let size = ChunkPos::new(self.next(3) + 1, self.next(3) + 1, self.next(3) + 1);
+			(min, min + size)
+		}
+	}
+
+	// loads and unloads overlapping boxes of chunks from several threads while the task runners are working on them,
+	// then checks that exactly the chunks which are still loaded by something are in the world
+	#[test]
+	fn interleaved_load_unload() {
+		let folder = TestFolder::new("load-unload-test");
+
+		let world = World::create(&folder, 0).unwrap();
+		crate::game::parallel::init(world.clone(), 4);
+
+		let threads = (0..4).map(|i| {
+			let world = world.clone();
+			thread::spawn(move || {
+				let mut rng = TestRng(0x9e3779b97f4a7c15 + i);
+				let mut loaded = Vec::new();
+
+				for _ in 0..200 {
+					if loaded.is_empty() || rng.next(2) == 0 {
+						let (min, max) = rng.next_box();
+						world.load_chunks(min, max);
+						loaded.push((min, max));
+					} else {
+						let (min, max) = loaded.swap_remove(rng.next(loaded.len() as i64) as usize);
+						world.unload_chunks(min, max);
+					}
+
+					if rng.next(4) == 0 {
+						thread::sleep(Duration::from_micros(rng.next(500) as u64));
+					}
+				}
+
+				loaded
+			})
+		}).collect::<Vec<_>>();
+
+		let loaded = threads.into_iter()
+			.flat_map(|thread| thread.join().unwrap())
+			.collect::<Vec<_>>();
+		world.run_until_idle();
+
+		let mut expected = chunks_in(&loaded);
+		let mut loaded_chunks = world.chunks.iter().map(|chunk| *chunk.key()).collect::<Vec<_>>();
+		loaded_chunks.sort_by_key(|chunk| (chunk.x, chunk.y, chunk.z));
+
+		{
+			let chunk_states = world.chunk_states.lock();
+			for chunk in expected.iter() {
+				let state = chunk_states.get(chunk).unwrap();
+				assert_eq!(state.status, ChunkStatus::Loaded);
+				assert_eq!(state.ref_count as usize, expected.iter().filter(|other| *other == chunk).count());
 			}
 		}
 
-		if chunk_position.y != player.chunk_position().y {
-			let yaxis = ChunkPos::new(0, 1, 0);
+		expected.dedup();
+		assert_eq!(loaded_chunks, expected);
+		assert_eq!(world.chunk_states.lock().len(), expected.len());
+
+		for (min, max) in loaded {
+			world.unload_chunks(min, max);
+		}
+		world.run_until_idle();
+
+		assert!(world.chunks.is_empty());
+		assert!(world.chunk_states.lock().is_empty());
 
-			let pos_min_chunk = render_zone_corner + render_zone_length.yonly();
-			let pos_max_chunk = render_zone_corner + render_zone_length + yaxis;
+		world.tasks.stop();
+	}
 
-			let neg_min_chunk = render_zone_corner - yaxis;
-			let neg_max_chunk = render_zone_corner + render_zone_length.xzonly();
+	// random blocks so there are lots of faces and ambient occlusion on the chunk borders
+	// the blocks only depend on the position, so every load order gives the same chunks
+	fn test_chunk_data(position: ChunkPos) -> Vec<u8> {
+		// the saved id followed by the default state
+		let [stone_low, stone_high] = Block::from(Stone::new()).id().to_le_bytes();
+		let [air_low, air_high] = Block::from(Air::new()).id().to_le_bytes();
+		let stone = [stone_low, stone_high, 0];
+		let air = [air_low, air_high, 0];
+
+		let mut rng = TestRng((position.x as u64).wrapping_mul(0x9e3779b97f4a7c15)
+			^ (position.y as u64).wrapping_mul(0xc2b2ae3d27d4eb4f)
+			^ (position.z as u64).wrapping_mul(0x165667b19e3779f9)
+			| 1);
+		(0..CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE)
+			.flat_map(|_| if rng.next(2) == 0 { stone } else { air })
+			.collect()
+	}
 
-			if chunk_position.y == player.chunk_position().y + 1 {
-				let neg_min_chunk = neg_min_chunk + yaxis;
-				let neg_max_chunk = neg_max_chunk + yaxis;
+	// checks that remeshing every chunk from scratch gives the same mesh the chunk already has, and returns the meshes
+	fn check_meshes(world: &World) -> FxHashMap<ChunkPos, ChunkFaces> {
+		let mut meshes = FxHashMap::default();
 
-				self.unload_chunks(neg_min_chunk, neg_max_chunk, None);
-				//self.chunk_mesh_update_face(BlockFace::YPos, neg_min_chunk - BlockPos::new(0, 1, 0), neg_max_chunk - BlockPos::new(0, 1, 0));
+		for chunk in world.chunks.iter() {
+			let mesh = chunk.chunk.get_chunk_mesh().unwrap();
+			chunk.chunk.chunk_mesh_update();
+			assert!(mesh == chunk.chunk.get_chunk_mesh().unwrap(), "mesh of chunk {:?} is out of date", chunk.key());
+			meshes.insert(*chunk.key(), mesh);
+		}
 
-				self.load_chunks(pos_min_chunk, pos_max_chunk, None);
-			} else if chunk_position.y == player.chunk_position().y - 1 {
-				let pos_min_chunk = pos_min_chunk - yaxis;
-				let pos_max_chunk = pos_max_chunk - yaxis;
+		meshes
+	}
 
-				self.unload_chunks(pos_min_chunk, pos_max_chunk, None);
-				//self.chunk_mesh_update_face(BlockFace::YNeg, neg_min_chunk + BlockPos::new(0, 1, 0), neg_max_chunk + BlockPos::new(0, 1, 0));
+	// chunks are added and removed in different orders while the task runners are meshing,
+	// and the meshes have to end up the same as if every chunk was meshed after all the others were loaded
+	#[test]
+	fn mesh_independent_of_load_order() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		crate::game::parallel::init(world.clone(), 4);
+
+		let mut chunks = chunks_in(&[(ChunkPos::new(0, 0, 0), ChunkPos::new(3, 3, 3))]);
+		let mut rng = TestRng(0x2545f4914f6cdd1d);
+		let mut expected_meshes = None;
+
+		for order in 0..3 {
+			match order {
+				0 => (),
+				1 => chunks.reverse(),
+				_ => for i in (1..chunks.len()).rev() {
+					chunks.swap(i, rng.next(i as i64 + 1) as usize);
+				},
+			}
 
-				self.load_chunks(neg_min_chunk, neg_max_chunk, None);
-			} else {
-				todo!("moved to far for current player moving code");
+			for chunk in chunks.iter() {
+				world.insert_remote_chunk(*chunk, &test_chunk_data(*chunk)).unwrap();
+			}
+			world.run_until_idle();
+
+			let meshes = check_meshes(&world);
+			assert_eq!(meshes.len(), chunks.len());
+			match &expected_meshes {
+				Some(expected_meshes) => assert!(meshes == *expected_meshes, "meshes depend on load order"),
+				None => expected_meshes = Some(meshes),
+			}
+
+			// removing chunks should fill in the faces of the chunks next to them
+			for chunk in chunks.iter().step_by(3) {
+				world.remove_remote_chunk(*chunk);
+			}
+			world.run_until_idle();
+			check_meshes(&world);
+
+			for chunk in chunks.iter() {
+				world.remove_remote_chunk(*chunk);
 			}
+			world.run_until_idle();
 		}
 
-		if chunk_position.z != player.chunk_position().z {
-			let zaxis = ChunkPos::new(0, 0, 1);
+		world.tasks.stop();
+	}
 
-			let pos_min_chunk = render_zone_corner + render_zone_length.zonly();
-			let pos_max_chunk = render_zone_corner + render_zone_length + zaxis;
+	// changes blocks on the edges and corners of chunks, where ambient occlusion depends on the chunks diagonal to them,
+	// and checks the mesh after each change against remeshing everything
+	// there are no task runners, so everything is meshed on this thread
+	#[test]
+	fn block_change_remesh_matches_full_remesh() {
+		let world = World::new_remote(BlockIdMap::from_registry());
 
-			let neg_min_chunk = render_zone_corner - zaxis;
-			let neg_max_chunk = render_zone_corner + render_zone_length.xyonly();
+		let chunks = chunks_in(&[(ChunkPos::new(0, 0, 0), ChunkPos::new(2, 2, 2))]);
+		for chunk in chunks.iter() {
+			world.insert_remote_chunk(*chunk, &test_chunk_data(*chunk)).unwrap();
+		}
+		for chunk in world.chunks.iter() {
+			chunk.chunk.chunk_mesh_update();
+		}
 
-			if chunk_position.z == player.chunk_position().z + 1 {
-				let neg_min_chunk = neg_min_chunk + zaxis;
-				let neg_max_chunk = neg_max_chunk + zaxis;
+		let mut changed_blocks = Vec::new();
+		// the corner where all 8 chunks meet
+		for x in 31..=32 {
+			for y in 31..=32 {
+				for z in 31..=32 {
+					changed_blocks.push(BlockPos::new(x, y, z));
+				}
+			}
+		}
+		// edges where 4 chunks meet, and faces where 2 chunks meet
+		for i in [0, 15, 31, 32, 33, 63] {
+			changed_blocks.push(BlockPos::new(i, 31, 32));
+			changed_blocks.push(BlockPos::new(32, i, 31));
+			changed_blocks.push(BlockPos::new(31, 32, i));
+			changed_blocks.push(BlockPos::new(32, i, 17));
+		}
+		let mut rng = TestRng(0x5851f42d4c957f2d);
+		for _ in 0..20 {
+			changed_blocks.push(BlockPos::new(rng.next(64), rng.next(64), rng.next(64)));
+		}
 
-				self.unload_chunks(neg_min_chunk, neg_max_chunk, None);
-				//self.chunk_mesh_update_face(BlockFace::ZPos, neg_min_chunk - BlockPos::new(0, 0, 1), neg_max_chunk - BlockPos::new(0, 0, 1));
+		for block in changed_blocks {
+			let new_block: Block = if world.with_block(block, |block| block.is_air()).unwrap() {
+				Stone::new().into()
+			} else {
+				Air::new().into()
+			};
 
-				self.load_chunks(pos_min_chunk, pos_max_chunk, None);
-			} else if chunk_position.z == player.chunk_position().z - 1 {
-				let pos_min_chunk = pos_min_chunk - zaxis;
-				let pos_max_chunk = pos_max_chunk - zaxis;
+			assert!(world.set_block(block, new_block));
+			check_meshes(&world);
+		}
+	}
 
-				self.unload_chunks(pos_min_chunk, pos_max_chunk, None);
-				//self.chunk_mesh_update_face(BlockFace::ZNeg, neg_min_chunk + BlockPos::new(0, 0, 1), neg_max_chunk + BlockPos::new(0, 0, 1));
+	// faces between translucent blocks of the same type are culled, and translucent faces are kept seperate
+	#[test]
+	fn translucent_faces() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let air = Block::from(Air::new());
+		let [air_low, air_high] = air.id().to_le_bytes();
+		let data = [air_low, air_high, 0].repeat(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE);
+		world.insert_remote_chunk(ChunkPos::new(0, 0, 0), &data).unwrap();
+
+		let glass = Block::from_name("glass").unwrap();
+		assert!(world.set_block(BlockPos::new(4, 4, 4), glass.clone()));
+		assert!(world.set_block(BlockPos::new(5, 4, 4), glass));
+		assert!(world.set_block(BlockPos::new(4, 5, 4), Stone::new().into()));
+
+		let chunk = world.chunks.get(&ChunkPos::new(0, 0, 0)).unwrap();
+		chunk.chunk.chunk_mesh_update();
+		let faces = chunk.chunk.get_chunk_mesh().unwrap();
+
+		// the stone is drawn on every side, even the one touching the glass
+		assert_eq!(faces.opaque.len(), 6);
+		// the glass is merged into 1 face on each side, except for the top which is half covered by the stone,
+		// and the bottom which is split where the stone shades it
+		assert_eq!(faces.translucent.len(), 7);
+	}
 
-				self.load_chunks(neg_min_chunk, neg_max_chunk, None);
+	// blocks without textures aren't drawn, but still hide nothing behind them since they are translucent
+	#[test]
+	fn untextured_blocks_not_drawn() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let [air_low, air_high] = Block::from(Air::new()).id().to_le_bytes();
+		let data = [air_low, air_high, 0].repeat(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE);
+		world.insert_remote_chunk(ChunkPos::new(0, 0, 0), &data).unwrap();
+
+		let barrier = Block::from_name("barrier").unwrap();
+		assert!(barrier.face_texture(BlockFace::YPos).is_none());
+		assert!(world.set_block(BlockPos::new(4, 4, 4), barrier));
+		assert!(world.set_block(BlockPos::new(5, 4, 4), Stone::new().into()));
+
+		let chunk = world.chunks.get(&ChunkPos::new(0, 0, 0)).unwrap();
+		chunk.chunk.chunk_mesh_update();
+		let faces = chunk.chunk.get_chunk_mesh().unwrap();
+
+		// only the stone is drawn, including the side touching the barrier
+		assert_eq!(faces.opaque.len(), 6);
+		assert!(faces.translucent.is_empty());
+	}
+
+	// sunlight is blocked by a chunk loaded above, comes back in through a hole, and light from a lamp fades with distance
+	#[test]
+	fn light_propagation() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let [air_low, air_high] = Block::from(Air::new()).id().to_le_bytes();
+		let [stone_low, stone_high] = Block::from(Stone::new()).id().to_le_bytes();
+		let volume = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+		let light_at = |block: BlockPos| {
+			let (chunk, local) = block.as_chunk_block_pos();
+			world.chunks.get(&chunk).unwrap().chunk.get_light(local)
+		};
+
+		world.insert_remote_chunk(ChunkPos::new(0, 0, 0), &[air_low, air_high, 0].repeat(volume)).unwrap();
+		assert_eq!(light_at(BlockPos::new(5, 0, 5)).sky(), MAX_LIGHT);
+
+		// a stone floor across the bottom of the chunk above
+		let floor = (0..volume)
+			.flat_map(|index| if (index / CHUNK_SIZE) % CHUNK_SIZE == 0 {
+				[stone_low, stone_high, 0]
 			} else {
-				todo!("moved to far for current player moving code");
+				[air_low, air_high, 0]
+			})
+			.collect::<Vec<_>>();
+		world.insert_remote_chunk(ChunkPos::new(0, 1, 0), &floor).unwrap();
+		assert_eq!(light_at(BlockPos::new(5, 0, 5)).sky(), 0);
+		assert_eq!(light_at(BlockPos::new(5, 33, 5)).sky(), MAX_LIGHT);
+
+		// sunlight goes straight down through a hole in the floor, and spreads out from there
+		assert!(world.set_block(BlockPos::new(5, 32, 5), Air::new().into()));
+		assert_eq!(light_at(BlockPos::new(5, 0, 5)).sky(), MAX_LIGHT);
+		assert_eq!(light_at(BlockPos::new(8, 0, 5)).sky(), MAX_LIGHT - 3);
+
+		assert!(world.set_block(BlockPos::new(20, 10, 20), Block::from_name("lamp").unwrap()));
+		assert_eq!(light_at(BlockPos::new(21, 10, 20)).block(), 14);
+		assert_eq!(light_at(BlockPos::new(20, 10, 26)).block(), 9);
+
+		assert!(world.set_block(BlockPos::new(5, 32, 5), Stone::new().into()));
+		assert_eq!(light_at(BlockPos::new(8, 0, 5)).sky(), 0);
+		assert!(world.set_block(BlockPos::new(20, 10, 20), Air::new().into()));
+		assert_eq!(light_at(BlockPos::new(21, 10, 20)).block(), 0);
+
+		check_meshes(&world);
+	}
+
+	// light updates on different threads touching the same chunks give the same light as running them 1 at a time
+	#[test]
+	fn concurrent_light_updates() {
+		let [air_low, air_high] = Block::from(Air::new()).id().to_le_bytes();
+		let air = [air_low, air_high, 0].repeat(CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE);
+		let chunks = chunks_in(&[(ChunkPos::new(0, 0, 0), ChunkPos::new(2, 2, 2))]);
+		let new_world = || {
+			let world = World::new_remote(BlockIdMap::from_registry());
+			for chunk in chunks.iter() {
+				world.insert_remote_chunk(*chunk, &air).unwrap();
 			}
+			world
+		};
+
+		// each thread changes blocks in different columns, so the blocks end up the same whatever order they are set in
+		let changes = (0..4).map(|i| {
+			let mut rng = TestRng(0x853c49e6748cea9b + i);
+			(0..40).map(|n| {
+				let x = rng.next(CHUNK_SIZE as i64 / 2) * 4 + i as i64;
+				let position = BlockPos::new(x, rng.next(2 * CHUNK_SIZE as i64), rng.next(2 * CHUNK_SIZE as i64));
+				let block = if n % 2 == 0 { Block::from_name("lamp").unwrap() } else { Stone::new().into() };
+				(position, block)
+			}).collect::<Vec<_>>()
+		}).collect::<Vec<_>>();
+
+		let world = new_world();
+		let threads = changes.iter().cloned().map(|changes| {
+			let world = world.clone();
+			thread::spawn(move || for (position, block) in changes {
+				world.set_block(position, block);
+			})
+		}).collect::<Vec<_>>();
+		for thread in threads {
+			thread.join().unwrap();
 		}
 
-		let out = chunk_position != player.chunk_position();
+		let expected_world = new_world();
+		for (position, block) in changes.into_iter().flatten() {
+			expected_world.set_block(position, block);
+		}
 
-		player.position = position;
-		Some(out)
+		for chunk in chunks.iter() {
+			let chunk_block = *chunk * CHUNK_SIZE as i64;
+			let light = world.chunks.get(chunk).unwrap().chunk.light_data().light;
+			let expected_light = expected_world.chunks.get(chunk).unwrap().chunk.light_data().light;
+			for (i, (level, expected_level)) in light.iter().zip(expected_light.iter()).enumerate() {
+				assert!(level == expected_level, "wrong light in chunk {:?} at index {}, {:?}", chunk, i, chunk_block);
+			}
+		}
 	}
 
-	pub fn world_mesh(&self) -> Vec<BlockFaceMesh> {
-		self.chunks.iter()
-			.filter_map(|item| item.value().chunk.get_chunk_mesh())
-			.flatten()
-			.collect::<Vec<_>>()
+	// a falling block updates while its chunk is loaded, leaves the world with the chunk when it is unloaded,
+	// and comes back when the chunk is loaded from disk
+	// chunks are added to the world directly, since there are no task runners to load them
+	#[test]
+	fn entities_saved_with_chunks() {
+		let folder = TestFolder::new("entity-test");
+
+		// high enough up that the chunk is empty
+		let chunk = ChunkPos::new(0, 40, 0);
+		let start = chunk * CHUNK_SIZE as i64 + BlockPos::new(4, 20, 4);
+		let stone = Block::from(Stone::new());
+
+		let world = World::create(&folder, 0).unwrap();
+		world.chunks.insert(chunk, world.load_or_generate_chunk(chunk));
+
+		let entity = FallingBlock::from_block(&world, start, stone.clone());
+		let id = entity.id();
+		world.spawn_entity(entity);
+		for _ in 0..5 {
+			world.tick();
+		}
+
+		let position = world.with_entity(id, |entity| entity.position()).unwrap();
+		assert!(position.y < start.y as f64);
+		let around = Aabb::new(position - Vector3::repeat(1.0), position + Vector3::repeat(1.0));
+		assert_eq!(world.entities_in_box(&around), vec![id]);
+		assert!(world.entities_in_box(&around.translated(Vector3::new(3.0, 0.0, 0.0))).is_empty());
+
+		let (_, loaded_chunk) = world.chunks.remove(&chunk).unwrap();
+		assert!(world.cache_chunk(loaded_chunk.chunk));
+		assert!(world.with_entity(id, |_| ()).is_none());
+		world.save().unwrap();
+		assert!(world.cached_chunks.read().is_empty());
+
+		let world = World::open(&folder).unwrap();
+		world.chunks.insert(chunk, world.load_or_generate_chunk(chunk));
+		let (loaded_position, velocity) = world.with_entity(id, |entity| (entity.position(), entity.velocity())).unwrap();
+		assert_eq!(loaded_position, position);
+		assert!(velocity.y < 0.0);
+		assert!(world.new_entity_id() > id);
+
+		// the chunk below isn't loaded, so the block lands on the bottom of the chunk
+		for _ in 0..100 {
+			world.tick();
+		}
+		assert!(world.with_entity(id, |_| ()).is_none());
+		let landed = BlockPos::new(start.x, chunk.y * CHUNK_SIZE as i64, start.z);
+		assert_eq!(world.get_block(landed), Some(stone));
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	extern crate test;
+	// entities spawned in chunks that were never loaded are saved with them,
+	// and chunks whose entities have stopped moving aren't written again
+	#[test]
+	fn entities_in_unloaded_chunks_saved() {
+		let folder = TestFolder::new("unloaded-entity-test");
+
+		let chunk = ChunkPos::new(0, 40, 0);
+		let start = chunk * CHUNK_SIZE as i64 + BlockPos::new(4, 0, 4);
+		let stone = Block::from(Stone::new());
+
+		let world = World::create(&folder, 0).unwrap();
+		let entity = FallingBlock::from_block(&world, start, stone.clone());
+		let id = entity.id();
+		world.spawn_entity(entity);
+		world.save().unwrap();
+		assert!(world.with_entity(id, |_| ()).is_none());
+
+		let world = World::open(&folder).unwrap();
+		world.chunks.insert(chunk, world.load_or_generate_chunk(chunk));
+		assert!(world.with_entity(id, |_| ()).is_some());
+
+		// the block is already on the bottom of the chunk, so it lands without moving
+		assert!(!world.chunks.get(&chunk).unwrap().chunk.is_dirty());
+		world.tick();
+		assert_eq!(world.get_block(start), Some(stone));
+		assert!(world.with_entity(id, |_| ()).is_none());
+		world.save().unwrap();
+		assert!(!world.chunks.get(&chunk).unwrap().chunk.is_dirty());
+	}
 
-	use test::Bencher;
-	use super::*;
+	// a chunk with an entity that can't be loaded still loads its blocks and the other entities
+	#[test]
+	fn invalid_entities_skipped() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let chunk = ChunkPos::new(0, 0, 0);
+		let stone = Block::from(Stone::new());
+
+		let blocks = world.world_generator.generate_chunk(world.clone(), chunk).chunk;
+		blocks.set_block(BlockPos::new(1, 2, 3).as_chunk_local(), stone.clone());
+		let valid = FallingBlock::from_block(&world, BlockPos::new(4, 4, 4), stone.clone()).save(&world);
+		let mut invalid = FallingBlock::from_block(&world, BlockPos::new(5, 5, 5), stone.clone()).save(&world);
+		invalid.kind = SavedEntityKind::FallingBlock { block_id: u16::MAX, block_state: 0 };
+
+		let data = ChunkData::to_bytes(&blocks, &[invalid, valid.clone()]).unwrap();
+		let chunk_data = ChunkData::from_bytes(world.clone(), chunk, &data).unwrap();
+		assert!(*chunk_data.chunk.get_block(BlockPos::new(1, 2, 3).as_chunk_local()) == stone);
+		assert_eq!(chunk_data.entities.len(), 1);
+		assert_eq!(chunk_data.entities[0].id().as_u64(), valid.id);
+
+		// entities which can't be decoded at all are also left out
+		let mut data = blocks.to_bytes();
+		data.extend([1, 2, 3]);
+		assert!(ChunkData::from_bytes(world, chunk, &data).unwrap().entities.is_empty());
+	}
+
+	// blocks which were removed from the registry are loaded as air instead of the chunk being regenerated
+	#[test]
+	fn removed_blocks_replaced_with_air() {
+		let folder = TestFolder::new("removed-block-test");
+
+		// high enough up that the chunk is generated empty
+		let chunk = ChunkPos::new(0, 40, 0);
+		let stone_position = chunk * CHUNK_SIZE as i64;
+		let dirt_position = stone_position + BlockPos::new(1, 0, 0);
+		let dirt = Block::from(Dirt::new());
+
+		let world = World::create(&folder, 0).unwrap();
+		world.chunks.insert(chunk, world.load_or_generate_chunk(chunk));
+		assert!(world.set_block(stone_position, Stone::new().into()));
+		assert!(world.set_block(dirt_position, dirt.clone()));
+		world.save().unwrap();
+
+		let mut metadata = WorldMetadata::load(&folder).unwrap();
+		for name in metadata.block_names.iter_mut().filter(|name| *name == "stone") {
+			*name = String::from("removed block");
+		}
+		metadata.save(&folder).unwrap();
+
+		let world = World::open(&folder).unwrap();
+		world.chunks.insert(chunk, world.load_or_generate_chunk(chunk));
+		assert!(world.get_block(stone_position).unwrap().is_air());
+		assert_eq!(world.get_block(dirt_position), Some(dirt));
+	}
+
+	// unloading a chunk more times than it was loaded only warns, whatever state the chunk is in
+	#[test]
+	fn unbalanced_unload() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let chunk = ChunkPos::new(0, 0, 0);
+		let area = (chunk, chunk + ChunkPos::repeat(1));
+
+		// the chunk stays queued, since there are no task runners
+		world.load_chunks(area.0, area.1);
+		let handle = match world.chunk_states.lock()[&chunk].status {
+			ChunkStatus::Queued(handle) => handle,
+			status => panic!("chunk is {:?} instead of queued", status),
+		};
+		world.unload_chunks(area.0, area.1);
+		world.unload_chunks(area.0, area.1);
+		assert!(world.chunk_states.lock().is_empty());
+
+		for status in [ChunkStatus::Generating(handle), ChunkStatus::Meshing(1), ChunkStatus::Unloading] {
+			world.chunk_states.lock().insert(chunk, ChunkState {
+				status,
+				ref_count: 0,
+				needs_mesh: false,
+			});
+			world.unload_chunks(area.0, area.1);
+			let chunk_states = world.chunk_states.lock();
+			assert_eq!(chunk_states[&chunk].ref_count, 0);
+			assert_eq!(chunk_states[&chunk].status, status);
+		}
+	}
+
+	// unloading a chunk before it is generated cancels its generate task, so the chunk is never generated
+	#[test]
+	fn unload_cancels_generate() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let chunk = ChunkPos::new(0, 0, 0);
+		let area = (chunk, chunk + ChunkPos::repeat(1));
+
+		world.load_chunks(area.0, area.1);
+		let handle = match world.chunk_states.lock()[&chunk].status {
+			ChunkStatus::Queued(handle) => handle,
+			status => panic!("chunk is {:?} instead of queued", status),
+		};
+		world.unload_chunks(area.0, area.1);
+		assert!(world.tasks.is_finished(handle));
+
+		// the mesh task that was waiting on it is skipped
+		crate::game::parallel::init(world.clone(), 1);
+		world.run_until_idle();
+		assert!(world.chunks.is_empty());
+		assert!(world.chunk_states.lock().is_empty());
+		world.tasks.stop();
+	}
+
+	// a chunk which is loaded again while its unload task is running keeps the blocks changed in it
+	// the entity update lock is held, so the unload task waits in the middle of unloading the chunk while it is loaded again
+	#[test]
+	fn reloaded_while_unloading() {
+		let folder = TestFolder::new("reload-test");
+
+		let world = World::create(&folder, 0).unwrap();
+		let chunk = ChunkPos::new(0, 40, 0);
+		let area = (chunk, chunk + ChunkPos::repeat(1));
+		let block = chunk * CHUNK_SIZE as i64 + BlockPos::new(3, 4, 5);
+		let stone = Block::from(Stone::new());
+
+		// there are no task runners, so the generate task is run here
+		let load = |world: &World| {
+			world.load_chunks(area.0, area.1);
+			let status = world.chunk_states.lock()[&chunk].status;
+			if let ChunkStatus::Queued(handle) = status {
+				world.load_chunk(chunk, handle);
+			}
+		};
+
+		load(&world);
+		assert!(world.set_block(block, stone.clone()));
+		world.unload_chunks(area.0, area.1);
+
+		let update_lock = world.entity_update_lock.lock();
+		let unload_task = {
+			let world = world.clone();
+			thread::spawn(move || world.unload_chunk(chunk))
+		};
+		thread::sleep(Duration::from_millis(50));
+		load(&world);
+		drop(update_lock);
+		unload_task.join().unwrap();
+		assert_eq!(world.get_block(block), Some(stone.clone()));
+
+		// the same after the unload task has finished, when the chunk comes back from the cache
+		world.unload_chunks(area.0, area.1);
+		assert!(world.unload_chunk(chunk));
+		load(&world);
+		assert_eq!(world.get_block(block), Some(stone));
+	}
+
+	// players are put back where the player with the same name left, even after the world is reopened
+	#[test]
+	fn players_saved_by_name() {
+		let folder = TestFolder::new("player-test");
+
+		let world = World::create(&folder, 0).unwrap();
+		let spawn = world.spawn_position();
+		let moved = spawn + Vector3::new(100.0, 5.0, -40.0);
+
+		// connecting doesn't depend on what order players join in
+		let spawn_player = world.keep_spawn_loaded();
+		let alice = world.connect("alice").unwrap();
+		assert!(world.connect("alice").is_err());
+		assert!(world.teleport_player(alice, moved));
+		world.disconnect(alice);
+
+		let bob = world.connect("bob").unwrap();
+		assert_eq!(world.get_player_position(bob), Some(spawn));
+		let alice = world.connect("alice").unwrap();
+		assert_eq!(world.get_player_position(alice), Some(moved));
+		world.save().unwrap();
+
+		// the player keeping spawn loaded isn't saved
+		assert_eq!(world.metadata.read().players.len(), 2);
+		for player in [spawn_player, alice, bob] {
+			world.disconnect(player);
+		}
+
+		let world = World::open(&folder).unwrap();
+		let alice = world.connect("alice").unwrap();
+		assert_eq!(world.get_player_position(alice), Some(moved));
+	}
+
+	// moving a player loads the chunks that came into range and unloads the ones that went out of range,
+	// so exactly the chunks around the player are loaded, each by 1 reference
+	#[test]
+	fn teleport_loads_and_unloads_chunks() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		let check_loaded = |area: (ChunkPos, ChunkPos)| {
+			let chunk_states = world.chunk_states.lock();
+			let mut loaded = chunk_states.keys().copied().collect::<Vec<_>>();
+			loaded.sort_by_key(|chunk| (chunk.x, chunk.y, chunk.z));
+			assert_eq!(loaded, chunks_in(&[area]));
+			assert!(chunk_states.values().all(|state| state.ref_count == 1));
+		};
+
+		let player = world.connect("mover").unwrap();
+		check_loaded(world.player_load_area(player).unwrap());
+
+		// moving inside the same chunk doesn't change anything
+		let position = world.get_player_position(player).unwrap();
+		assert_eq!(world.set_player_position(player, position + Vector3::new(0.1, 0.0, 0.0)), Some(false));
+
+		for offset in [Vector3::new(CHUNK_SIZE as f64, 0.0, 0.0), Vector3::new(-500.0, 200.0, 1000.0), Vector3::new(3.0 * CHUNK_SIZE as f64, 0.0, -(CHUNK_SIZE as f64))] {
+			let position = world.get_player_position(player).unwrap() + offset;
+			assert!(world.teleport_player(player, position));
+			check_loaded(world.player_load_area(player).unwrap());
+		}
+
+		world.disconnect(player);
+		assert!(world.chunk_states.lock().is_empty());
+		assert!(!world.teleport_player(player, Position::new(0.0, 0.0, 0.0)));
+	}
+
+	// the cached viewers the task runners use change with the players
+	#[test]
+	fn viewers_follow_players() {
+		let world = World::new_remote(BlockIdMap::from_registry());
+		assert!(world.viewers().is_empty());
+
+		let player = world.connect("viewer").unwrap();
+		let position = Position::new(100.0, 40.0, -70.0);
+		assert!(world.teleport_player(player, position));
+		world.set_player_direction(player, Vector3::new(0.0, 0.0, 2.0));
+
+		let viewers = world.viewers();
+		assert_eq!(viewers.len(), 1);
+		assert_eq!(viewers[0].chunk, position.into_chunk_pos());
+		assert_eq!(viewers[0].direction, Vector3::new(0.0, 0.0, 1.0));
+		assert_eq!((viewers[0].min_chunk, viewers[0].max_chunk), world.player_load_area(player).unwrap());
+
+		world.disconnect(player);
+		assert!(world.viewers().is_empty());
+	}
+
+	// worlds saved with a different format can't be read, so they aren't opened
+	#[test]
+	fn format_version_checked() {
+		let folder = TestFolder::new("format-test");
+
+		World::create(&folder, 0).unwrap();
+		let mut metadata = WorldMetadata::load(&folder).unwrap();
+		metadata.format_version = STORAGE_FORMAT_VERSION + 1;
+		metadata.save(&folder).unwrap();
+		assert!(World::open(&folder).is_err());
+
+		metadata.format_version = STORAGE_FORMAT_VERSION;
+		metadata.save(&folder).unwrap();
+		assert!(World::open(&folder).is_ok());
+	}
 
 	#[bench]
 	fn mesh_generation_benchmark(b: &mut Bencher) {
+		// removed once the benchmark is finished, the world is only created by the first iteration
+		let folder = TestFolder::new("bench-world");
+
 		b.iter(|| {
-			let world = World::new_test().unwrap();
-			world.connect();
+			let world = if World::exists(&folder) {
+				World::open(&folder)
+			} else {
+				World::create(&folder, 0)
+			}.unwrap();
+			world.connect("benchmark").unwrap();
 		})
 	}
 }
@@ -1,5 +1,7 @@
-use crate::prelude::*;
-use crate::game::block::{Block, BlockTrait, Air, Grass, Dirt, RockyDirt, Stone};
+// only BiomeNoiseData is used by the generator so far, the rest are the biomes planned for later
+#![allow(dead_code)]
+
+use crate::game::block::{Block, Air, Grass, Dirt, RockyDirt, Stone};
 
 #[derive(Debug, Clone, Copy)]
 pub struct BiomeNoiseData {
@@ -1,22 +1,25 @@
 use std::sync::Arc;
 
 use noise::{Seedable, NoiseFn, OpenSimplex};
-use parking_lot::RwLock;
 use rustc_hash::FxHashMap;
 use nalgebra::Vector2;
 use statrs::function::erf::erf;
 
 use crate::prelude::*;
-use biome::{SurfaceBiome, BiomeNoiseData};
+use biome::BiomeNoiseData;
 use surface_biome::SurfaceBiomeMap;
 use super::chunk::{Chunk, LoadedChunk};
 use super::world::World;
-use super::block::*;
 
 mod biome;
 mod surface_biome;
 
+// version of the world generator, this should be incramented whenever the generated terrain changes
+pub const GENERATOR_VERSION: u32 = 1;
+
 type Cache2D = FxHashMap<Vector2<i64>, f64>;
+// 3d noise isn't used by the generator yet
+#[allow(dead_code)]
 type Cache3D = FxHashMap<BlockPos, f64>;
 
 #[derive(Debug, Default)]
@@ -56,12 +59,14 @@ impl CachedNoise2D {
 	}
 }
 
+#[allow(dead_code)]
 struct CachedNoise3D {
 	noise: OpenSimplex,
 	scale: f64,
 	amplitude_fn: fn(f64) -> f64,
 }
 
+#[allow(dead_code)]
 impl CachedNoise3D {
 	fn new(seed: u32, scale: f64) -> Self {
 		Self {
@@ -123,14 +128,6 @@ impl WorldGenerator {
 	}
 
 	fn get_biome_noise(&self, block: BlockPos, cache: &mut NoiseCache) -> BiomeNoiseData {
-		// TODO: this doesn't make it completely uniform, could be better
-		let make_uniform = |value: f64| {
-			// the varience of opensimplex is about this
-			let varience: f64 = 0.0463;
-			let uniform = erf(value / (2.0 * varience).sqrt());
-			(25.0 + 25.0 * uniform).clamp(0.0, 49.0) as u8
-			/*(25.0 + 55.0 * value).clamp(0.0, 49.0) as u8*/
-		};
 		let heat = self.biome_heat_noise.get_block_pos(block, &mut cache.biome_heat_noise) as u8;
 		let humidity = self.biome_humidity_noise.get_block_pos(block, &mut cache.biome_humidity_noise) as u8;
 		BiomeNoiseData {
@@ -142,7 +139,7 @@ impl WorldGenerator {
 	pub fn generate_chunk(&self, world: Arc<World>, position: ChunkPos) -> LoadedChunk {
 		let mut cache = NoiseCache::default();
 		LoadedChunk::new(Chunk::new(world, position, |block| {
-			let biome_height = self.get_biome_height_noise(block, &mut cache);
+			let _biome_height = self.get_biome_height_noise(block, &mut cache);
 			let biome_noise = self.get_biome_noise(block, &mut cache);
 
 			/*let mut temp = [[0; 50]; 50];
@@ -2,7 +2,6 @@ use std::lazy::SyncLazy;
 
 use array_init::array_init;
 
-use crate::prelude::*;
 use crate::game::block::*;
 
 use super::biome::BiomeNoiseData;
@@ -147,6 +146,8 @@ impl SurfaceBiomeMap {
 		self.map[noise.heat as usize][noise.humidity as usize]
 	}
 
+	// prints the first letter of the biome at every point of the map, for debugging
+	#[allow(dead_code)]
 	pub fn print_diagram(&self) {
 		let mut out_str = String::from("");
 		for heat in 0..50 {
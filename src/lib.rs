@@ -0,0 +1,17 @@
+#![feature(once_cell)]
+#![feature(const_fn_floating_point_arithmetic)]
+#![feature(let_chains)]
+#![cfg_attr(test, feature(test))]
+#![feature(drain_filter)]
+
+#![warn(clippy::disallowed_types)]
+
+#[macro_use]
+extern crate log;
+
+pub mod assets;
+#[cfg(feature = "client")]
+pub mod render;
+pub mod game;
+pub mod util;
+pub mod prelude;
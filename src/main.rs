@@ -1,13 +1,4 @@
-#![feature(once_cell)]
-#![feature(const_fn_floating_point_arithmetic)]
-#![feature(let_chains)]
-#![feature(test)]
-#![feature(drain_filter)]
-
-#![warn(clippy::disallowed_types)]
-
-#[macro_use]
-extern crate log;
+use std::env;
 
 use winit::{
 	event_loop::EventLoop,
@@ -15,12 +6,13 @@ use winit::{
 	dpi::PhysicalSize,
 };
 
-mod assets;
-mod render;
-mod game;
-mod util;
-mod prelude;
+use mineclone::game;
+
+// name used when the player doesn't give one
+const DEFAULT_PLAYER_NAME: &str = "player";
 
+// usage: mineclone [server address] [player name]
+// if no server address is given a local world is played
 fn main() {
 	pretty_env_logger::init();
 
@@ -31,7 +23,9 @@ fn main() {
 		.build(&event_loop)
 		.unwrap();
 
-	let mut game = game::Game::new(60, &window);
+	let server_address = env::args().nth(1);
+	let player_name = env::args().nth(2).unwrap_or_else(|| DEFAULT_PLAYER_NAME.to_owned());
+	let mut game = game::Game::new(60, &window, server_address.as_deref(), &player_name);
 
 	event_loop.run(move |event, _, control_flow| {
 		*control_flow = game.event_update(event);
@@ -239,16 +239,16 @@ impl BlockPosExt for BlockPos {
 }
 
 pub trait PositionExt {
-	fn into_block_pos(&self) -> BlockPos;
-	fn into_chunk_pos(&self) -> ChunkPos;
+	fn into_block_pos(self) -> BlockPos;
+	fn into_chunk_pos(self) -> ChunkPos;
 }
 
 impl PositionExt for Position {
-	fn into_block_pos(&self) -> BlockPos {
+	fn into_block_pos(self) -> BlockPos {
 		BlockPos::new(self[0].floor() as i64, self[1].floor() as i64, self[2].floor() as i64)
 	}
 
-	fn into_chunk_pos(&self) -> ChunkPos{
+	fn into_chunk_pos(self) -> ChunkPos {
 		self.into_block_pos().as_chunk_pos()
 	}
 }
@@ -0,0 +1,198 @@
+use std::collections::VecDeque;
+
+use nalgebra::{Matrix4, Vector3, Vector4};
+use rustc_hash::FxHashSet;
+
+use crate::prelude::*;
+use crate::game::{BlockFace, ChunkConnectivity, CHUNK_SIZE};
+
+// the space the camera can see, as the 6 planes around it
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+	// a point is on the inside of a plane if plane.dot(point) >= 0, where the point has a w of 1
+	planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+	// camera_matrix converts world coordinates to wgpu clip space, where points with -w <= x, y <= w and 0 <= z <= w are visible
+	pub fn from_matrix(camera_matrix: &Matrix4<f32>) -> Self {
+		let row = |i| camera_matrix.row(i).transpose();
+		let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+		Self {
+			planes: [w + x, w - x, w + y, w - y, z, w - z],
+		}
+	}
+
+	// returns false if the box between min and max is completely outside the frustum
+	// some boxes near the corners of the frustum are not completely inside any plane, and still return true
+	pub fn contains_box(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+		self.planes.iter().all(|plane| {
+			// the corner of the box which is the furthest inside the plane
+			let corner = Vector4::new(
+				if plane.x >= 0.0 { max.x } else { min.x },
+				if plane.y >= 0.0 { max.y } else { min.y },
+				if plane.z >= 0.0 { max.z } else { min.z },
+				1.0,
+			);
+			plane.dot(&corner) >= 0.0
+		})
+	}
+
+	pub fn contains_chunk(&self, chunk: ChunkPos) -> bool {
+		let min = (chunk * CHUNK_SIZE as i64).cast::<f32>();
+		self.contains_box(min, min + Vector3::repeat(CHUNK_SIZE as f32))
+	}
+}
+
+fn face_bit(face: BlockFace) -> u8 {
+	1 << usize::from(face)
+}
+
+// returns the chunks that can be seen from the chunk the camera is in
+//
+// chunks are searched outwards from the camera, only in directions that don't go back towards the camera,
+// and a chunk is only left through a face that can be seen from the face it was entered through
+// connectivity returns None for chunks that haven't been meshed yet, which are not searched past
+pub fn visible_chunks<F>(camera_chunk: ChunkPos, frustum: &Frustum, mut connectivity: F) -> FxHashSet<ChunkPos>
+	where F: FnMut(ChunkPos) -> Option<ChunkConnectivity> {
+	let mut visible = FxHashSet::default();
+	visible.insert(camera_chunk);
+
+	// each chunk is stored with the face it was entered through and the directions taken to get to it
+	let mut queue = VecDeque::new();
+	queue.push_back((camera_chunk, None, 0u8, ChunkConnectivity::ALL));
+
+	while let Some((chunk, entered_through, directions, chunk_connectivity)) = queue.pop_front() {
+		for face in BlockFace::iter() {
+			if directions & face_bit(face.opposite()) != 0 {
+				continue;
+			}
+
+			let can_see_face = entered_through.map_or(true, |entered_through| chunk_connectivity.can_see(entered_through, face));
+			if !can_see_face {
+				continue;
+			}
+
+			let next_chunk = chunk + face.block_pos_offset();
+			if visible.contains(&next_chunk) || !frustum.contains_chunk(next_chunk) {
+				continue;
+			}
+
+			if let Some(next_connectivity) = connectivity(next_chunk) {
+				visible.insert(next_chunk);
+				queue.push_back((next_chunk, Some(face.opposite()), directions | face_bit(face), next_connectivity));
+			}
+		}
+	}
+
+	visible
+}
+
+// sorts chunks from the furthest from the camera to the closest, which is the order translucent meshes are drawn in
+pub fn sort_back_to_front(chunks: &mut [ChunkPos], camera_position: Position) {
+	let distance = |chunk: &ChunkPos| {
+		let center = (chunk * CHUNK_SIZE as i64).cast::<f64>() + Position::repeat(CHUNK_SIZE as f64 / 2.0);
+		(center - camera_position).norm_squared()
+	};
+
+	chunks.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+}
+
+#[cfg(test)]
+mod tests {
+	use nalgebra::Point3;
+
+	use super::*;
+	use crate::render::camera::Camera;
+
+	// camera in the middle of chunk 0, 0, 0 looking along the z axis
+	fn test_frustum() -> Frustum {
+		let center = CHUNK_SIZE as f32 / 2.0;
+		let camera = Camera::new(Point3::new(center, center, center), Point3::new(center, center, center + 1.0), 1.0);
+		Frustum::from_matrix(&camera.get_camera_matrix())
+	}
+
+	#[test]
+	fn frustum_contains_boxes_in_front() {
+		let frustum = test_frustum();
+
+		assert!(frustum.contains_chunk(ChunkPos::new(0, 0, 0)));
+		assert!(frustum.contains_chunk(ChunkPos::new(0, 0, 1)));
+		assert!(frustum.contains_chunk(ChunkPos::new(0, 0, 2)));
+		assert!(frustum.contains_box(Vector3::new(15.0, 15.0, 40.0), Vector3::new(17.0, 17.0, 41.0)));
+
+		assert!(!frustum.contains_chunk(ChunkPos::new(0, 0, -2)));
+		assert!(!frustum.contains_box(Vector3::new(15.0, 15.0, -40.0), Vector3::new(17.0, 17.0, -20.0)));
+		// far off to the side and above
+		assert!(!frustum.contains_box(Vector3::new(500.0, 15.0, 40.0), Vector3::new(501.0, 16.0, 41.0)));
+		assert!(!frustum.contains_box(Vector3::new(15.0, 500.0, 40.0), Vector3::new(16.0, 501.0, 41.0)));
+	}
+
+	#[test]
+	fn open_chunks_are_visible() {
+		let visible = visible_chunks(ChunkPos::new(0, 0, 0), &test_frustum(), |chunk| {
+			(chunk.x.abs() <= 2 && chunk.y.abs() <= 2 && chunk.z.abs() <= 4).then(|| ChunkConnectivity::ALL)
+		});
+
+		assert!(visible.contains(&ChunkPos::new(0, 0, 0)));
+		assert!(visible.contains(&ChunkPos::new(0, 0, 1)));
+		assert!(visible.contains(&ChunkPos::new(0, 0, 4)));
+		assert!(visible.contains(&ChunkPos::new(1, 0, 3)));
+		// behind the camera
+		assert!(!visible.contains(&ChunkPos::new(0, 0, -2)));
+		// not meshed
+		assert!(!visible.contains(&ChunkPos::new(0, 0, 5)));
+	}
+
+	#[test]
+	fn translucent_chunks_are_sorted_back_to_front() {
+		let mut chunks = vec![ChunkPos::new(0, 0, 1), ChunkPos::new(0, 0, -3), ChunkPos::new(0, 0, 0), ChunkPos::new(2, 0, 0)];
+		sort_back_to_front(&mut chunks, Position::new(16.0, 16.0, 20.0));
+
+		assert_eq!(chunks, [ChunkPos::new(0, 0, -3), ChunkPos::new(2, 0, 0), ChunkPos::new(0, 0, 1), ChunkPos::new(0, 0, 0)]);
+	}
+
+	#[test]
+	fn solid_chunks_hide_chunks_behind_them() {
+		// a solid wall of chunks at z = 2
+		let visible = visible_chunks(ChunkPos::new(0, 0, 0), &test_frustum(), |chunk| {
+			if chunk.x.abs() > 4 || chunk.y.abs() > 4 || chunk.z.abs() > 4 {
+				None
+			} else if chunk.z == 2 {
+				Some(ChunkConnectivity::NONE)
+			} else {
+				Some(ChunkConnectivity::ALL)
+			}
+		});
+
+		assert!(visible.contains(&ChunkPos::new(0, 0, 1)));
+		// the front of the wall can be seen, but not what is behind it
+		assert!(visible.contains(&ChunkPos::new(0, 0, 2)));
+		assert!(!visible.contains(&ChunkPos::new(0, 0, 3)));
+		assert!(!visible.contains(&ChunkPos::new(1, 1, 4)));
+	}
+
+	#[test]
+	fn tunnels_can_be_seen_through() {
+		// chunks at z = 2 are solid except for a tunnel through chunk 0, 0, 2 from the front to the back
+		let mut tunnel = ChunkConnectivity::NONE;
+		tunnel.connect(BlockFace::ZNeg, BlockFace::ZPos);
+
+		let visible = visible_chunks(ChunkPos::new(0, 0, 0), &test_frustum(), |chunk| {
+			if chunk.x.abs() > 4 || chunk.y.abs() > 4 || chunk.z.abs() > 4 {
+				None
+			} else if chunk == ChunkPos::new(0, 0, 2) {
+				Some(tunnel)
+			} else if chunk.z == 2 {
+				Some(ChunkConnectivity::NONE)
+			} else {
+				Some(ChunkConnectivity::ALL)
+			}
+		});
+
+		assert!(visible.contains(&ChunkPos::new(0, 0, 3)));
+		assert!(visible.contains(&ChunkPos::new(0, 0, 4)));
+		assert!(visible.contains(&ChunkPos::new(1, 0, 4)));
+	}
+}
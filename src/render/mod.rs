@@ -1,16 +1,20 @@
-use std::num::NonZeroU32;
 
-use nalgebra::{Point3, Vector3, Scale3, UnitQuaternion, Unit};
+use nalgebra::Point3;
+use anyhow::{Result, Context, bail};
 use winit::window::Window;
 use wgpu::util::DeviceExt;
 
-use texture::{Texture, DepthTexture};
+use texture::DepthTexture;
 use camera::Camera;
+use sky::SkyUniform;
 use model::*;
-use crate::game::{BlockVertex, num_textures};
+use crate::game::{BlockVertex, num_texture_layers};
+use crate::game::clock::{Sky, START_TIME};
 
 pub mod camera;
+pub mod culling;
 pub mod model;
+pub mod sky;
 pub mod texture;
 
 
@@ -21,12 +25,19 @@ pub struct Renderer {
 	queue: wgpu::Queue,
 	config: wgpu::SurfaceConfiguration,
 	render_pipeline: wgpu::RenderPipeline,
+	// draws translucent blocks after the opaque blocks, blending them with what is behind them
+	translucent_pipeline: wgpu::RenderPipeline,
 	texture_bind_layout: wgpu::BindGroupLayout,
+	chunk_bind_layout: wgpu::BindGroupLayout,
 	depth_texture: DepthTexture,
 	camera: Camera,
 	camera_modified: bool,
 	camera_buffer: wgpu::Buffer,
 	camera_bind_group: wgpu::BindGroup,
+	// the clear color is the color of the sky
+	sky: Sky,
+	sky_buffer: wgpu::Buffer,
+	sky_bind_group: wgpu::BindGroup,
 	pub size: winit::dpi::PhysicalSize<u32>,
 }
 
@@ -38,11 +49,13 @@ pub struct RenderContext<'a> {
 	device: &'a wgpu::Device,
 	queue: &'a wgpu::Queue,
 	texture_bind_layout: &'a wgpu::BindGroupLayout,
+	chunk_bind_layout: &'a wgpu::BindGroupLayout,
 }
 
 impl Renderer {
 	// Creating some of the wgpu types requires async code
-	pub async fn new(window: &Window) -> Self {
+	// fails if there is no gpu that can be used, or if the block textures don't fit in a texture array on the gpu
+	pub async fn new(window: &Window) -> Result<Self> {
 		let size = window.inner_size();
 
 		let instance = wgpu::Instance::new(wgpu::Backends::VULKAN);
@@ -54,22 +67,25 @@ impl Renderer {
 				compatible_surface: Some(&surface),
 				force_fallback_adapter: false,
 			},
-		).await.unwrap();
+		).await.context("could not find a gpu to render with")?;
 
-		let features = wgpu::Features::TEXTURE_BINDING_ARRAY
-			| wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING;
+		// every side of every block texture is a layer of the block texture array
+		let supported_layers = adapter.limits().max_texture_array_layers;
+		if num_texture_layers() > supported_layers {
+			bail!("the block textures need {} texture array layers, but the gpu only supports {}", num_texture_layers(), supported_layers);
+		}
 
 		let (device, queue) = adapter.request_device(
 			&wgpu::DeviceDescriptor {
-				features,
+				features: wgpu::Features::empty(),
 				limits: wgpu::Limits {
-					max_texture_array_layers: 256,
+					max_texture_array_layers: num_texture_layers().max(256).min(supported_layers),
 					..Default::default()
 				},
 				label: None,
 			},
 			None,
-		).await.unwrap();
+		).await.context("could not open the gpu")?;
 
 		let config = wgpu::SurfaceConfiguration {
 			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -89,10 +105,10 @@ impl Renderer {
 						visibility: wgpu::ShaderStages::FRAGMENT,
 						ty: wgpu::BindingType::Texture {
 							multisampled: false,
-							view_dimension: wgpu::TextureViewDimension::D2,
+							view_dimension: wgpu::TextureViewDimension::D2Array,
 							sample_type: wgpu::TextureSampleType::Float { filterable: true },
 						},
-						count: NonZeroU32::new(num_textures()),
+						count: None,
 					},
 					wgpu::BindGroupLayoutEntry {
 						binding: 1,
@@ -149,83 +165,106 @@ impl Renderer {
 			}
 		);
 
+		let sky = Sky::at(START_TIME);
+		let sky_buffer = device.create_buffer_init(
+			&wgpu::util::BufferInitDescriptor {
+				label: Some("sky buffer"),
+				contents: bytemuck::cast_slice(&[SkyUniform::from(&sky)]),
+				usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			}
+		);
+
+		let sky_bind_group_layout = device.create_bind_group_layout(
+			&wgpu::BindGroupLayoutDescriptor {
+				label: Some("sky bind group layout"),
+				entries: &[
+					wgpu::BindGroupLayoutEntry {
+						binding: 0,
+						visibility: wgpu::ShaderStages::VERTEX,
+						ty: wgpu::BindingType::Buffer {
+							ty: wgpu::BufferBindingType::Uniform,
+							has_dynamic_offset: false,
+							min_binding_size: None,
+						},
+						count: None,
+					}
+				],
+			}
+		);
+
+		let sky_bind_group = device.create_bind_group(
+			&wgpu::BindGroupDescriptor {
+				label: Some("sky bind group"),
+				layout: &sky_bind_group_layout,
+				entries: &[
+					wgpu::BindGroupEntry {
+						binding: 0,
+						resource: sky_buffer.as_entire_binding(),
+					},
+				],
+			}
+		);
+
+		let chunk_bind_group_layout = device.create_bind_group_layout(
+			&wgpu::BindGroupLayoutDescriptor {
+				label: Some("chunk bind group layout"),
+				entries: &[
+					wgpu::BindGroupLayoutEntry {
+						binding: 0,
+						visibility: wgpu::ShaderStages::VERTEX,
+						ty: wgpu::BindingType::Buffer {
+							ty: wgpu::BufferBindingType::Uniform,
+							has_dynamic_offset: false,
+							min_binding_size: None,
+						},
+						count: None,
+					}
+				],
+			}
+		);
+
 		let shader = device.create_shader_module(&wgpu::include_wgsl!("shader.wgsl"));
 		let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
 			label: Some("render pipeline layout"),
 			bind_group_layouts: &[
 				&texture_bind_group_layout,
 				&camera_bind_group_layout,
+				&chunk_bind_group_layout,
+				&sky_bind_group_layout,
 			],
 			push_constant_ranges: &[],
 		});
 
-		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-			label: Some("render pipeline"),
-			layout: Some(&render_pipeline_layout),
-			vertex: wgpu::VertexState {
-				module: &shader,
-				entry_point: "vs_main",
-				buffers: &[
-					BlockVertex::desc(),
-				],
-			},
-			fragment: Some(wgpu::FragmentState {
-				module: &shader,
-				entry_point: "fs_main",
-				targets: &[wgpu::ColorTargetState {
-					format: config.format,
-					blend: Some(wgpu::BlendState::REPLACE),
-					write_mask: wgpu::ColorWrites::ALL,
-				}],
-			}),
-			primitive: wgpu::PrimitiveState {
-				topology: wgpu::PrimitiveTopology::TriangleList,
-				strip_index_format: None,
-				front_face: wgpu::FrontFace::Ccw,
-				cull_mode: Some(wgpu::Face::Back),
-				// Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-				polygon_mode: wgpu::PolygonMode::Fill,
-				// Requires Features::DEPTH_CLIP_CONTROL
-				unclipped_depth: false,
-				// Requires Features::CONSERVATIVE_RASTERIZATION
-				conservative: false,
-			},
-			depth_stencil: Some(wgpu::DepthStencilState {
-				format: DepthTexture::DEPTH_FORMAT,
-				depth_write_enabled: true,
-				depth_compare: wgpu::CompareFunction::Less,
-				stencil: wgpu::StencilState::default(),
-				bias: wgpu::DepthBiasState::default(),
-			}),
-			multisample: wgpu::MultisampleState {
-				count: 1,
-				mask: !0,
-				alpha_to_coverage_enabled: false,
-			},
-			multiview: None,
-		});
+		let render_pipeline = create_block_pipeline(&device, &render_pipeline_layout, &shader, config.format, false);
+		let translucent_pipeline = create_block_pipeline(&device, &render_pipeline_layout, &shader, config.format, true);
 
-		Self {
+		Ok(Self {
 			surface,
 			device,
 			queue,
 			config,
 			render_pipeline,
+			translucent_pipeline,
 			texture_bind_layout: texture_bind_group_layout,
+			chunk_bind_layout: chunk_bind_group_layout,
 			depth_texture,
 			camera,
 			camera_modified: false,
 			camera_buffer,
 			camera_bind_group,
+			sky,
+			sky_buffer,
+			sky_bind_group,
 			size,
-		}
+		})
 	}
 
-	pub fn context(&self) -> RenderContext {
+	pub fn context(&self) -> RenderContext<'_> {
 		RenderContext {
 			device: &self.device,
 			queue: &self.queue,
 			texture_bind_layout: &self.texture_bind_layout,
+			chunk_bind_layout: &self.chunk_bind_layout,
 		}
 	}
 
@@ -239,12 +278,26 @@ impl Renderer {
 		}
 	}
 
+	pub fn camera(&self) -> &Camera {
+		&self.camera
+	}
+
 	pub fn get_camera_mut(&mut self) -> &mut Camera {
 		self.camera_modified = true;
 		&mut self.camera
 	}
 
-	pub fn render(&mut self, models: &[(&Mesh, &Material)]) {
+	// changes the sky color and how bright sky light is
+	pub fn set_sky(&mut self, sky: Sky) {
+		if sky != self.sky {
+			self.queue.write_buffer(&self.sky_buffer, 0, bytemuck::cast_slice(&[SkyUniform::from(&sky)]));
+			self.sky = sky;
+		}
+	}
+
+	// draws the mesh of every chunk with the material of the blocks, followed by the translucent meshes
+	// the translucent meshes must be sorted from back to front, so the ones behind are blended in first
+	pub fn render(&mut self, chunks: &[(&Mesh, &ChunkOffset)], translucent_chunks: &[(&Mesh, &ChunkOffset)], material: &Material) {
 		if self.camera_modified {
 			self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera.get_camera_uniform()]));
 			self.camera_modified = false;
@@ -275,9 +328,9 @@ impl Renderer {
 					resolve_target: None,
 					ops: wgpu::Operations {
 						load: wgpu::LoadOp::Clear(wgpu::Color {
-							r: 0.1,
-							g: 0.2,
-							b: 0.3,
+							r: self.sky.color[0] as f64,
+							g: self.sky.color[1] as f64,
+							b: self.sky.color[2] as f64,
 							a: 1.0,
 						}),
 						store: true,
@@ -294,9 +347,16 @@ impl Renderer {
 			});
 
 			render_pass.set_pipeline(&self.render_pipeline);
+			render_pass.set_bind_group(3, &self.sky_bind_group, &[]);
+
+			for (mesh, offset) in chunks.iter() {
+				render_pass.draw_chunk_mesh(mesh, material, offset, &self.camera_bind_group);
+			}
+
+			render_pass.set_pipeline(&self.translucent_pipeline);
 
-			for (mesh, material) in models.iter() {
-				render_pass.draw_mesh(mesh, material, &self.camera_bind_group);
+			for (mesh, offset) in translucent_chunks.iter() {
+				render_pass.draw_chunk_mesh(mesh, material, offset, &self.camera_bind_group);
 			}
 		}
 
@@ -304,3 +364,66 @@ impl Renderer {
 		output.present();
 	}
 }
+
+// the opaque pipeline replaces what is behind it and writes to the depth buffer,
+// while the translucent pipeline blends with what is behind it and doesn't, so translucent faces behind it are still drawn
+fn create_block_pipeline(
+	device: &wgpu::Device,
+	layout: &wgpu::PipelineLayout,
+	shader: &wgpu::ShaderModule,
+	format: wgpu::TextureFormat,
+	translucent: bool,
+) -> wgpu::RenderPipeline {
+	let (label, entry_point, blend) = if translucent {
+		("translucent render pipeline", "fs_translucent", wgpu::BlendState::ALPHA_BLENDING)
+	} else {
+		("render pipeline", "fs_main", wgpu::BlendState::REPLACE)
+	};
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some(label),
+		layout: Some(layout),
+		vertex: wgpu::VertexState {
+			module: shader,
+			entry_point: "vs_main",
+			buffers: &[
+				BlockVertex::desc(),
+			],
+		},
+		fragment: Some(wgpu::FragmentState {
+			module: shader,
+			entry_point,
+			targets: &[wgpu::ColorTargetState {
+				format,
+				blend: Some(blend),
+				write_mask: wgpu::ColorWrites::ALL,
+			}],
+		}),
+		primitive: wgpu::PrimitiveState {
+			topology: wgpu::PrimitiveTopology::TriangleList,
+			strip_index_format: None,
+			front_face: wgpu::FrontFace::Ccw,
+			// translucent faces can be seen from behind through the block, like the surface of water from under it
+			cull_mode: if translucent { None } else { Some(wgpu::Face::Back) },
+			// Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+			polygon_mode: wgpu::PolygonMode::Fill,
+			// Requires Features::DEPTH_CLIP_CONTROL
+			unclipped_depth: false,
+			// Requires Features::CONSERVATIVE_RASTERIZATION
+			conservative: false,
+		},
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: DepthTexture::DEPTH_FORMAT,
+			depth_write_enabled: !translucent,
+			depth_compare: wgpu::CompareFunction::Less,
+			stencil: wgpu::StencilState::default(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState {
+			count: 1,
+			mask: !0,
+			alpha_to_coverage_enabled: false,
+		},
+		multiview: None,
+	})
+}
@@ -5,8 +5,10 @@ use image::DynamicImage;
 use wgpu::util::DeviceExt;
 use nalgebra::{Vector3, Scale3, Matrix4, UnitQuaternion};
 
-use super::{RenderContext, texture::Texture};
+use super::{RenderContext, texture::{Texture, TextureFiltering}};
 use crate::assets::loader;
+use crate::prelude::*;
+use crate::game::CHUNK_SIZE;
 
 pub trait Vertex: bytemuck::Pod + bytemuck::Zeroable {
 	fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
@@ -40,6 +42,9 @@ pub struct Mesh {
 	name: String,
 	vertex_buffer: wgpu::Buffer,
 	index_buffer: wgpu::Buffer,
+	// sizes of the buffers in bytes, which is how much write can put in them
+	vertex_buffer_size: usize,
+	index_buffer_size: usize,
 	num_elements: u32,
 	material_index: usize,
 }
@@ -56,7 +61,7 @@ impl Mesh {
 			&wgpu::util::BufferInitDescriptor {
 				label: Some(&format!("{} vertex buffer", name)),
 				contents: bytemuck::cast_slice(vertices),
-				usage: wgpu::BufferUsages::VERTEX,
+				usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
 			}
 		);
 
@@ -64,7 +69,7 @@ impl Mesh {
 			&wgpu::util::BufferInitDescriptor {
 				label: Some(&format!("{} index buffer", name)),
 				contents: bytemuck::cast_slice(indices),
-				usage: wgpu::BufferUsages::INDEX,
+				usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
 			}
 		);
 
@@ -72,17 +77,81 @@ impl Mesh {
 			name: name.to_owned(),
 			vertex_buffer,
 			index_buffer,
+			vertex_buffer_size: mem::size_of_val(vertices),
+			index_buffer_size: mem::size_of_val(indices),
 			num_elements: indices.len().try_into().unwrap(),
 			material_index,
 		}
 	}
+
+	// replaces the contents of the mesh, which reuses the buffers if the new vertices and indices fit in them
+	// ChunkMeshes keeps track of the sizes itself, so new buffers are only made here if a caller got them wrong
+	pub fn write<T: Vertex>(&mut self, vertices: &[T], indices: &[u32], context: RenderContext) {
+		let fits = mem::size_of_val(vertices) <= self.vertex_buffer_size && mem::size_of_val(indices) <= self.index_buffer_size;
+		debug_assert!(fits, "mesh {} is too big for its buffers", self.name);
+		if !fits {
+			*self = Self::new(&self.name, vertices, indices, self.material_index, context);
+			return;
+		}
+
+		context.queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(vertices));
+		context.queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(indices));
+		self.num_elements = indices.len().try_into().unwrap();
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+}
+
+// the position of the chunk a mesh is for, since the vertexes of chunk meshes are relative to their chunk
+#[derive(Debug)]
+pub struct ChunkOffset {
+	bind_group: wgpu::BindGroup,
+}
+
+// uniforms have to be a multiple of 16 bytes, so the offset is padded to 4 floats
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkOffsetUniform([f32; 4]);
+
+impl ChunkOffset {
+	pub fn new(chunk: ChunkPos, context: RenderContext) -> Self {
+		let offset = (chunk * CHUNK_SIZE as i64).cast::<f32>();
+
+		let buffer = context.device.create_buffer_init(
+			&wgpu::util::BufferInitDescriptor {
+				label: Some(&format!("chunk {} {} {} offset buffer", chunk.x, chunk.y, chunk.z)),
+				contents: bytemuck::cast_slice(&[ChunkOffsetUniform([offset.x, offset.y, offset.z, 0.0])]),
+				usage: wgpu::BufferUsages::UNIFORM,
+			}
+		);
+
+		let bind_group = context.device.create_bind_group(
+			&wgpu::BindGroupDescriptor {
+				label: Some(&format!("chunk {} {} {} bind group", chunk.x, chunk.y, chunk.z)),
+				layout: context.chunk_bind_layout,
+				entries: &[
+					wgpu::BindGroupEntry {
+						binding: 0,
+						resource: buffer.as_entire_binding(),
+					},
+				],
+			}
+		);
+
+		Self {
+			bind_group,
+		}
+	}
 }
 
 #[derive(Debug)]
 pub struct Material {
 	name: String,
-	diffuse_textures: Vec<Texture>,
-	diffuse_sampler: wgpu::Sampler,
+	// the bind group uses these, they are only kept so they live as long as the material
+	_diffuse_textures: Vec<Texture>,
+	_diffuse_sampler: wgpu::Sampler,
 	bind_group: wgpu::BindGroup,
 }
 
@@ -91,21 +160,13 @@ impl Material {
 	pub fn load_from_file<T: AsRef<Path>>(
 		file_name: T,
 		name: String,
+		filtering: TextureFiltering,
 		context: RenderContext,
 	) -> Result<Self> {
 		let diffuse_texture = Texture::from_file(file_name, &format!("{} diffuse texture", name), context)?;
 
 		let diffuse_sampler = context.device.create_sampler(
-			&wgpu::SamplerDescriptor {
-				address_mode_u: wgpu::AddressMode::ClampToEdge,
-				address_mode_v: wgpu::AddressMode::ClampToEdge,
-				address_mode_w: wgpu::AddressMode::ClampToEdge,
-				// TODO: make adjustable
-				mag_filter: wgpu::FilterMode::Nearest,
-				min_filter: wgpu::FilterMode::Nearest,
-				mipmap_filter: wgpu::FilterMode::Nearest,
-				..Default::default()
-			}
+			&filtering.sampler_descriptor(&format!("{} diffuse sampler", name))
 		);
 
 		let bind_group = context.device.create_bind_group(
@@ -127,38 +188,23 @@ impl Material {
 
 		Ok(Self {
 			name,
-			diffuse_textures: vec![diffuse_texture],
-			diffuse_sampler,
+			_diffuse_textures: vec![diffuse_texture],
+			_diffuse_sampler: diffuse_sampler,
 			bind_group,
 		})
 	}
 
+	// each image is a layer of the texture array
 	pub fn array_from_images(
 		images: &[DynamicImage],
 		name: String,
+		filtering: TextureFiltering,
 		context: RenderContext,
-	) -> Self {
-		let mut diffuse_textures = Vec::with_capacity(images.len());
-		for image in images.iter() {
-			diffuse_textures.push(Texture::from_image(image, &format!("{} diffuse texture", name), context));
-		}
-
-		let mut texture_views = Vec::with_capacity(images.len());
-		for texture in diffuse_textures.iter() {
-			texture_views.push(&texture.view);
-		}
+	) -> Result<Self> {
+		let diffuse_texture = Texture::array_from_images(images, &format!("{} diffuse texture", name), context)?;
 
 		let diffuse_sampler = context.device.create_sampler(
-			&wgpu::SamplerDescriptor {
-				address_mode_u: wgpu::AddressMode::ClampToEdge,
-				address_mode_v: wgpu::AddressMode::ClampToEdge,
-				address_mode_w: wgpu::AddressMode::ClampToEdge,
-				// TODO: make adjustable
-				mag_filter: wgpu::FilterMode::Nearest,
-				min_filter: wgpu::FilterMode::Nearest,
-				mipmap_filter: wgpu::FilterMode::Nearest,
-				..Default::default()
-			}
+			&filtering.sampler_descriptor(&format!("{} diffuse sampler", name))
 		);
 
 		let bind_group = context.device.create_bind_group(
@@ -168,7 +214,7 @@ impl Material {
 				entries: &[
 					wgpu::BindGroupEntry {
 						binding: 0,
-						resource: wgpu::BindingResource::TextureViewArray(&texture_views),
+						resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
 					},
 					wgpu::BindGroupEntry {
 						binding: 1,
@@ -178,12 +224,16 @@ impl Material {
 			}
 		);
 
-		Self {
+		Ok(Self {
 			name,
-			diffuse_textures,
-			diffuse_sampler,
+			_diffuse_textures: vec![diffuse_texture],
+			_diffuse_sampler: diffuse_sampler,
 			bind_group,
-		}
+		})
+	}
+
+	pub fn name(&self) -> &str {
+		&self.name
 	}
 }
 
@@ -204,7 +254,7 @@ impl Model {
 
 		let mut materials = Vec::with_capacity(obj_materials.len());
 		for mat in obj_materials.into_iter() {
-			materials.push(Material::load_from_file(&mat.diffuse_texture, mat.diffuse_texture.clone(), context)?);
+			materials.push(Material::load_from_file(&mat.diffuse_texture, mat.diffuse_texture.clone(), TextureFiltering::default(), context)?);
 		}
 
 		let mut meshes = Vec::with_capacity(obj_meshes.len());
@@ -354,6 +404,14 @@ pub trait DrawModel<'a> {
 		camera_bind_group: &'a wgpu::BindGroup,
 	);
 
+	fn draw_chunk_mesh(
+		&mut self,
+		mesh: &'a Mesh,
+		material: &'a Material,
+		offset: &'a ChunkOffset,
+		camera_bind_group: &'a wgpu::BindGroup,
+	);
+
 	// Don't use
 	fn draw_model_instanced(
 		&mut self,
@@ -379,6 +437,17 @@ where
 		self.draw_indexed(0..mesh.num_elements, 0, 0..1);
 	}
 
+	fn draw_chunk_mesh(
+		&mut self,
+		mesh: &'b Mesh,
+		material: &'b Material,
+		offset: &'b ChunkOffset,
+		camera_bind_group: &'b wgpu::BindGroup,
+	) {
+		self.set_bind_group(2, &offset.bind_group, &[]);
+		self.draw_mesh(mesh, material, camera_bind_group);
+	}
+
 	fn draw_model_instanced(
 		&mut self,
 		model_instance: &'b ModelInstance,
@@ -7,29 +7,95 @@ struct CameraUniform {
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+struct ChunkUniform {
+	// only xyz is used, w is padding
+	offset: vec4<f32>,
+}
+
+@group(2) @binding(0)
+var<uniform> chunk: ChunkUniform;
+
+struct SkyUniform {
+	// points towards the sun, w is padding
+	sun_direction: vec4<f32>,
+	// rgb is the color of the sky, a is what sky light is multiplied by
+	color: vec4<f32>,
+}
+
+@group(3) @binding(0)
+var<uniform> sky: SkyUniform;
+
+// the vertex is packed into 2 integers, see BlockVertex
 struct VertexInput {
-	@location(0) position: vec3<f32>,
-	@location(1) normal: vec3<f32>,
-	@location(2) color: vec3<f32>,
-	@location(3) texture_index: i32,
+	@location(0) data: vec2<u32>,
 }
 
+// number of steps each block is split into for vertex positions
+let SHAPE_UNITS: f32 = 16.0;
+// the brightest light level, see MAX_LIGHT
+let MAX_LIGHT: f32 = 15.0;
+// each light level is this much brighter than the one below it
+let LIGHT_FALLOFF: f32 = 0.8;
+
 struct VertexOutput {
 	@builtin(position) clip_position: vec4<f32>,
 	@location(0) world_pos: vec3<f32>,
 	@location(1) world_normal: vec3<f32>,
 	@location(2) color: vec3<f32>,
 	@location(3) texture_index: i32,
+	@location(4) @interpolate(flat) texture_rotation: u32,
+	@location(5) @interpolate(flat) facing: u32,
 }
 
 @vertex
 fn vs_main(model: VertexInput) -> VertexOutput {
+	// the block faces followed by the 2 diagonal planes of cross shaped blocks
+	var normals = array<vec3<f32>, 8>(
+		vec3<f32>(1.0, 0.0, 0.0),
+		vec3<f32>(-1.0, 0.0, 0.0),
+		vec3<f32>(0.0, 1.0, 0.0),
+		vec3<f32>(0.0, -1.0, 0.0),
+		vec3<f32>(0.0, 0.0, 1.0),
+		vec3<f32>(0.0, 0.0, -1.0),
+		vec3<f32>(0.70710678, 0.0, -0.70710678),
+		vec3<f32>(0.70710678, 0.0, 0.70710678)
+	);
+	// the texture color is multiplied by this for each occlusion level
+	var occlusion_colors = array<f32, 4>(1.0, 0.8, 0.6, 0.4);
+
+	let position = model.data.x;
+	let local_pos = vec3<f32>(
+		f32(position & 1023u),
+		f32((position >> 10u) & 1023u),
+		f32((position >> 20u) & 1023u)
+	) / SHAPE_UNITS;
+
+	let data = model.data.y;
+	let texture_layer = data & 65535u;
+	let facing = (data >> 16u) & 7u;
+	let occlusion_level = (data >> 19u) & 3u;
+	let texture_rotation = (data >> 21u) & 3u;
+	let sky_light = (data >> 23u) & 15u;
+	let block_light = (data >> 27u) & 15u;
+	// sky light gets dimmer at night
+	let sky_level = f32(sky_light) * sky.color.a;
+	let brightness = pow(LIGHT_FALLOFF, MAX_LIGHT - max(sky_level, f32(block_light)));
+	// faces turned towards the sun, or the moon at night, are lit a little more where the sky can be seen
+	var sun = sky.sun_direction.xyz;
+	if (sun.y < 0.0) {
+		sun = -sun;
+	}
+	let sun_shading = mix(1.0, 0.8 + 0.2 * max(dot(normals[facing], sun), 0.0), f32(sky_light) / MAX_LIGHT);
+	let world_pos = chunk.offset.xyz + local_pos;
+
 	var vertex_out: VertexOutput;
-	vertex_out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
-	vertex_out.world_pos = model.position;
-	vertex_out.world_normal = model.normal;
-	vertex_out.color = model.color;
-	vertex_out.texture_index = model.texture_index;
+	vertex_out.clip_position = camera.view_proj * vec4<f32>(world_pos, 1.0);
+	vertex_out.world_pos = world_pos;
+	vertex_out.world_normal = normals[facing];
+	vertex_out.color = vec3<f32>(occlusion_colors[occlusion_level] * brightness * sun_shading);
+	vertex_out.texture_index = i32(texture_layer);
+	vertex_out.texture_rotation = texture_rotation;
+	vertex_out.facing = facing;
 	return vertex_out;
 }
 
@@ -37,54 +103,70 @@ fn vs_main(model: VertexInput) -> VertexOutput {
 // Fragment shader
 
 @group(0) @binding(0)
-var block_diffuse_textures: binding_array<texture_2d<f32>>;
+var block_diffuse_textures: texture_2d_array<f32>;
 @group(0) @binding(1)
 var block_diffuse_sampler: sampler;
 
-fn wrap_pos(n: f32) -> f32 {
-	if (n >= 0.0) {
-		return n % 1.0;
+// the color of the block at the fragment, including the alpha of its texture
+fn block_color(fragment_in: VertexOutput) -> vec4<f32> {
+	let pos = fragment_in.world_pos;
+	let facing = fragment_in.facing;
+
+	// texture coordinates are chosen so the texture is upright on side faces when looking at them,
+	// and the top of the texture faces negative z on the top and bottom faces
+	// the diagonal planes of cross shaped blocks are treated like side faces
+	var tex_coords: vec2<f32>;
+	if (facing == 0u) {
+		tex_coords = vec2<f32>(-pos.z, -pos.y);
+	} else if (facing == 1u) {
+		tex_coords = vec2<f32>(pos.z, -pos.y);
+	} else if (facing == 2u) {
+		tex_coords = vec2<f32>(pos.x, pos.z);
+	} else if (facing == 3u) {
+		tex_coords = vec2<f32>(-pos.x, pos.z);
+	} else if (facing == 4u || facing == 7u) {
+		tex_coords = vec2<f32>(pos.x, -pos.y);
 	} else {
-		return 1.0 - (-n % 1.0);
+		tex_coords = vec2<f32>(-pos.x, -pos.y);
 	}
+
+	// blocks with a rotated state turn their textures by a number of quarter turns
+	let rotation = fragment_in.texture_rotation;
+	if (rotation == 1u) {
+		tex_coords = vec2<f32>(-tex_coords.y, tex_coords.x);
+	} else if (rotation == 2u) {
+		tex_coords = -tex_coords;
+	} else if (rotation == 3u) {
+		tex_coords = vec2<f32>(tex_coords.y, -tex_coords.x);
+	}
+
+	// faces can cover multiple blocks, so the texture is repeated on each block
+	// the gradients are taken before wrapping, so the mip level doesn't jump at the edge of each block
+	let color = textureSampleGrad(
+		block_diffuse_textures,
+		block_diffuse_sampler,
+		fract(tex_coords),
+		fragment_in.texture_index,
+		dpdx(tex_coords),
+		dpdy(tex_coords)
+	);
+
+	return vec4<f32>(fragment_in.color, 1.0) * color;
 }
 
+// opaque blocks are either fully drawn or not drawn at each pixel, so parts of their textures can be cut out
 @fragment
 fn fs_main(fragment_in: VertexOutput) -> @location(0) vec4<f32> {
-	var offset: vec2<f32>;
-	var sample_pos: vec2<f32>;
-
-	if (fragment_in.world_normal.x > 0.0) {
-		offset.x = 0.5;
-		offset.y = 0.66666;
-		sample_pos.x = 0.25 * wrap_pos(fragment_in.world_pos.z);
-		sample_pos.y = 0.33333 * wrap_pos(fragment_in.world_pos.y);
-	} else if (fragment_in.world_normal.x < 0.0) {
-		offset.x = 0.5;
-		offset.y = 0.33333;
-		sample_pos.x = 0.25 * wrap_pos(fragment_in.world_pos.z);
-		sample_pos.y = -0.33333 * wrap_pos(fragment_in.world_pos.y);
-	} else if (fragment_in.world_normal.y > 0.0) {
-		offset.x = 0.25;
-		offset.y = 0.33333;
-		sample_pos.x = -0.25 * wrap_pos(fragment_in.world_pos.z);
-		sample_pos.y = 0.33333 * wrap_pos(fragment_in.world_pos.x);
-	} else if (fragment_in.world_normal.y < 0.0) {
-		offset.x = 0.5;
-		offset.y = 0.33333;
-		sample_pos.x = 0.25 * wrap_pos(fragment_in.world_pos.z);
-		sample_pos.y = 0.33333 * wrap_pos(fragment_in.world_pos.x);
-	} else if (fragment_in.world_normal.z > 0.0) {
-		offset.x = 0.75;
-		offset.y = 0.33333;
-		sample_pos.x = 0.25 * wrap_pos(fragment_in.world_pos.y);
-		sample_pos.y = 0.33333 * wrap_pos(fragment_in.world_pos.x);
-	} else {
-		offset.x = 0.5;
-		offset.y = 0.33333;
-		sample_pos.x = -0.25 * wrap_pos(fragment_in.world_pos.y);
-		sample_pos.y = 0.33333 * wrap_pos(fragment_in.world_pos.x);
+	let color = block_color(fragment_in);
+	if (color.a < 0.5) {
+		discard;
 	}
 
-	return vec4<f32>(fragment_in.color, 1.0) * textureSample(block_diffuse_textures[fragment_in.texture_index], block_diffuse_sampler, offset + sample_pos);
+	return vec4<f32>(color.rgb, 1.0);
+}
+
+// translucent blocks are blended with what is behind them
+@fragment
+fn fs_translucent(fragment_in: VertexOutput) -> @location(0) vec4<f32> {
+	return block_color(fragment_in);
 }
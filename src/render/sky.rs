@@ -0,0 +1,21 @@
+use crate::game::clock::Sky;
+
+// the sky at the current time, which the shader uses to light blocks
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkyUniform {
+	// xyz points towards the sun, w is padding
+	sun_direction: [f32; 4],
+	// rgb is the color of the sky, a is what sky light is multiplied by
+	color: [f32; 4],
+}
+
+impl From<&Sky> for SkyUniform {
+	fn from(sky: &Sky) -> Self {
+		let [r, g, b] = sky.color;
+		Self {
+			sun_direction: [sky.sun_direction.x as f32, sky.sun_direction.y as f32, sky.sun_direction.z as f32, 0.0],
+			color: [r, g, b, sky.brightness],
+		}
+	}
+}
@@ -1,6 +1,6 @@
 use std::{num::NonZeroU32, path::Path};
 
-use image::{DynamicImage, GenericImageView};
+use image::{DynamicImage, GenericImageView, RgbaImage, Rgba};
 use anyhow::*;
 
 use crate::assets::loader;
@@ -36,22 +36,47 @@ impl Texture {
 		label: &str,
 		context: RenderContext,
 	) -> Self {
-		let dimensions = image.dimensions();
-		let rgba = image.to_rgba8();
+		Self::from_layers(&[image.to_rgba8()], label, context)
+	}
 
-		let texture_size = wgpu::Extent3d {
-			width: dimensions.0,
-			height: dimensions.1,
-			depth_or_array_layers: 1,
-		};
+	// creates a texture array with a layer for each image, all the images must be the same size
+	pub fn array_from_images(
+		images: &[DynamicImage],
+		label: &str,
+		context: RenderContext,
+	) -> Result<Self> {
+		let dimensions = images.first()
+			.ok_or_else(|| anyhow!("texture array {} has no images", label))?
+			.dimensions();
+
+		let layers = images.iter()
+			.map(|image| {
+				ensure!(image.dimensions() == dimensions, "texture array {} has images of different sizes", label);
+				Ok(image.to_rgba8())
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		Ok(Self::from_layers(&layers, label, context))
+	}
+
+	// the view is always a 2d array view, so textures with 1 layer can be bound in the same place as texture arrays
+	fn from_layers(
+		layers: &[RgbaImage],
+		label: &str,
+		context: RenderContext,
+	) -> Self {
+		let dimensions = layers[0].dimensions();
+		let mip_level_count = mip_level_count(dimensions.0, dimensions.1);
 
 		let texture = context.device.create_texture(
 			&wgpu::TextureDescriptor {
 				label: Some(label),
-				// All textures are stored as 3D, we represent our 2D texture
-				// by setting depth to 1.
-				size: texture_size,
-				mip_level_count: 1,
+				size: wgpu::Extent3d {
+					width: dimensions.0,
+					height: dimensions.1,
+					depth_or_array_layers: layers.len() as u32,
+				},
+				mip_level_count,
 				sample_count: 1,
 				dimension: wgpu::TextureDimension::D2,
 				// Most images are stored using sRGB so we need to reflect that here.
@@ -62,26 +87,43 @@ impl Texture {
 			}
 		);
 
-		context.queue.write_texture(
-			// where to copy the pixel data to
-			wgpu::ImageCopyTexture {
-				texture: &texture,
-				mip_level: 0,
-				origin: wgpu::Origin3d::ZERO,
-				aspect: wgpu::TextureAspect::All,
-			},
-			// the actual pixel data
-			&rgba,
-			// the layout of the texture
-			wgpu::ImageDataLayout {
-				offset: 0,
-				bytes_per_row: NonZeroU32::new(4 * dimensions.0),
-				rows_per_image: NonZeroU32::new(dimensions.1),
-			},
-			texture_size,
-		);
+		for (layer_index, layer) in layers.iter().enumerate() {
+			for (mip_level, mip) in generate_mips(layer, mip_level_count).iter().enumerate() {
+				let size = wgpu::Extent3d {
+					width: mip.width(),
+					height: mip.height(),
+					depth_or_array_layers: 1,
+				};
 
-		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+				context.queue.write_texture(
+					// where to copy the pixel data to
+					wgpu::ImageCopyTexture {
+						texture: &texture,
+						mip_level: mip_level as u32,
+						origin: wgpu::Origin3d {
+							x: 0,
+							y: 0,
+							z: layer_index as u32,
+						},
+						aspect: wgpu::TextureAspect::All,
+					},
+					// the actual pixel data
+					mip,
+					// the layout of the texture
+					wgpu::ImageDataLayout {
+						offset: 0,
+						bytes_per_row: NonZeroU32::new(4 * mip.width()),
+						rows_per_image: NonZeroU32::new(mip.height()),
+					},
+					size,
+				);
+			}
+		}
+
+		let view = texture.create_view(&wgpu::TextureViewDescriptor {
+			dimension: Some(wgpu::TextureViewDimension::D2Array),
+			..Default::default()
+		});
 
 		Self {
 			texture,
@@ -90,6 +132,80 @@ impl Texture {
 	}
 }
 
+// how textures are sampled when they are drawn larger or smaller than their size
+#[derive(Debug, Clone, Copy)]
+pub struct TextureFiltering {
+	pub mag_filter: wgpu::FilterMode,
+	pub min_filter: wgpu::FilterMode,
+	pub mipmap_filter: wgpu::FilterMode,
+}
+
+impl TextureFiltering {
+	pub fn sampler_descriptor<'a>(&self, label: &'a str) -> wgpu::SamplerDescriptor<'a> {
+		wgpu::SamplerDescriptor {
+			label: Some(label),
+			address_mode_u: wgpu::AddressMode::ClampToEdge,
+			address_mode_v: wgpu::AddressMode::ClampToEdge,
+			address_mode_w: wgpu::AddressMode::ClampToEdge,
+			mag_filter: self.mag_filter,
+			min_filter: self.min_filter,
+			mipmap_filter: self.mipmap_filter,
+			..Default::default()
+		}
+	}
+}
+
+impl Default for TextureFiltering {
+	// pixels stay sharp up close, and blend between mip levels far away
+	fn default() -> Self {
+		Self {
+			mag_filter: wgpu::FilterMode::Nearest,
+			min_filter: wgpu::FilterMode::Nearest,
+			mipmap_filter: wgpu::FilterMode::Linear,
+		}
+	}
+}
+
+// number of mip levels needed to go down to 1 pixel on the longest side
+fn mip_level_count(width: u32, height: u32) -> u32 {
+	u32::BITS - width.max(height).leading_zeros()
+}
+
+// returns the image followed by each smaller mip level, each half the size of the last
+//
+// each pixel is the average of the 2x2 pixels above it,
+// images with an odd size have the last row or column left out
+fn generate_mips(image: &RgbaImage, mip_level_count: u32) -> Vec<RgbaImage> {
+	let mut out = vec![image.clone()];
+
+	for _ in 1..mip_level_count {
+		let last = out.last().unwrap();
+		let width = (last.width() / 2).max(1);
+		let height = (last.height() / 2).max(1);
+
+		let mip = RgbaImage::from_fn(width, height, |x, y| {
+			let mut total = [0u32; 4];
+			let mut count = 0;
+
+			for (sample_x, sample_y) in [(2 * x, 2 * y), (2 * x + 1, 2 * y), (2 * x, 2 * y + 1), (2 * x + 1, 2 * y + 1)] {
+				if sample_x < last.width() && sample_y < last.height() {
+					let pixel = last.get_pixel(sample_x, sample_y);
+					for channel in 0..4 {
+						total[channel] += pixel[channel] as u32;
+					}
+					count += 1;
+				}
+			}
+
+			Rgba(total.map(|channel| ((channel + count / 2) / count) as u8))
+		});
+
+		out.push(mip);
+	}
+
+	out
+}
+
 #[derive(Debug)]
 pub struct DepthTexture {
 	pub texture: wgpu::Texture,
@@ -146,3 +262,25 @@ impl DepthTexture {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn mip_levels() {
+		assert_eq!(mip_level_count(32, 32), 6);
+		assert_eq!(mip_level_count(1, 1), 1);
+		assert_eq!(mip_level_count(64, 16), 7);
+
+		let image = RgbaImage::from_fn(4, 2, |x, _| if x < 2 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 0]) });
+		let mips = generate_mips(&image, mip_level_count(4, 2));
+
+		assert_eq!(mips.len(), 3);
+		assert_eq!(mips[1].dimensions(), (2, 1));
+		assert_eq!(*mips[1].get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+		assert_eq!(*mips[1].get_pixel(1, 0), Rgba([0, 0, 255, 0]));
+		assert_eq!(mips[2].dimensions(), (1, 1));
+		assert_eq!(*mips[2].get_pixel(0, 0), Rgba([128, 0, 128, 128]));
+	}
+}
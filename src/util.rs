@@ -27,3 +27,41 @@ pub const fn vec3_gety<T: Copy>(vector: Vector3<T>) -> T {
 pub const fn vec3_getz<T: Copy>(vector: Vector3<T>) -> T {
 	vector.data.0[0][2]
 }
+
+// a folder in the temp dir for a test to save a world in, which is deleted once this is dropped, even if the test panics
+#[cfg(test)]
+pub struct TestFolder(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestFolder {
+	// the process id is added to the name, so tests running at the same time in different processes don't share a folder,
+	// and anything left in the folder by an earlier run is deleted
+	pub fn new(name: &str) -> Self {
+		let path = std::env::temp_dir().join(format!("mineclone-{}-{}", name, std::process::id()));
+		let _ = std::fs::remove_dir_all(&path);
+		Self(path)
+	}
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestFolder {
+	type Target = std::path::Path;
+
+	fn deref(&self) -> &std::path::Path {
+		&self.0
+	}
+}
+
+#[cfg(test)]
+impl AsRef<std::path::Path> for TestFolder {
+	fn as_ref(&self) -> &std::path::Path {
+		&self.0
+	}
+}
+
+#[cfg(test)]
+impl Drop for TestFolder {
+	fn drop(&mut self) {
+		let _ = std::fs::remove_dir_all(&self.0);
+	}
+}